@@ -0,0 +1,4 @@
+// The migrations are embedded by `sqlx::migrate!`, so changing them must trigger a rebuild.
+fn main() {
+    println!("cargo:rerun-if-changed=migrations");
+}
@@ -0,0 +1,2 @@
+DROP SCHEMA IF EXISTS api CASCADE;
+DROP SCHEMA IF EXISTS main CASCADE;
@@ -0,0 +1,63 @@
+CREATE SCHEMA IF NOT EXISTS main;
+CREATE SCHEMA IF NOT EXISTS api;
+
+CREATE TABLE main.currencies (
+  code CHAR(3) PRIMARY KEY,
+  name VARCHAR(255) NOT NULL,
+  decimals INTEGER NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TYPE api.currency_type AS (
+  code CHAR(3),
+  name VARCHAR(255),
+  decimals INTEGER
+);
+
+CREATE OR REPLACE FUNCTION api.list_currencies()
+RETURNS SETOF api.currency_type
+AS $$
+  SELECT code, name, decimals FROM main.currencies ORDER BY code;
+$$ LANGUAGE SQL STABLE;
+
+CREATE OR REPLACE FUNCTION api.add_currency(
+  _code CHAR(3),
+  _name VARCHAR(255),
+  _decimals INTEGER
+) RETURNS api.currency_type
+AS $$
+  INSERT INTO main.currencies (code, name, decimals)
+  VALUES (_code, _name, _decimals)
+  RETURNING code, name, decimals;
+$$ LANGUAGE SQL VOLATILE;
+
+CREATE OR REPLACE FUNCTION api.find_currency_by_code(
+  _code CHAR(3)
+) RETURNS SETOF api.currency_type
+AS $$
+  SELECT code, name, decimals FROM main.currencies WHERE code = _code;
+$$ LANGUAGE SQL STABLE;
+
+CREATE OR REPLACE FUNCTION api.update_currency(
+  _code CHAR(3),
+  _name VARCHAR(255),
+  _decimals INTEGER
+) RETURNS SETOF api.currency_type
+AS $$
+  UPDATE main.currencies
+  SET name = COALESCE(_name, name),
+      decimals = COALESCE(_decimals, decimals),
+      updated_at = NOW()
+  WHERE code = _code
+  RETURNING code, name, decimals;
+$$ LANGUAGE SQL VOLATILE;
+
+CREATE OR REPLACE FUNCTION api.delete_currency(
+  _code CHAR(3)
+) RETURNS SETOF api.currency_type
+AS $$
+  DELETE FROM main.currencies
+  WHERE code = _code
+  RETURNING code, name, decimals;
+$$ LANGUAGE SQL VOLATILE;
@@ -0,0 +1,2 @@
+DROP TYPE IF EXISTS api.security_type CASCADE;
+DROP TABLE IF EXISTS main.securities;
@@ -0,0 +1,55 @@
+CREATE TABLE main.securities (
+  id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+  ticker VARCHAR(32) NOT NULL UNIQUE,
+  isin CHAR(12) NOT NULL UNIQUE,
+  name VARCHAR(255) NOT NULL,
+  exchange CHAR(4) NOT NULL,
+  currency CHAR(3) NOT NULL REFERENCES main.currencies(code) ON UPDATE CASCADE ON DELETE RESTRICT,
+  asset_class VARCHAR(32) NOT NULL CHECK (asset_class IN ('equity', 'etf', 'fund', 'bond', 'commodity', 'cryptocurrency', 'other')),
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TYPE api.security_type AS (
+  id UUID,
+  ticker VARCHAR(32),
+  isin CHAR(12),
+  name VARCHAR(255),
+  exchange CHAR(4),
+  currency CHAR(3),
+  asset_class VARCHAR(32)
+);
+
+CREATE OR REPLACE FUNCTION api.list_securities()
+RETURNS SETOF api.security_type
+AS $$
+  SELECT id, ticker, isin, name, exchange, currency, asset_class FROM main.securities ORDER BY ticker;
+$$ LANGUAGE SQL STABLE;
+
+CREATE OR REPLACE FUNCTION api.add_security(
+  _ticker VARCHAR(32),
+  _isin CHAR(12),
+  _name VARCHAR(255),
+  _exchange CHAR(4),
+  _currency CHAR(3),
+  _asset_class VARCHAR(32)
+) RETURNS api.security_type
+AS $$
+  INSERT INTO main.securities (ticker, isin, name, exchange, currency, asset_class)
+  VALUES (_ticker, _isin, _name, _exchange, _currency, _asset_class)
+  RETURNING id, ticker, isin, name, exchange, currency, asset_class;
+$$ LANGUAGE SQL VOLATILE;
+
+CREATE OR REPLACE FUNCTION api.find_security_by_ticker(
+  _ticker VARCHAR(32)
+) RETURNS SETOF api.security_type
+AS $$
+  SELECT id, ticker, isin, name, exchange, currency, asset_class FROM main.securities WHERE ticker = _ticker;
+$$ LANGUAGE SQL STABLE;
+
+CREATE OR REPLACE FUNCTION api.find_security_by_isin(
+  _isin CHAR(12)
+) RETURNS SETOF api.security_type
+AS $$
+  SELECT id, ticker, isin, name, exchange, currency, asset_class FROM main.securities WHERE isin = _isin;
+$$ LANGUAGE SQL STABLE;
@@ -0,0 +1,2 @@
+DROP TYPE IF EXISTS api.exchange_type CASCADE;
+DROP TABLE IF EXISTS main.exchanges;
@@ -0,0 +1,80 @@
+CREATE TABLE main.exchanges (
+  mic CHAR(4) PRIMARY KEY,
+  name VARCHAR(255) NOT NULL,
+  country CHAR(2) NOT NULL,
+  timezone VARCHAR(64) NOT NULL,
+  open_time TIME NOT NULL,
+  close_time TIME NOT NULL,
+  currency CHAR(3) NOT NULL REFERENCES main.currencies(code) ON UPDATE CASCADE ON DELETE RESTRICT,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TYPE api.exchange_type AS (
+  mic CHAR(4),
+  name VARCHAR(255),
+  country CHAR(2),
+  timezone VARCHAR(64),
+  open_time TIME,
+  close_time TIME,
+  currency CHAR(3)
+);
+
+CREATE OR REPLACE FUNCTION api.list_exchanges()
+RETURNS SETOF api.exchange_type
+AS $$
+  SELECT mic, name, country, timezone, open_time, close_time, currency FROM main.exchanges ORDER BY mic;
+$$ LANGUAGE SQL STABLE;
+
+CREATE OR REPLACE FUNCTION api.add_exchange(
+  _mic CHAR(4),
+  _name VARCHAR(255),
+  _country CHAR(2),
+  _timezone VARCHAR(64),
+  _open_time TIME,
+  _close_time TIME,
+  _currency CHAR(3)
+) RETURNS api.exchange_type
+AS $$
+  INSERT INTO main.exchanges (mic, name, country, timezone, open_time, close_time, currency)
+  VALUES (_mic, _name, _country, _timezone, _open_time, _close_time, _currency)
+  RETURNING mic, name, country, timezone, open_time, close_time, currency;
+$$ LANGUAGE SQL VOLATILE;
+
+CREATE OR REPLACE FUNCTION api.find_exchange_by_mic(
+  _mic CHAR(4)
+) RETURNS SETOF api.exchange_type
+AS $$
+  SELECT mic, name, country, timezone, open_time, close_time, currency FROM main.exchanges WHERE mic = _mic;
+$$ LANGUAGE SQL STABLE;
+
+CREATE OR REPLACE FUNCTION api.update_exchange(
+  _mic CHAR(4),
+  _name VARCHAR(255),
+  _country CHAR(2),
+  _timezone VARCHAR(64),
+  _open_time TIME,
+  _close_time TIME,
+  _currency CHAR(3)
+) RETURNS SETOF api.exchange_type
+AS $$
+  UPDATE main.exchanges
+  SET name = COALESCE(_name, name),
+      country = COALESCE(_country, country),
+      timezone = COALESCE(_timezone, timezone),
+      open_time = COALESCE(_open_time, open_time),
+      close_time = COALESCE(_close_time, close_time),
+      currency = COALESCE(_currency, currency),
+      updated_at = NOW()
+  WHERE mic = _mic
+  RETURNING mic, name, country, timezone, open_time, close_time, currency;
+$$ LANGUAGE SQL VOLATILE;
+
+CREATE OR REPLACE FUNCTION api.delete_exchange(
+  _mic CHAR(4)
+) RETURNS SETOF api.exchange_type
+AS $$
+  DELETE FROM main.exchanges
+  WHERE mic = _mic
+  RETURNING mic, name, country, timezone, open_time, close_time, currency;
+$$ LANGUAGE SQL VOLATILE;
@@ -0,0 +1,3 @@
+DROP TYPE IF EXISTS api.price_bar_type CASCADE;
+DROP FUNCTION IF EXISTS api.add_price_bars;
+DROP TABLE IF EXISTS main.price_bars;
@@ -0,0 +1,95 @@
+CREATE TABLE main.price_bars (
+  security UUID NOT NULL REFERENCES main.securities(id) ON DELETE CASCADE,
+  date DATE NOT NULL,
+  open DOUBLE PRECISION NOT NULL,
+  high DOUBLE PRECISION NOT NULL,
+  low DOUBLE PRECISION NOT NULL,
+  close DOUBLE PRECISION NOT NULL,
+  volume BIGINT NOT NULL CHECK (volume >= 0),
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  PRIMARY KEY (security, date),
+  CHECK (low <= high)
+);
+
+CREATE TYPE api.price_bar_type AS (
+  ticker VARCHAR(32),
+  date DATE,
+  open DOUBLE PRECISION,
+  high DOUBLE PRECISION,
+  low DOUBLE PRECISION,
+  close DOUBLE PRECISION,
+  volume BIGINT
+);
+
+CREATE OR REPLACE FUNCTION api.add_price_bars(
+  _tickers VARCHAR(32)[],
+  _dates DATE[],
+  _opens DOUBLE PRECISION[],
+  _highs DOUBLE PRECISION[],
+  _lows DOUBLE PRECISION[],
+  _closes DOUBLE PRECISION[],
+  _volumes BIGINT[]
+) RETURNS BIGINT
+AS $$
+DECLARE
+  _missing VARCHAR(32);
+  _count BIGINT;
+BEGIN
+  SELECT t.ticker INTO _missing
+  FROM UNNEST(_tickers) AS t(ticker)
+  LEFT JOIN main.securities s ON s.ticker = t.ticker
+  WHERE s.id IS NULL
+  LIMIT 1;
+
+  IF FOUND THEN
+    RAISE foreign_key_violation
+      USING MESSAGE = format('Unknown security %s', _missing),
+            DETAIL = format('Key (ticker)=(%s) is not present in table "securities".', _missing);
+  END IF;
+
+  -- When a batch holds several bars for the same key, the last one wins.
+  INSERT INTO main.price_bars (security, date, open, high, low, close, volume)
+  SELECT DISTINCT ON (s.id, b.date) s.id, b.date, b.open, b.high, b.low, b.close, b.volume
+  FROM UNNEST(_tickers, _dates, _opens, _highs, _lows, _closes, _volumes)
+    WITH ORDINALITY AS b(ticker, date, open, high, low, close, volume, ord)
+  JOIN main.securities s ON s.ticker = b.ticker
+  ORDER BY s.id, b.date, b.ord DESC
+  ON CONFLICT (security, date) DO UPDATE
+  SET open = EXCLUDED.open,
+      high = EXCLUDED.high,
+      low = EXCLUDED.low,
+      close = EXCLUDED.close,
+      volume = EXCLUDED.volume,
+      updated_at = NOW();
+
+  GET DIAGNOSTICS _count = ROW_COUNT;
+  RETURN _count;
+END;
+$$ LANGUAGE plpgsql VOLATILE;
+
+CREATE OR REPLACE FUNCTION api.find_price_bars(
+  _ticker VARCHAR(32),
+  _from DATE,
+  _to DATE
+) RETURNS SETOF api.price_bar_type
+AS $$
+  SELECT s.ticker, p.date, p.open, p.high, p.low, p.close, p.volume
+  FROM main.price_bars p
+  JOIN main.securities s ON s.id = p.security
+  WHERE s.ticker = _ticker AND p.date BETWEEN _from AND _to
+  ORDER BY p.date;
+$$ LANGUAGE SQL STABLE;
+
+CREATE OR REPLACE FUNCTION api.find_latest_price_bar(
+  _ticker VARCHAR(32),
+  _date DATE
+) RETURNS SETOF api.price_bar_type
+AS $$
+  SELECT s.ticker, p.date, p.open, p.high, p.low, p.close, p.volume
+  FROM main.price_bars p
+  JOIN main.securities s ON s.id = p.security
+  WHERE s.ticker = _ticker AND p.date <= _date
+  ORDER BY p.date DESC
+  LIMIT 1;
+$$ LANGUAGE SQL STABLE;
@@ -0,0 +1,2 @@
+DROP TYPE IF EXISTS api.fx_rate_type CASCADE;
+DROP TABLE IF EXISTS main.fx_rates;
@@ -0,0 +1,44 @@
+CREATE TABLE main.fx_rates (
+  base CHAR(3) NOT NULL REFERENCES main.currencies(code) ON UPDATE CASCADE ON DELETE RESTRICT,
+  quote CHAR(3) NOT NULL REFERENCES main.currencies(code) ON UPDATE CASCADE ON DELETE RESTRICT,
+  date DATE NOT NULL,
+  rate DOUBLE PRECISION NOT NULL CHECK (rate > 0),
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  PRIMARY KEY (base, quote, date),
+  CHECK (base <> quote)
+);
+
+CREATE TYPE api.fx_rate_type AS (
+  base CHAR(3),
+  quote CHAR(3),
+  date DATE,
+  rate DOUBLE PRECISION
+);
+
+CREATE OR REPLACE FUNCTION api.add_fx_rate(
+  _base CHAR(3),
+  _quote CHAR(3),
+  _date DATE,
+  _rate DOUBLE PRECISION
+) RETURNS api.fx_rate_type
+AS $$
+  INSERT INTO main.fx_rates (base, quote, date, rate)
+  VALUES (_base, _quote, _date, _rate)
+  ON CONFLICT (base, quote, date) DO UPDATE
+  SET rate = EXCLUDED.rate, updated_at = NOW()
+  RETURNING base, quote, date, rate;
+$$ LANGUAGE SQL VOLATILE;
+
+CREATE OR REPLACE FUNCTION api.find_fx_rate(
+  _base CHAR(3),
+  _quote CHAR(3),
+  _date DATE
+) RETURNS SETOF api.fx_rate_type
+AS $$
+  SELECT base, quote, date, rate
+  FROM main.fx_rates
+  WHERE base = _base AND quote = _quote AND date <= _date
+  ORDER BY date DESC
+  LIMIT 1;
+$$ LANGUAGE SQL STABLE;
@@ -0,0 +1,4 @@
+DROP TYPE IF EXISTS api.transaction_type CASCADE;
+DROP TYPE IF EXISTS api.portfolio_type CASCADE;
+DROP TABLE IF EXISTS main.transactions;
+DROP TABLE IF EXISTS main.portfolios;
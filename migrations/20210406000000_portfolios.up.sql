@@ -0,0 +1,119 @@
+CREATE TABLE main.portfolios (
+  id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+  name VARCHAR(255) NOT NULL,
+  currency CHAR(3) NOT NULL REFERENCES main.currencies(code) ON UPDATE CASCADE ON DELETE RESTRICT,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE main.transactions (
+  id UUID PRIMARY KEY,
+  portfolio UUID NOT NULL REFERENCES main.portfolios(id) ON DELETE CASCADE,
+  kind VARCHAR(16) NOT NULL CHECK (kind IN ('buy', 'sell', 'deposit', 'withdrawal', 'fee', 'dividend')),
+  security UUID REFERENCES main.securities(id) ON DELETE RESTRICT,
+  executed_at TIMESTAMPTZ NOT NULL,
+  quantity DOUBLE PRECISION NOT NULL CHECK (quantity >= 0),
+  amount DOUBLE PRECISION NOT NULL CHECK (amount >= 0),
+  fees DOUBLE PRECISION NOT NULL DEFAULT 0 CHECK (fees >= 0),
+  seq BIGSERIAL NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  CHECK (kind NOT IN ('buy', 'sell', 'dividend') OR security IS NOT NULL),
+  CHECK (kind NOT IN ('buy', 'sell') OR quantity > 0),
+  CHECK (kind IN ('buy', 'sell') OR quantity = 0)
+);
+
+CREATE INDEX transactions_portfolio_idx ON main.transactions (portfolio, executed_at, seq);
+
+CREATE TYPE api.portfolio_type AS (
+  id UUID,
+  name VARCHAR(255),
+  currency CHAR(3)
+);
+
+CREATE TYPE api.transaction_type AS (
+  id UUID,
+  portfolio UUID,
+  kind VARCHAR(16),
+  ticker VARCHAR(32),
+  executed_at TIMESTAMPTZ,
+  quantity DOUBLE PRECISION,
+  amount DOUBLE PRECISION,
+  fees DOUBLE PRECISION
+);
+
+CREATE OR REPLACE FUNCTION api.list_portfolios()
+RETURNS SETOF api.portfolio_type
+AS $$
+  SELECT id, name, currency FROM main.portfolios ORDER BY name;
+$$ LANGUAGE SQL STABLE;
+
+CREATE OR REPLACE FUNCTION api.add_portfolio(
+  _name VARCHAR(255),
+  _currency CHAR(3)
+) RETURNS api.portfolio_type
+AS $$
+  INSERT INTO main.portfolios (name, currency)
+  VALUES (_name, _currency)
+  RETURNING id, name, currency;
+$$ LANGUAGE SQL VOLATILE;
+
+CREATE OR REPLACE FUNCTION api.find_portfolio_by_id(
+  _id UUID
+) RETURNS SETOF api.portfolio_type
+AS $$
+  SELECT id, name, currency FROM main.portfolios WHERE id = _id;
+$$ LANGUAGE SQL STABLE;
+
+CREATE OR REPLACE FUNCTION api.add_transaction(
+  _id UUID,
+  _portfolio UUID,
+  _kind VARCHAR(16),
+  _ticker VARCHAR(32),
+  _executed_at TIMESTAMPTZ,
+  _quantity DOUBLE PRECISION,
+  _amount DOUBLE PRECISION,
+  _fees DOUBLE PRECISION
+) RETURNS api.transaction_type
+AS $$
+DECLARE
+  _security UUID;
+BEGIN
+  IF _ticker IS NOT NULL THEN
+    SELECT id INTO _security FROM main.securities WHERE ticker = _ticker;
+    IF NOT FOUND THEN
+      RAISE foreign_key_violation
+        USING MESSAGE = format('Unknown security %s', _ticker),
+              DETAIL = format('Key (ticker)=(%s) is not present in table "securities".', _ticker);
+    END IF;
+  END IF;
+
+  INSERT INTO main.transactions (id, portfolio, kind, security, executed_at, quantity, amount, fees)
+  VALUES (_id, _portfolio, _kind, _security, _executed_at, _quantity, _amount, _fees);
+
+  RETURN (_id, _portfolio, _kind, _ticker, _executed_at, _quantity, _amount, _fees)::api.transaction_type;
+END;
+$$ LANGUAGE plpgsql VOLATILE;
+
+CREATE OR REPLACE FUNCTION api.list_transactions(
+  _portfolio UUID
+) RETURNS SETOF api.transaction_type
+AS $$
+  SELECT t.id, t.portfolio, t.kind, s.ticker, t.executed_at, t.quantity, t.amount, t.fees
+  FROM main.transactions t
+  LEFT JOIN main.securities s ON s.id = t.security
+  WHERE t.portfolio = _portfolio
+  ORDER BY t.executed_at, t.seq;
+$$ LANGUAGE SQL STABLE;
+
+CREATE OR REPLACE FUNCTION api.delete_transaction(
+  _id UUID
+) RETURNS SETOF api.transaction_type
+AS $$
+  WITH deleted AS (
+    DELETE FROM main.transactions WHERE id = _id
+    RETURNING id, portfolio, kind, security, executed_at, quantity, amount, fees
+  )
+  SELECT d.id, d.portfolio, d.kind, s.ticker, d.executed_at, d.quantity, d.amount, d.fees
+  FROM deleted d
+  LEFT JOIN main.securities s ON s.id = d.security;
+$$ LANGUAGE SQL VOLATILE;
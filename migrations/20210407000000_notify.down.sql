@@ -0,0 +1,7 @@
+DROP TRIGGER IF EXISTS transactions_notify ON main.transactions;
+DROP TRIGGER IF EXISTS price_bars_notify ON main.price_bars;
+DROP TRIGGER IF EXISTS currencies_notify ON main.currencies;
+DROP FUNCTION IF EXISTS main.notify_transaction_recorded;
+DROP FUNCTION IF EXISTS main.notify_price_bar_change;
+DROP FUNCTION IF EXISTS main.notify_currency_change;
+DROP FUNCTION IF EXISTS main.notify_change;
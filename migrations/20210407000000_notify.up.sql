@@ -0,0 +1,97 @@
+-- Changes are announced with pg_notify, so that services are told of changes made by
+-- any client of the api functions. The channel defaults to 'stocks_changes', and can be
+-- changed with: ALTER DATABASE stocks SET stocks.notify_channel = '...';
+--
+-- The payload is a JSON object:
+--   { "origin": <application_name of the writer>, "event": <name>, "data": <row> }
+
+CREATE OR REPLACE FUNCTION main.notify_change(_event TEXT, _data JSON)
+RETURNS VOID
+AS $$
+  SELECT pg_notify(
+    COALESCE(NULLIF(current_setting('stocks.notify_channel', true), ''), 'stocks_changes'),
+    json_build_object(
+      'origin', current_setting('application_name'),
+      'event', _event,
+      'data', _data
+    )::TEXT
+  );
+$$ LANGUAGE SQL VOLATILE;
+
+CREATE OR REPLACE FUNCTION main.notify_currency_change()
+RETURNS TRIGGER
+AS $$
+DECLARE
+  _row main.currencies;
+BEGIN
+  IF TG_OP = 'DELETE' THEN
+    _row := OLD;
+  ELSE
+    _row := NEW;
+  END IF;
+  PERFORM main.notify_change(
+    CASE TG_OP
+      WHEN 'INSERT' THEN 'currency_added'
+      WHEN 'UPDATE' THEN 'currency_updated'
+      ELSE 'currency_deleted'
+    END,
+    json_build_object('code', _row.code, 'name', _row.name, 'decimals', _row.decimals)
+  );
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER currencies_notify
+AFTER INSERT OR UPDATE OR DELETE ON main.currencies
+FOR EACH ROW EXECUTE FUNCTION main.notify_currency_change();
+
+CREATE OR REPLACE FUNCTION main.notify_price_bar_change()
+RETURNS TRIGGER
+AS $$
+BEGIN
+  PERFORM main.notify_change(
+    CASE TG_OP WHEN 'INSERT' THEN 'price_inserted' ELSE 'price_updated' END,
+    json_build_object(
+      'ticker', s.ticker,
+      'date', NEW.date,
+      'open', NEW.open,
+      'high', NEW.high,
+      'low', NEW.low,
+      'close', NEW.close,
+      'volume', NEW.volume
+    )
+  )
+  FROM main.securities s
+  WHERE s.id = NEW.security;
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER price_bars_notify
+AFTER INSERT OR UPDATE ON main.price_bars
+FOR EACH ROW EXECUTE FUNCTION main.notify_price_bar_change();
+
+CREATE OR REPLACE FUNCTION main.notify_transaction_recorded()
+RETURNS TRIGGER
+AS $$
+BEGIN
+  PERFORM main.notify_change(
+    'transaction_recorded',
+    json_build_object(
+      'id', NEW.id,
+      'portfolio', NEW.portfolio,
+      'kind', NEW.kind,
+      'ticker', (SELECT s.ticker FROM main.securities s WHERE s.id = NEW.security),
+      'executed_at', NEW.executed_at,
+      'quantity', NEW.quantity,
+      'amount', NEW.amount,
+      'fees', NEW.fees
+    )
+  );
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER transactions_notify
+AFTER INSERT ON main.transactions
+FOR EACH ROW EXECUTE FUNCTION main.notify_transaction_recorded();
@@ -0,0 +1 @@
+DROP TYPE IF EXISTS api.entity_count_type CASCADE;
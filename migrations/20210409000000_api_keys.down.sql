@@ -0,0 +1,14 @@
+CREATE OR REPLACE FUNCTION api.entity_counts()
+RETURNS SETOF api.entity_count_type
+AS $$
+  SELECT 'currencies', COUNT(*) FROM main.currencies
+  UNION ALL SELECT 'exchanges', COUNT(*) FROM main.exchanges
+  UNION ALL SELECT 'securities', COUNT(*) FROM main.securities
+  UNION ALL SELECT 'price_bars', COUNT(*) FROM main.price_bars
+  UNION ALL SELECT 'fx_rates', COUNT(*) FROM main.fx_rates
+  UNION ALL SELECT 'portfolios', COUNT(*) FROM main.portfolios
+  UNION ALL SELECT 'transactions', COUNT(*) FROM main.transactions;
+$$ LANGUAGE SQL STABLE;
+
+DROP TYPE IF EXISTS api.api_key_type CASCADE;
+DROP TABLE IF EXISTS main.api_keys;
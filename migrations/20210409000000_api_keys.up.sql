@@ -0,0 +1,67 @@
+-- Only the SHA-256 digest of a key is stored: the key itself is shown once, when created.
+CREATE TABLE main.api_keys (
+  id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+  name VARCHAR(64) NOT NULL UNIQUE,
+  hash CHAR(64) NOT NULL UNIQUE,
+  roles VARCHAR(32)[] NOT NULL DEFAULT '{}',
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  revoked_at TIMESTAMPTZ
+);
+
+CREATE TYPE api.api_key_type AS (
+  id UUID,
+  name VARCHAR(64),
+  roles VARCHAR(32)[],
+  created_at TIMESTAMPTZ,
+  revoked_at TIMESTAMPTZ
+);
+
+CREATE OR REPLACE FUNCTION api.add_api_key(
+  _name VARCHAR(64),
+  _hash CHAR(64),
+  _roles VARCHAR(32)[]
+) RETURNS api.api_key_type
+AS $$
+  INSERT INTO main.api_keys (name, hash, roles)
+  VALUES (_name, _hash, _roles)
+  RETURNING id, name, roles, created_at, revoked_at;
+$$ LANGUAGE SQL VOLATILE;
+
+-- Revoked keys are not found.
+CREATE OR REPLACE FUNCTION api.find_api_key_by_hash(
+  _hash CHAR(64)
+) RETURNS SETOF api.api_key_type
+AS $$
+  SELECT id, name, roles, created_at, revoked_at
+  FROM main.api_keys
+  WHERE hash = _hash AND revoked_at IS NULL;
+$$ LANGUAGE SQL STABLE;
+
+CREATE OR REPLACE FUNCTION api.list_api_keys()
+RETURNS SETOF api.api_key_type
+AS $$
+  SELECT id, name, roles, created_at, revoked_at FROM main.api_keys ORDER BY name;
+$$ LANGUAGE SQL STABLE;
+
+CREATE OR REPLACE FUNCTION api.revoke_api_key(
+  _name VARCHAR(64)
+) RETURNS SETOF api.api_key_type
+AS $$
+  UPDATE main.api_keys
+  SET revoked_at = COALESCE(revoked_at, NOW())
+  WHERE name = _name
+  RETURNING id, name, roles, created_at, revoked_at;
+$$ LANGUAGE SQL VOLATILE;
+
+CREATE OR REPLACE FUNCTION api.entity_counts()
+RETURNS SETOF api.entity_count_type
+AS $$
+  SELECT 'currencies', COUNT(*) FROM main.currencies
+  UNION ALL SELECT 'exchanges', COUNT(*) FROM main.exchanges
+  UNION ALL SELECT 'securities', COUNT(*) FROM main.securities
+  UNION ALL SELECT 'price_bars', COUNT(*) FROM main.price_bars
+  UNION ALL SELECT 'fx_rates', COUNT(*) FROM main.fx_rates
+  UNION ALL SELECT 'portfolios', COUNT(*) FROM main.portfolios
+  UNION ALL SELECT 'transactions', COUNT(*) FROM main.transactions
+  UNION ALL SELECT 'api_keys', COUNT(*) FROM main.api_keys;
+$$ LANGUAGE SQL STABLE;
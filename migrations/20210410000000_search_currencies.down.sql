@@ -0,0 +1,2 @@
+DROP FUNCTION IF EXISTS api.count_currencies(VARCHAR(3), VARCHAR(255), INTEGER);
+DROP FUNCTION IF EXISTS api.search_currencies(VARCHAR(3), VARCHAR(255), INTEGER, VARCHAR(16), BOOLEAN, TEXT, CHAR(3), TEXT, CHAR(3), BOOLEAN, BIGINT, BIGINT);
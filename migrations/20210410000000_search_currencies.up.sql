@@ -0,0 +1,54 @@
+-- The currencies matching the filters (NULL filters match everything), sorted on _sort
+-- ('code', 'name' or 'decimals') then code, a page at a time.
+--
+-- The page is a keyset: the rows strictly after (_after_key, _after_code) and before
+-- (_before_key, _before_code) in the sort order, the keys being the values of the sort column.
+-- With _backward, the rows are returned from the end, in reverse order.
+CREATE OR REPLACE FUNCTION api.search_currencies(
+  _code_prefix VARCHAR(3),
+  _name_contains VARCHAR(255),
+  _decimals INTEGER,
+  _sort VARCHAR(16),
+  _descending BOOLEAN,
+  _after_key TEXT,
+  _after_code CHAR(3),
+  _before_key TEXT,
+  _before_code CHAR(3),
+  _backward BOOLEAN,
+  _limit BIGINT,
+  _offset BIGINT
+) RETURNS SETOF api.currency_type
+AS $$
+DECLARE
+  _column TEXT := CASE _sort WHEN 'name' THEN 'name' WHEN 'decimals' THEN 'decimals' ELSE 'code' END;
+  _type TEXT := CASE _sort WHEN 'name' THEN 'VARCHAR' WHEN 'decimals' THEN 'INTEGER' ELSE 'CHAR(3)' END;
+  _after TEXT := CASE WHEN _descending THEN '<' ELSE '>' END;
+  _before TEXT := CASE WHEN _descending THEN '>' ELSE '<' END;
+  _direction TEXT := CASE WHEN _descending <> _backward THEN 'DESC' ELSE 'ASC' END;
+BEGIN
+  RETURN QUERY EXECUTE format(
+    'SELECT code, name, decimals FROM main.currencies
+     WHERE ($1 IS NULL OR left(code, char_length($1)) = $1)
+       AND ($2 IS NULL OR strpos(lower(name), lower($2)) > 0)
+       AND ($3 IS NULL OR decimals = $3)
+       AND ($5 IS NULL OR (%1$I, code) %2$s ($4::%4$s, $5))
+       AND ($7 IS NULL OR (%1$I, code) %3$s ($6::%4$s, $7))
+     ORDER BY %1$I %5$s, code %5$s
+     LIMIT $8 OFFSET $9',
+    _column, _after, _before, _type, _direction)
+  USING _code_prefix, _name_contains, _decimals, _after_key, _after_code, _before_key,
+    _before_code, _limit, _offset;
+END;
+$$ LANGUAGE plpgsql STABLE;
+
+CREATE OR REPLACE FUNCTION api.count_currencies(
+  _code_prefix VARCHAR(3),
+  _name_contains VARCHAR(255),
+  _decimals INTEGER
+) RETURNS BIGINT
+AS $$
+  SELECT COUNT(*) FROM main.currencies
+  WHERE (_code_prefix IS NULL OR left(code, char_length(_code_prefix)) = _code_prefix)
+    AND (_name_contains IS NULL OR strpos(lower(name), lower(_name_contains)) > 0)
+    AND (_decimals IS NULL OR decimals = _decimals);
+$$ LANGUAGE SQL STABLE;
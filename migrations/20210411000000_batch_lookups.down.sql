@@ -0,0 +1,3 @@
+DROP FUNCTION IF EXISTS api.find_securities_by_ticker(VARCHAR(32)[]);
+DROP FUNCTION IF EXISTS api.find_exchanges_by_mic(CHAR(4)[]);
+DROP FUNCTION IF EXISTS api.find_currencies_by_code(CHAR(3)[]);
@@ -0,0 +1,22 @@
+-- Lookups of several rows at once, used to batch the resolution of nested objects.
+
+CREATE OR REPLACE FUNCTION api.find_currencies_by_code(
+  _codes CHAR(3)[]
+) RETURNS SETOF api.currency_type
+AS $$
+  SELECT code, name, decimals FROM main.currencies WHERE code = ANY(_codes);
+$$ LANGUAGE SQL STABLE;
+
+CREATE OR REPLACE FUNCTION api.find_exchanges_by_mic(
+  _mics CHAR(4)[]
+) RETURNS SETOF api.exchange_type
+AS $$
+  SELECT mic, name, country, timezone, open_time, close_time, currency FROM main.exchanges WHERE mic = ANY(_mics);
+$$ LANGUAGE SQL STABLE;
+
+CREATE OR REPLACE FUNCTION api.find_securities_by_ticker(
+  _tickers VARCHAR(32)[]
+) RETURNS SETOF api.security_type
+AS $$
+  SELECT id, ticker, isin, name, exchange, currency, asset_class FROM main.securities WHERE ticker = ANY(_tickers);
+$$ LANGUAGE SQL STABLE;
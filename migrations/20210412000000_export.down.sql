@@ -0,0 +1,3 @@
+DROP FUNCTION IF EXISTS api.export_price_bars(TIMESTAMPTZ);
+DROP FUNCTION IF EXISTS api.export_securities(TIMESTAMPTZ);
+DROP FUNCTION IF EXISTS api.export_currencies(TIMESTAMPTZ);
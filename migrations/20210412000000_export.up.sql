@@ -0,0 +1,33 @@
+-- Rows created or updated since a point in time, all of them without one, in key order.
+-- These functions are inlined in the calling query, so their rows can be streamed.
+
+CREATE OR REPLACE FUNCTION api.export_currencies(
+  _since TIMESTAMPTZ
+) RETURNS SETOF api.currency_type
+AS $$
+  SELECT code, name, decimals
+  FROM main.currencies
+  WHERE _since IS NULL OR updated_at >= _since
+  ORDER BY code;
+$$ LANGUAGE SQL STABLE;
+
+CREATE OR REPLACE FUNCTION api.export_securities(
+  _since TIMESTAMPTZ
+) RETURNS SETOF api.security_type
+AS $$
+  SELECT id, ticker, isin, name, exchange, currency, asset_class
+  FROM main.securities
+  WHERE _since IS NULL OR updated_at >= _since
+  ORDER BY ticker;
+$$ LANGUAGE SQL STABLE;
+
+CREATE OR REPLACE FUNCTION api.export_price_bars(
+  _since TIMESTAMPTZ
+) RETURNS SETOF api.price_bar_type
+AS $$
+  SELECT s.ticker, p.date, p.open, p.high, p.low, p.close, p.volume
+  FROM main.price_bars p
+  JOIN main.securities s ON s.id = p.security
+  WHERE _since IS NULL OR p.updated_at >= _since
+  ORDER BY s.ticker, p.date;
+$$ LANGUAGE SQL STABLE;
@@ -0,0 +1 @@
+DROP FUNCTION IF EXISTS api.lock_portfolio(UUID);
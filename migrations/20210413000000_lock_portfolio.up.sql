@@ -0,0 +1,8 @@
+-- Changes to a ledger are checked against the whole ledger, so they are made one at a time.
+
+CREATE OR REPLACE FUNCTION api.lock_portfolio(
+  _id UUID
+) RETURNS SETOF api.portfolio_type
+AS $$
+  SELECT id, name, currency FROM main.portfolios WHERE id = _id FOR UPDATE;
+$$ LANGUAGE SQL VOLATILE;
@@ -0,0 +1,413 @@
+//! Authentication of the callers, with API keys or JWT bearer tokens.
+//!
+//! API keys are sent in the `X-API-Key` header. They are random, so their SHA-256 digest is
+//! enough to store them. JWTs are sent in the `Authorization: Bearer` header, signed with the
+//! HS256 secret or the RS256 key of the settings, and carry the caller's roles in a `roles`
+//! claim.
+//!
+//! Roles are granted permissions in the settings, and resolvers require a permission with a
+//! `PermissionGuard`.
+
+use async_graphql::guard::Guard;
+use async_graphql::{Context, Enum, ErrorExtensions, InputType, SimpleObject};
+use http::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use rand::RngCore;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use snafu::{ResultExt, Snafu};
+use sqlx::postgres::PgPool;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use super::error::Code;
+use super::metrics;
+use crate::db::model::{ProvideError, ProvideStock};
+use crate::settings::{self, JwtAlgorithm};
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Prefix of the keys, telling them apart from other secrets.
+const API_KEY_PREFIX: &str = "stk_";
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Missing credentials"))]
+    MissingCredentials,
+    #[snafu(display("Invalid API key"))]
+    InvalidApiKey,
+    #[snafu(display("Invalid token: {}", source))]
+    InvalidToken { source: jsonwebtoken::errors::Error },
+    #[snafu(display("Unsupported authorization scheme"))]
+    UnsupportedScheme,
+    #[snafu(display("Invalid JWT settings: {}", msg))]
+    SettingsError { msg: String },
+    #[snafu(display("Invalid permission '{}' of role {}", permission, role))]
+    PermissionError { role: String, permission: String },
+    #[snafu(display("Could not get database connection: {}", source))]
+    DBConnectionError { source: sqlx::Error },
+    #[snafu(display("Could not find API key: {}", source))]
+    DBProvideError { source: ProvideError },
+}
+
+#[derive(Enum, Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AuthMethod {
+    ApiKey,
+    Jwt,
+    /// No credentials, when authentication is not required
+    Anonymous,
+}
+
+/// What a role allows.
+#[derive(Enum, Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Permission {
+    /// Run queries and subscriptions
+    Read,
+    /// Record market data, portfolios and transactions
+    Write,
+    /// Manage reference data: currencies, exchanges and securities
+    Admin,
+}
+
+impl std::str::FromStr for Permission {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read" => Ok(Permission::Read),
+            "write" => Ok(Permission::Write),
+            "admin" => Ok(Permission::Admin),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The permissions granted to each role, from the settings.
+#[derive(Debug, Clone, Default)]
+pub struct Roles(HashMap<String, Vec<Permission>>);
+
+impl Roles {
+    pub fn new(roles: &HashMap<String, Vec<String>>) -> Result<Self, Error> {
+        roles
+            .iter()
+            .map(|(role, permissions)| {
+                let permissions = permissions
+                    .iter()
+                    .map(|permission| {
+                        permission.parse().map_err(|_| Error::PermissionError {
+                            role: role.clone(),
+                            permission: permission.clone(),
+                        })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok((role.clone(), permissions))
+            })
+            .collect::<Result<_, _>>()
+            .map(Roles)
+    }
+
+    /// The permissions of all these roles. Unknown roles grant nothing.
+    pub fn permissions(&self, roles: &[String]) -> Vec<Permission> {
+        let mut permissions = roles
+            .iter()
+            .filter_map(|role| self.0.get(role))
+            .flatten()
+            .copied()
+            .collect::<Vec<_>>();
+        permissions.sort();
+        permissions.dedup();
+        permissions
+    }
+}
+
+/// The identity of the caller.
+#[derive(Debug, Clone, PartialEq, SimpleObject)]
+pub struct Principal {
+    /// The name of the API key, or the subject of the token
+    pub subject: String,
+    pub method: AuthMethod,
+    pub roles: Vec<String>,
+    /// The permissions granted by the roles
+    pub permissions: Vec<Permission>,
+}
+
+impl Principal {
+    /// A caller without credentials nor permissions.
+    pub fn anonymous() -> Self {
+        Principal {
+            subject: String::from("anonymous"),
+            method: AuthMethod::Anonymous,
+            roles: Vec::new(),
+            permissions: Vec::new(),
+        }
+    }
+}
+
+/// Lets the field be resolved only for callers with `permission`.
+///
+/// Callers without it get a `FORBIDDEN` error, and so do requests without a principal.
+pub struct PermissionGuard {
+    pub permission: Permission,
+}
+
+#[async_trait::async_trait]
+impl Guard for PermissionGuard {
+    async fn check(&self, ctx: &Context<'_>) -> async_graphql::Result<()> {
+        let allowed = ctx
+            .data_opt::<Principal>()
+            .is_some_and(|principal| principal.permissions.contains(&self.permission));
+        if allowed {
+            return Ok(());
+        }
+        let permission = self.permission;
+        Err(
+            async_graphql::Error::new(format!("Forbidden, {:?} permission required", permission))
+                .extend_with(|_, e| {
+                    e.set("code", Code::Forbidden.as_str());
+                    e.set("permission", permission.to_value());
+                }),
+        )
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Claims {
+    sub: String,
+    #[serde(default)]
+    roles: Vec<String>,
+}
+
+struct Jwt {
+    key: DecodingKey<'static>,
+    validation: Validation,
+}
+
+impl TryFrom<&settings::Jwt> for Jwt {
+    type Error = Error;
+
+    fn try_from(settings: &settings::Jwt) -> Result<Self, Self::Error> {
+        let (algorithm, key) = match settings.algorithm {
+            JwtAlgorithm::HS256 => {
+                let secret = settings.secret.as_ref().ok_or(Error::SettingsError {
+                    msg: String::from("HS256 needs a secret"),
+                })?;
+                (
+                    Algorithm::HS256,
+                    DecodingKey::from_secret(secret.as_bytes()).into_static(),
+                )
+            }
+            JwtAlgorithm::RS256 => {
+                let path = settings.public_key.as_ref().ok_or(Error::SettingsError {
+                    msg: String::from("RS256 needs a public key"),
+                })?;
+                let pem = std::fs::read(path).map_err(|err| Error::SettingsError {
+                    msg: format!("could not read {}: {}", path, err),
+                })?;
+                let key = DecodingKey::from_rsa_pem(&pem).map_err(|err| Error::SettingsError {
+                    msg: format!("invalid public key {}: {}", path, err),
+                })?;
+                (Algorithm::RS256, key.into_static())
+            }
+        };
+
+        let mut validation = Validation::new(algorithm);
+        validation.iss = settings.issuer.clone();
+        if let Some(audience) = &settings.audience {
+            validation.set_audience(&[audience]);
+        }
+        Ok(Jwt { key, validation })
+    }
+}
+
+/// A new API key, to hand over to its user. Only its digest is stored.
+pub fn generate_api_key() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    format!("{}{}", API_KEY_PREFIX, hex::encode(bytes))
+}
+
+/// The digest of an API key, as stored.
+pub fn hash_api_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+/// Resolves the principal of a request from its headers.
+pub struct Authenticator {
+    pool: PgPool,
+    jwt: Option<Jwt>,
+    required: bool,
+    roles: Roles,
+    anonymous_roles: Vec<String>,
+}
+
+impl Authenticator {
+    pub fn new(pool: PgPool, settings: &settings::Auth) -> Result<Self, Error> {
+        Ok(Authenticator {
+            pool,
+            jwt: settings.jwt.as_ref().map(Jwt::try_from).transpose()?,
+            required: settings.enabled,
+            roles: Roles::new(&settings.roles)?,
+            anonymous_roles: settings.anonymous_roles.clone(),
+        })
+    }
+
+    fn principal(&self, subject: String, method: AuthMethod, roles: Vec<String>) -> Principal {
+        Principal {
+            subject,
+            method,
+            permissions: self.roles.permissions(&roles),
+            roles,
+        }
+    }
+
+    /// The principal of a request, anonymous when there are no credentials and they are not
+    /// required.
+    pub async fn authenticate(&self, headers: &HeaderMap) -> Result<Principal, Error> {
+        if let Some(key) = headers.get(API_KEY_HEADER) {
+            let key = key.to_str().map_err(|_| Error::InvalidApiKey)?;
+            return self.authenticate_api_key(key).await;
+        }
+        if let Some(authorization) = headers.get(AUTHORIZATION) {
+            let authorization = authorization
+                .to_str()
+                .map_err(|_| Error::UnsupportedScheme)?;
+            let claims = match (authorization.strip_prefix("Bearer "), &self.jwt) {
+                (Some(token), Some(jwt)) => authenticate_token(jwt, token.trim())?,
+                _ => return Err(Error::UnsupportedScheme),
+            };
+            return Ok(self.principal(claims.sub, AuthMethod::Jwt, claims.roles));
+        }
+        if self.required {
+            Err(Error::MissingCredentials)
+        } else {
+            Ok(self.principal(
+                String::from("anonymous"),
+                AuthMethod::Anonymous,
+                self.anonymous_roles.clone(),
+            ))
+        }
+    }
+
+    /// The principal of a websocket connection, from the credentials in the payload of its
+    /// `connection_init` message, eg `{ "X-API-Key": "..." }`.
+    pub async fn authenticate_payload(
+        &self,
+        payload: &serde_json::Value,
+    ) -> Result<Principal, Error> {
+        let mut headers = HeaderMap::new();
+        if let Some(payload) = payload.as_object() {
+            for (name, value) in payload {
+                let name = HeaderName::try_from(name.as_str());
+                let value = value.as_str().map(HeaderValue::try_from);
+                if let (Ok(name), Some(Ok(value))) = (name, value) {
+                    headers.insert(name, value);
+                }
+            }
+        }
+        self.authenticate(&headers).await
+    }
+
+    async fn authenticate_api_key(&self, key: &str) -> Result<Principal, Error> {
+        let mut conn = metrics::acquire(&self.pool)
+            .await
+            .context(DBConnectionError)?;
+        let key = conn
+            .find_api_key_by_hash(&hash_api_key(key))
+            .await
+            .context(DBProvideError)?
+            .ok_or(Error::InvalidApiKey)?;
+        Ok(self.principal(key.name, AuthMethod::ApiKey, key.roles))
+    }
+}
+
+fn authenticate_token(jwt: &Jwt, token: &str) -> Result<Claims, Error> {
+    jsonwebtoken::decode::<Claims>(token, &jwt.key, &jwt.validation)
+        .context(InvalidToken)
+        .map(|data| data.claims)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{EncodingKey, Header};
+    use serde_json::json;
+
+    fn jwt(secret: &str) -> Jwt {
+        Jwt::try_from(&settings::Jwt {
+            algorithm: JwtAlgorithm::HS256,
+            secret: Some(String::from(secret)),
+            public_key: None,
+            issuer: Some(String::from("stocks-test")),
+            audience: None,
+        })
+        .expect("jwt")
+    }
+
+    fn token(claims: serde_json::Value, secret: &str) -> String {
+        jsonwebtoken::encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(secret.as_bytes()),
+        )
+        .expect("token")
+    }
+
+    #[test]
+    fn test_api_keys_are_random_and_hashed() {
+        let key = generate_api_key();
+
+        assert!(key.starts_with(API_KEY_PREFIX));
+        assert_ne!(key, generate_api_key());
+        assert_eq!(hash_api_key(&key).len(), 64);
+        assert_eq!(hash_api_key(&key), hash_api_key(&key));
+    }
+
+    #[test]
+    fn test_token_with_valid_signature_and_claims() {
+        let exp = chrono::Utc::now().timestamp() + 60;
+        let valid = token(
+            json!({ "sub": "alice", "iss": "stocks-test", "exp": exp, "roles": ["reader"] }),
+            "secret",
+        );
+        let forged = token(
+            json!({ "sub": "alice", "iss": "stocks-test", "exp": exp }),
+            "guess",
+        );
+        let expired = token(
+            json!({ "sub": "alice", "iss": "stocks-test", "exp": exp - 3600 }),
+            "secret",
+        );
+        let foreign = token(
+            json!({ "sub": "alice", "iss": "elsewhere", "exp": exp }),
+            "secret",
+        );
+
+        let claims = authenticate_token(&jwt("secret"), &valid).expect("claims");
+        assert_eq!(claims.sub, "alice");
+        assert_eq!(claims.roles, vec![String::from("reader")]);
+        assert!(authenticate_token(&jwt("secret"), &forged).is_err());
+        assert!(authenticate_token(&jwt("secret"), &expired).is_err());
+        assert!(authenticate_token(&jwt("secret"), &foreign).is_err());
+    }
+
+    #[test]
+    fn test_permissions_are_the_union_of_the_roles() {
+        let mut settings = HashMap::new();
+        settings.insert(String::from("reader"), vec![String::from("read")]);
+        settings.insert(
+            String::from("writer"),
+            vec![String::from("read"), String::from("write")],
+        );
+        let roles = Roles::new(&settings).expect("roles");
+        let role = |role: &str| String::from(role);
+
+        assert_eq!(
+            roles.permissions(&[role("reader"), role("writer"), role("unknown")]),
+            vec![Permission::Read, Permission::Write]
+        );
+        assert!(roles.permissions(&[role("unknown")]).is_empty());
+
+        settings.insert(String::from("root"), vec![String::from("everything")]);
+        assert!(Roles::new(&settings).is_err());
+    }
+}
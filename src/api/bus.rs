@@ -0,0 +1,105 @@
+use futures::stream::{self, Stream};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tracing::warn;
+
+use super::model;
+
+/// Number of events kept for subscribers which are falling behind.
+const DEFAULT_CAPACITY: usize = 256;
+
+/// A change in the store, published once the transaction making it is committed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "payload", rename_all = "snake_case")]
+pub enum Event {
+    PriceUpdated(model::PriceUpdate),
+    CurrencyChanged(model::CurrencyChange),
+    TransactionRecorded(model::Transaction),
+}
+
+/// In-process broadcast of store changes, feeding the GraphQL subscriptions.
+///
+/// Cloning a bus gives another handle on the same channel.
+#[derive(Debug, Clone)]
+pub struct Bus {
+    sender: broadcast::Sender<Event>,
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+        Bus::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl Bus {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        Bus { sender }
+    }
+
+    /// Publish an event to the current subscribers, if any.
+    pub fn publish(&self, event: Event) {
+        // Sending only fails when nobody is listening, which is not an error.
+        let _ = self.sender.send(event);
+    }
+
+    /// Stream the events published from now on.
+    ///
+    /// A subscriber falling behind by more than the capacity of the bus skips the events it
+    /// missed.
+    pub fn subscribe(&self) -> impl Stream<Item = Event> {
+        stream::unfold(self.sender.subscribe(), |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => return Some((event, receiver)),
+                    Err(broadcast::error::RecvError::Lagged(count)) => {
+                        warn!("Subscriber lagging, skipped {} events", count);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    fn currency_change(code: &str) -> Event {
+        Event::CurrencyChanged(model::CurrencyChange {
+            kind: model::ChangeKind::Added,
+            currency: model::Currency {
+                code: String::from(code),
+                name: String::from("Test"),
+                decimals: 2,
+            },
+        })
+    }
+
+    #[tokio::test]
+    async fn test_subscribers_receive_events_published_after_subscribing() {
+        let bus = Bus::new(4);
+        bus.publish(currency_change("XXX"));
+
+        let events = bus.subscribe();
+        futures::pin_mut!(events);
+        bus.publish(currency_change("XTS"));
+
+        assert_eq!(events.next().await, Some(currency_change("XTS")));
+    }
+
+    #[tokio::test]
+    async fn test_lagging_subscriber_skips_events() {
+        let bus = Bus::new(2);
+        let events = bus.subscribe();
+        futures::pin_mut!(events);
+        for code in &["AAA", "BBB", "CCC"] {
+            bus.publish(currency_change(code));
+        }
+
+        assert_eq!(events.next().await, Some(currency_change("BBB")));
+        assert_eq!(events.next().await, Some(currency_change("CCC")));
+    }
+}
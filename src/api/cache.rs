@@ -0,0 +1,608 @@
+//! A read-through cache of the reference data, in front of any `StockService`.
+//!
+//! Currencies and exchanges are read by most queries, and seldom change. `CachedStockService`
+//! keeps them in memory for a while, and forgets them as soon as it changes them itself.
+//! Changes made by other instances, or directly in the database, are seen once the values
+//! expire. Everything else goes straight to the inner service.
+
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use super::error;
+use super::import;
+use super::metrics;
+use super::model::{
+    AssetClass, CostMethod, Currency, Exchange, FxRate, Interval, NewTransaction, Page,
+    Performance, Portfolio, Position, PriceBar, Security, StockService, TradingHours, Transaction,
+};
+use crate::db::model as db;
+use crate::settings;
+
+/// Lookups served from memory (hits), and passed to the inner service (misses).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+pub struct CachedStockService<S> {
+    inner: S,
+    currencies: Store<Currency>,
+    exchanges: Store<Exchange>,
+}
+
+impl<S> CachedStockService<S> {
+    pub fn new(inner: S, settings: &settings::Cache) -> Self {
+        let ttl = Duration::from_secs(settings.ttl_secs);
+        CachedStockService {
+            inner,
+            currencies: Store::new("currency", ttl, settings.max_entries),
+            exchanges: Store::new("exchange", ttl, settings.max_entries),
+        }
+    }
+
+    pub fn currency_stats(&self) -> CacheStats {
+        self.currencies.stats()
+    }
+
+    pub fn exchange_stats(&self) -> CacheStats {
+        self.exchanges.stats()
+    }
+}
+
+/// The values of one entity: the whole list, and the values by key.
+struct Store<V> {
+    entity: &'static str,
+    ttl: Duration,
+    max_entries: usize,
+    entries: Mutex<Entries<V>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+struct Entries<V> {
+    /// Incremented when the store is cleared, so values read before are not stored after.
+    generation: u64,
+    all: Option<(Instant, Vec<V>)>,
+    /// `None` for the keys the inner service did not find
+    by_key: HashMap<String, (Instant, Option<V>)>,
+}
+
+impl<V: Clone> Store<V> {
+    fn new(entity: &'static str, ttl: Duration, max_entries: usize) -> Self {
+        Store {
+            entity,
+            ttl,
+            max_entries,
+            entries: Mutex::new(Entries {
+                generation: 0,
+                all: None,
+                by_key: HashMap::new(),
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    fn record(&self, hit: bool) {
+        if hit {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+        }
+        metrics::record_cache_lookup(self.entity, hit);
+    }
+
+    fn generation(&self) -> u64 {
+        self.entries.lock().expect("cache").generation
+    }
+
+    fn get_all(&self) -> Option<Vec<V>> {
+        let all = {
+            let entries = self.entries.lock().expect("cache");
+            entries
+                .all
+                .as_ref()
+                .filter(|(inserted, _)| inserted.elapsed() < self.ttl)
+                .map(|(_, values)| values.clone())
+        };
+        self.record(all.is_some());
+        all
+    }
+
+    /// Keep the whole list, unless it holds more values than the store may.
+    fn put_all(&self, generation: u64, values: &[V]) {
+        if self.max_entries == 0 || values.len() > self.max_entries {
+            return;
+        }
+        let mut entries = self.entries.lock().expect("cache");
+        if entries.generation == generation {
+            entries.all = Some((Instant::now(), values.to_vec()));
+        }
+    }
+
+    /// The value of the key if it is in memory, `Some(None)` if it is known not to exist.
+    fn get(&self, key: &str) -> Option<Option<V>> {
+        let value = {
+            let entries = self.entries.lock().expect("cache");
+            entries
+                .by_key
+                .get(key)
+                .filter(|(inserted, _)| inserted.elapsed() < self.ttl)
+                .map(|(_, value)| value.clone())
+        };
+        self.record(value.is_some());
+        value
+    }
+
+    /// Keep the value of the key, making room by dropping the expired values, then the oldest.
+    fn put(&self, generation: u64, key: &str, value: Option<V>) {
+        if self.max_entries == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().expect("cache");
+        if entries.generation != generation {
+            return;
+        }
+        if !entries.by_key.contains_key(key) && entries.by_key.len() >= self.max_entries {
+            let ttl = self.ttl;
+            entries
+                .by_key
+                .retain(|_, (inserted, _)| inserted.elapsed() < ttl);
+            if entries.by_key.len() >= self.max_entries {
+                let oldest = entries
+                    .by_key
+                    .iter()
+                    .min_by_key(|(_, (inserted, _))| *inserted)
+                    .map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    entries.by_key.remove(&oldest);
+                }
+            }
+        }
+        entries
+            .by_key
+            .insert(String::from(key), (Instant::now(), value));
+    }
+
+    fn clear(&self) {
+        let mut entries = self.entries.lock().expect("cache");
+        entries.generation += 1;
+        entries.all = None;
+        entries.by_key.clear();
+    }
+}
+
+#[async_trait]
+impl<S: StockService + Send + Sync> StockService for CachedStockService<S> {
+    async fn list_currencies(&self) -> Result<Vec<Currency>, error::Error> {
+        if let Some(currencies) = self.currencies.get_all() {
+            return Ok(currencies);
+        }
+        let generation = self.currencies.generation();
+        let currencies = self.inner.list_currencies().await?;
+        self.currencies.put_all(generation, &currencies);
+        Ok(currencies)
+    }
+
+    async fn search_currencies(
+        &self,
+        filter: db::CurrencyFilter,
+        sort: db::CurrencySortKey,
+        descending: bool,
+        page: db::Pagination,
+    ) -> Result<Page<Currency>, error::Error> {
+        self.inner
+            .search_currencies(filter, sort, descending, page)
+            .await
+    }
+
+    async fn add_currency(
+        &self,
+        code: &str,
+        name: &str,
+        decimals: i32,
+    ) -> Result<Currency, error::Error> {
+        let currency = self.inner.add_currency(code, name, decimals).await?;
+        self.currencies.clear();
+        Ok(currency)
+    }
+
+    async fn find_currency(&self, code: &str) -> Result<Option<Currency>, error::Error> {
+        if let Some(currency) = self.currencies.get(code) {
+            return Ok(currency);
+        }
+        let generation = self.currencies.generation();
+        let currency = self.inner.find_currency(code).await?;
+        self.currencies.put(generation, code, currency.clone());
+        Ok(currency)
+    }
+
+    async fn find_currencies(&self, codes: &[String]) -> Result<Vec<Currency>, error::Error> {
+        let mut currencies = Vec::new();
+        let mut missing = Vec::new();
+        for code in codes {
+            match self.currencies.get(code) {
+                Some(currency) => currencies.extend(currency),
+                None => missing.push(code.clone()),
+            }
+        }
+        if !missing.is_empty() {
+            let generation = self.currencies.generation();
+            let found = self.inner.find_currencies(&missing).await?;
+            for code in &missing {
+                let currency = found.iter().find(|c| &c.code == code).cloned();
+                self.currencies.put(generation, code, currency);
+            }
+            currencies.extend(found);
+        }
+        Ok(currencies)
+    }
+
+    async fn update_currency(
+        &self,
+        code: &str,
+        name: Option<String>,
+        decimals: Option<i32>,
+    ) -> Result<Currency, error::Error> {
+        let currency = self.inner.update_currency(code, name, decimals).await?;
+        self.currencies.clear();
+        Ok(currency)
+    }
+
+    async fn delete_currency(&self, code: &str) -> Result<Currency, error::Error> {
+        let currency = self.inner.delete_currency(code).await?;
+        self.currencies.clear();
+        Ok(currency)
+    }
+
+    async fn import_currencies(
+        &self,
+        data: Vec<u8>,
+        format: import::Format,
+        on_conflict: import::OnConflict,
+    ) -> Result<import::ImportReport, error::Error> {
+        let report = self
+            .inner
+            .import_currencies(data, format, on_conflict)
+            .await?;
+        self.currencies.clear();
+        Ok(report)
+    }
+
+    async fn list_securities(&self) -> Result<Vec<Security>, error::Error> {
+        self.inner.list_securities().await
+    }
+
+    async fn add_security(
+        &self,
+        ticker: &str,
+        isin: &str,
+        name: &str,
+        exchange: &str,
+        currency: &str,
+        asset_class: AssetClass,
+    ) -> Result<Security, error::Error> {
+        self.inner
+            .add_security(ticker, isin, name, exchange, currency, asset_class)
+            .await
+    }
+
+    async fn find_security_by_ticker(
+        &self,
+        ticker: &str,
+    ) -> Result<Option<Security>, error::Error> {
+        self.inner.find_security_by_ticker(ticker).await
+    }
+
+    async fn find_security_by_isin(&self, isin: &str) -> Result<Option<Security>, error::Error> {
+        self.inner.find_security_by_isin(isin).await
+    }
+
+    async fn find_securities_by_ticker(
+        &self,
+        tickers: &[String],
+    ) -> Result<Vec<Security>, error::Error> {
+        self.inner.find_securities_by_ticker(tickers).await
+    }
+
+    async fn list_exchanges(&self) -> Result<Vec<Exchange>, error::Error> {
+        if let Some(exchanges) = self.exchanges.get_all() {
+            return Ok(exchanges);
+        }
+        let generation = self.exchanges.generation();
+        let exchanges = self.inner.list_exchanges().await?;
+        self.exchanges.put_all(generation, &exchanges);
+        Ok(exchanges)
+    }
+
+    async fn add_exchange(
+        &self,
+        mic: &str,
+        name: &str,
+        country: &str,
+        timezone: &str,
+        trading_hours: TradingHours,
+        currency: &str,
+    ) -> Result<Exchange, error::Error> {
+        let exchange = self
+            .inner
+            .add_exchange(mic, name, country, timezone, trading_hours, currency)
+            .await?;
+        self.exchanges.clear();
+        Ok(exchange)
+    }
+
+    async fn find_exchange(&self, mic: &str) -> Result<Option<Exchange>, error::Error> {
+        if let Some(exchange) = self.exchanges.get(mic) {
+            return Ok(exchange);
+        }
+        let generation = self.exchanges.generation();
+        let exchange = self.inner.find_exchange(mic).await?;
+        self.exchanges.put(generation, mic, exchange.clone());
+        Ok(exchange)
+    }
+
+    async fn find_exchanges(&self, mics: &[String]) -> Result<Vec<Exchange>, error::Error> {
+        let mut exchanges = Vec::new();
+        let mut missing = Vec::new();
+        for mic in mics {
+            match self.exchanges.get(mic) {
+                Some(exchange) => exchanges.extend(exchange),
+                None => missing.push(mic.clone()),
+            }
+        }
+        if !missing.is_empty() {
+            let generation = self.exchanges.generation();
+            let found = self.inner.find_exchanges(&missing).await?;
+            for mic in &missing {
+                let exchange = found.iter().find(|e| &e.mic == mic).cloned();
+                self.exchanges.put(generation, mic, exchange);
+            }
+            exchanges.extend(found);
+        }
+        Ok(exchanges)
+    }
+
+    async fn update_exchange(
+        &self,
+        mic: &str,
+        name: Option<String>,
+        country: Option<String>,
+        timezone: Option<String>,
+        trading_hours: Option<TradingHours>,
+        currency: Option<String>,
+    ) -> Result<Exchange, error::Error> {
+        let exchange = self
+            .inner
+            .update_exchange(mic, name, country, timezone, trading_hours, currency)
+            .await?;
+        self.exchanges.clear();
+        Ok(exchange)
+    }
+
+    async fn delete_exchange(&self, mic: &str) -> Result<Exchange, error::Error> {
+        let exchange = self.inner.delete_exchange(mic).await?;
+        self.exchanges.clear();
+        Ok(exchange)
+    }
+
+    async fn add_price_bars(&self, ticker: &str, bars: Vec<PriceBar>) -> Result<u64, error::Error> {
+        self.inner.add_price_bars(ticker, bars).await
+    }
+
+    async fn price_history(
+        &self,
+        ticker: &str,
+        from: NaiveDate,
+        to: NaiveDate,
+        interval: Interval,
+    ) -> Result<Vec<PriceBar>, error::Error> {
+        self.inner.price_history(ticker, from, to, interval).await
+    }
+
+    async fn add_fx_rate(
+        &self,
+        base: &str,
+        quote: &str,
+        date: NaiveDate,
+        rate: f64,
+    ) -> Result<FxRate, error::Error> {
+        self.inner.add_fx_rate(base, quote, date, rate).await
+    }
+
+    async fn fx_rate(
+        &self,
+        base: &str,
+        quote: &str,
+        date: NaiveDate,
+        pivot: &str,
+    ) -> Result<Option<FxRate>, error::Error> {
+        self.inner.fx_rate(base, quote, date, pivot).await
+    }
+
+    async fn convert(
+        &self,
+        amount: f64,
+        from: &str,
+        to: &str,
+        date: NaiveDate,
+        pivot: &str,
+    ) -> Result<Option<f64>, error::Error> {
+        self.inner.convert(amount, from, to, date, pivot).await
+    }
+
+    async fn list_portfolios(&self) -> Result<Vec<Portfolio>, error::Error> {
+        self.inner.list_portfolios().await
+    }
+
+    async fn add_portfolio(&self, name: &str, currency: &str) -> Result<Portfolio, error::Error> {
+        self.inner.add_portfolio(name, currency).await
+    }
+
+    async fn find_portfolio(&self, id: Uuid) -> Result<Option<Portfolio>, error::Error> {
+        self.inner.find_portfolio(id).await
+    }
+
+    async fn add_transaction(
+        &self,
+        portfolio: Uuid,
+        transaction: NewTransaction,
+    ) -> Result<Transaction, error::Error> {
+        self.inner.add_transaction(portfolio, transaction).await
+    }
+
+    async fn list_transactions(&self, portfolio: Uuid) -> Result<Vec<Transaction>, error::Error> {
+        self.inner.list_transactions(portfolio).await
+    }
+
+    async fn delete_transaction(&self, id: Uuid) -> Result<Transaction, error::Error> {
+        self.inner.delete_transaction(id).await
+    }
+
+    async fn positions(&self, portfolio: Uuid) -> Result<Vec<Position>, error::Error> {
+        self.inner.positions(portfolio).await
+    }
+
+    async fn performance(
+        &self,
+        portfolio: Uuid,
+        method: CostMethod,
+        as_of: NaiveDate,
+    ) -> Result<Performance, error::Error> {
+        self.inner.performance(portfolio, method, as_of).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::model::MockStockService;
+
+    fn settings(ttl_secs: u64, max_entries: usize) -> settings::Cache {
+        settings::Cache {
+            enabled: true,
+            ttl_secs,
+            max_entries,
+        }
+    }
+
+    fn currency(code: &str, name: &str) -> Currency {
+        Currency {
+            code: String::from(code),
+            name: String::from(name),
+            decimals: 2,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_currency_is_read_through_and_invalidated_by_updates() {
+        let mut inner = MockStockService::new();
+        let mut seq = mockall::Sequence::new();
+        inner
+            .expect_find_currency()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|code| Ok(Some(currency(code, "Euro"))));
+        inner
+            .expect_update_currency()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|code, name, _| Ok(currency(code, &name.unwrap())));
+        inner
+            .expect_find_currency()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|code| Ok(Some(currency(code, "Euro zone"))));
+        let service = CachedStockService::new(inner, &settings(60, 10));
+
+        let found = service.find_currency("EUR").await.expect("find");
+        assert_eq!(found.unwrap().name, "Euro");
+        let found = service.find_currency("EUR").await.expect("find");
+        assert_eq!(found.unwrap().name, "Euro");
+        assert_eq!(service.currency_stats(), CacheStats { hits: 1, misses: 1 });
+
+        service
+            .update_currency("EUR", Some(String::from("Euro zone")), None)
+            .await
+            .expect("update");
+        let found = service.find_currency("EUR").await.expect("find");
+        assert_eq!(found.unwrap().name, "Euro zone");
+        assert_eq!(service.currency_stats(), CacheStats { hits: 1, misses: 2 });
+    }
+
+    #[tokio::test]
+    async fn test_only_missing_currencies_are_looked_up() {
+        let mut inner = MockStockService::new();
+        inner
+            .expect_find_currency()
+            .times(1)
+            .returning(|code| Ok(Some(currency(code, code))));
+        inner
+            .expect_find_currencies()
+            .withf(|codes| codes == ["USD", "XXX"])
+            .times(1)
+            .returning(|_| Ok(vec![currency("USD", "USD")]));
+        let service = CachedStockService::new(inner, &settings(60, 10));
+
+        service.find_currency("EUR").await.expect("find");
+        let codes = vec![
+            String::from("EUR"),
+            String::from("USD"),
+            String::from("XXX"),
+        ];
+        let found = service.find_currencies(&codes).await.expect("find");
+        assert_eq!(found.len(), 2);
+        // All known now, including the code that does not exist.
+        let found = service.find_currencies(&codes).await.expect("find");
+        assert_eq!(found.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_expired_and_evicted_values_are_looked_up_again() {
+        let mut inner = MockStockService::new();
+        inner
+            .expect_list_exchanges()
+            .times(2)
+            .returning(|| Ok(Vec::new()));
+        let service = CachedStockService::new(inner, &settings(0, 10));
+        service.list_exchanges().await.expect("list");
+        service.list_exchanges().await.expect("list");
+        assert_eq!(service.exchange_stats(), CacheStats { hits: 0, misses: 2 });
+
+        let mut inner = MockStockService::new();
+        inner
+            .expect_find_currency()
+            .times(3)
+            .returning(|code| Ok(Some(currency(code, code))));
+        let service = CachedStockService::new(inner, &settings(60, 1));
+        service.find_currency("EUR").await.expect("find");
+        service.find_currency("USD").await.expect("find");
+        service.find_currency("EUR").await.expect("find");
+        service.find_currency("EUR").await.expect("find");
+        assert_eq!(service.currency_stats(), CacheStats { hits: 1, misses: 3 });
+
+        // A list longer than the store may hold is not kept.
+        let mut inner = MockStockService::new();
+        inner
+            .expect_list_currencies()
+            .times(2)
+            .returning(|| Ok(vec![currency("EUR", "Euro"), currency("USD", "US Dollar")]));
+        let service = CachedStockService::new(inner, &settings(60, 1));
+        service.list_currencies().await.expect("list");
+        service.list_currencies().await.expect("list");
+        assert_eq!(service.currency_stats(), CacheStats { hits: 0, misses: 2 });
+    }
+}
@@ -1,10 +1,51 @@
 // use juniper::{graphql_value, FieldError, IntoFieldError};
 // use async_graphql::{ErrorExtensions, FieldError, FieldResult, Object, ResultExt};
-use async_graphql::{ErrorExtensions, FieldError};
+use async_graphql::{value, ErrorExtensions, FieldError};
 use snafu::Snafu;
+use std::sync::atomic::{AtomicBool, Ordering};
 
+use super::metrics;
+use super::validate::Violation;
 use crate::db::model::ProvideError;
 
+/// Whether the messages of internal errors reach the clients, see `set_debug`.
+static DEBUG: AtomicBool = AtomicBool::new(false);
+
+/// Let the messages of internal errors, eg from sqlx, reach the clients. They are replaced
+/// by a generic message otherwise, which is the default.
+pub fn set_debug(debug: bool) {
+    DEBUG.store(debug, Ordering::Relaxed);
+}
+
+/// Whether the details of errors reach the clients, see `set_debug`.
+pub fn debug() -> bool {
+    DEBUG.load(Ordering::Relaxed)
+}
+
+/// The kind of an error, in the `code` extension. Clients can rely on these.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Code {
+    NotFound,
+    AlreadyExists,
+    ValidationFailed,
+    Forbidden,
+    Unavailable,
+    Internal,
+}
+
+impl Code {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Code::NotFound => "NOT_FOUND",
+            Code::AlreadyExists => "ALREADY_EXISTS",
+            Code::ValidationFailed => "VALIDATION_FAILED",
+            Code::Forbidden => "FORBIDDEN",
+            Code::Unavailable => "UNAVAILABLE",
+            Code::Internal => "INTERNAL",
+        }
+    }
+}
+
 #[derive(Debug, Snafu)]
 pub enum Error {
     #[snafu(display("DB Error: {} - {}", msg, source))]
@@ -18,16 +59,140 @@ pub enum Error {
     #[snafu(display("DB Provide Error: {} - {}", msg, source))]
     #[snafu(visibility(pub))]
     DBProvideError { msg: String, source: ProvideError },
+
+    #[snafu(display("Invalid input: {}", violations.iter().map(|violation| format!("{} {}", violation.field, violation.message)).collect::<Vec<_>>().join(", ")))]
+    #[snafu(visibility(pub))]
+    ValidationError { violations: Vec<Violation> },
+}
+
+impl Error {
+    pub fn code(&self) -> Code {
+        match self {
+            Error::DBConnectionError { .. } => Code::Unavailable,
+            Error::DBTransactionError { .. } => Code::Internal,
+            Error::DBProvideError { source, .. } => match source {
+                ProvideError::NotFound => Code::NotFound,
+                ProvideError::UniqueViolation { .. } => Code::AlreadyExists,
+                ProvideError::ModelViolation { .. } => Code::ValidationFailed,
+                ProvideError::ReferenceViolation { .. } => Code::ValidationFailed,
+                ProvideError::UnHandledError { .. } => Code::Internal,
+            },
+            Error::ValidationError { .. } => Code::ValidationFailed,
+        }
+    }
+
+    fn reason(&self) -> &str {
+        match self {
+            Error::DBConnectionError { msg, .. } => msg,
+            Error::DBTransactionError { msg, .. } => msg,
+            Error::DBProvideError { msg, .. } => msg,
+            Error::ValidationError { .. } => "Invalid input",
+        }
+    }
+
+    /// The offending fields, in the GraphQL spelling, and what is wrong with them.
+    fn fields(&self, debug: bool) -> Vec<(String, String)> {
+        match self {
+            Error::ValidationError { violations } => violations
+                .iter()
+                .map(|violation| (violation.field.clone(), violation.message.clone()))
+                .collect(),
+            Error::DBProvideError { source, .. } => match violation(source, debug) {
+                Some((Some(field), message)) => vec![(camel_case(field), message)],
+                _ => Vec::new(),
+            },
+            _ => Vec::new(),
+        }
+    }
+
+    /// The message clients get, without the database details unless debugging.
+    pub fn message(&self) -> String {
+        self.to_field_error(debug()).message
+    }
+
+    fn to_field_error(&self, debug: bool) -> FieldError {
+        let code = self.code();
+        let reason = self.reason().to_owned();
+        let fields = self.fields(debug);
+        // The sources of errors carry messages from the database, only the context and the
+        // offending fields are kept.
+        let message = if debug {
+            self.to_string()
+        } else if fields.is_empty() {
+            reason.clone()
+        } else {
+            let fields = fields
+                .iter()
+                .map(|(field, message)| format!("{} {}", field, message))
+                .collect::<Vec<_>>();
+            format!("{}: {}", reason, fields.join(", "))
+        };
+        FieldError::new(message).extend_with(|_, e| {
+            e.set("code", code.as_str());
+            e.set("reason", reason);
+            if !fields.is_empty() {
+                let fields = fields
+                    .into_iter()
+                    .map(|(field, message)| value!({ "field": field, "message": message }))
+                    .collect::<Vec<_>>();
+                e.set("fields", fields);
+            }
+        })
+    }
+}
+
+/// The column of a violation, and what is wrong with it: the details from the database when
+/// debugging, a phrase for the kind of violation otherwise.
+fn violation(err: &ProvideError, debug: bool) -> Option<(Option<&str>, String)> {
+    let (details, field, phrase) = match err {
+        ProvideError::UniqueViolation { details, field } => (details, field, "already exists"),
+        ProvideError::ModelViolation { details, field } => (details, field, "is invalid"),
+        ProvideError::ReferenceViolation { details, field } => {
+            (details, field, "is referenced by other records")
+        }
+        _ => return None,
+    };
+    let message = if debug {
+        details.clone()
+    } else {
+        String::from(phrase)
+    };
+    Some((field.as_deref(), message))
+}
+
+/// What is wrong with a row the store refused, eg `code already exists`, with the details
+/// from the database only when debugging.
+pub fn violation_message(err: &ProvideError) -> Option<String> {
+    let debug = debug();
+    violation(err, debug).map(|(field, message)| match field {
+        Some(field) if !debug => format!("{} {}", field, message),
+        None if !debug => format!("row {}", message),
+        _ => message,
+    })
+}
+
+/// `asset_class` as `assetClass`, the spelling of the GraphQL fields.
+fn camel_case(name: &str) -> String {
+    let mut words = name.split('_');
+    let first = words.next().unwrap_or_default().to_owned();
+    words.fold(first, |mut name, word| {
+        let mut chars = word.chars();
+        if let Some(c) = chars.next() {
+            name.extend(c.to_uppercase());
+            name.push_str(chars.as_str());
+        }
+        name
+    })
 }
 
 impl ErrorExtensions for Error {
     // lets define our base extensions
     fn extend(&self) -> FieldError {
-        self.extend_with(|err, e| match err {
-            Error::DBConnectionError { msg, .. } => e.set("reason", msg.clone()),
-            Error::DBTransactionError { msg, .. } => e.set("reason", msg.clone()),
-            Error::DBProvideError { msg, .. } => e.set("reason", msg.clone()),
-        })
+        // Errors reach the clients through here, which makes it the place to count them.
+        if let Error::DBProvideError { source, .. } = self {
+            metrics::record_provide_error(source);
+        }
+        self.to_field_error(debug())
     }
 }
 
@@ -58,3 +223,101 @@ impl ErrorExtensions for Error {
 //         }
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_errors_have_a_code_and_fields() {
+        let duplicate = Error::DBProvideError {
+            msg: String::from("Could not add currency"),
+            source: ProvideError::UniqueViolation {
+                details: String::from("Key (code)=(EUR) already exists."),
+                field: Some(String::from("code")),
+            },
+        };
+        let invalid = Error::DBProvideError {
+            msg: String::from("Could not add security"),
+            source: ProvideError::ModelViolation {
+                details: String::from("Unknown asset class 'stock'"),
+                field: Some(String::from("asset_class")),
+            },
+        };
+
+        let referenced = Error::DBProvideError {
+            msg: String::from("Could not delete currency"),
+            source: ProvideError::ReferenceViolation {
+                details: String::from(
+                    "Key (code)=(EUR) is still referenced from table \"securities\".",
+                ),
+                field: Some(String::from("code")),
+            },
+        };
+
+        let err = duplicate.to_field_error(false);
+        assert_eq!(err.message, "Could not add currency: code already exists");
+        let err = serde_json::to_value(err.extensions).expect("json");
+        assert_eq!(err["code"], "ALREADY_EXISTS");
+        assert_eq!(err["reason"], "Could not add currency");
+        assert_eq!(err["fields"][0]["field"], "code");
+        assert_eq!(err["fields"][0]["message"], "already exists");
+
+        let err = serde_json::to_value(invalid.to_field_error(false).extensions).expect("json");
+        assert_eq!(err["code"], "VALIDATION_FAILED");
+        assert_eq!(err["fields"][0]["field"], "assetClass");
+        assert_eq!(err["fields"][0]["message"], "is invalid");
+
+        let err = referenced.to_field_error(false);
+        assert_eq!(
+            err.message,
+            "Could not delete currency: code is referenced by other records"
+        );
+
+        // The details from the database are for debugging only.
+        let err = serde_json::to_value(duplicate.to_field_error(true).extensions).expect("json");
+        assert_eq!(
+            err["fields"][0]["message"],
+            "Key (code)=(EUR) already exists."
+        );
+    }
+
+    #[test]
+    fn test_internal_messages_are_hidden_unless_debugging() {
+        let err = Error::DBConnectionError {
+            msg: String::from("could not acquire connection"),
+            source: sqlx::Error::PoolTimedOut,
+        };
+
+        let hidden = err.to_field_error(false);
+        let shown = err.to_field_error(true);
+
+        assert_eq!(hidden.message, "could not acquire connection");
+        assert!(shown
+            .message
+            .contains(&sqlx::Error::PoolTimedOut.to_string()));
+        assert_eq!(
+            serde_json::to_value(hidden.extensions).expect("json")["code"],
+            "UNAVAILABLE"
+        );
+    }
+
+    #[test]
+    fn test_database_messages_are_hidden_unless_debugging() {
+        let err = Error::DBProvideError {
+            msg: String::from("Could not delete currency"),
+            source: ProvideError::ModelViolation {
+                details: String::from(
+                    "update or delete on table \"currencies\" violates foreign key constraint",
+                ),
+                field: None,
+            },
+        };
+
+        assert_eq!(
+            err.to_field_error(false).message,
+            "Could not delete currency"
+        );
+        assert!(err.to_field_error(true).message.contains("foreign key"));
+    }
+}
@@ -0,0 +1,194 @@
+use chrono::NaiveDate;
+
+use crate::db::model::{FxRateEntity, ProvideResult, ProvideStock};
+
+/// The currency rates are crossed through when none is given.
+pub const DEFAULT_PIVOT: &str = "USD";
+
+/// Find the rate of a currency pair on or before the date.
+///
+/// The stored rate of the pair and the inverse of the reverse pair are considered first,
+/// keeping the most recent. Failing that, the rate is crossed through the pivot currency.
+/// The date of a crossed rate is the oldest of the two rates it is derived from.
+pub async fn find_rate<P>(
+    provider: &mut P,
+    base: &str,
+    quote: &str,
+    date: NaiveDate,
+    pivot: &str,
+) -> ProvideResult<Option<FxRateEntity>>
+where
+    P: ProvideStock + Send,
+{
+    if base == quote {
+        return Ok(Some(FxRateEntity {
+            base: String::from(base),
+            quote: String::from(quote),
+            date,
+            rate: 1.0,
+        }));
+    }
+
+    if let Some(rate) = find_direct_rate(provider, base, quote, date).await? {
+        return Ok(Some(rate));
+    }
+
+    if base == pivot || quote == pivot {
+        return Ok(None);
+    }
+
+    let base_pivot = find_direct_rate(provider, base, pivot, date).await?;
+    let pivot_quote = find_direct_rate(provider, pivot, quote, date).await?;
+
+    Ok(base_pivot
+        .zip(pivot_quote)
+        .map(|(first, second)| FxRateEntity {
+            base: String::from(base),
+            quote: String::from(quote),
+            date: first.date.min(second.date),
+            rate: first.rate * second.rate,
+        }))
+}
+
+/// Find the rate of a pair, either as stored or as the inverse of the reverse pair.
+async fn find_direct_rate<P>(
+    provider: &mut P,
+    base: &str,
+    quote: &str,
+    date: NaiveDate,
+) -> ProvideResult<Option<FxRateEntity>>
+where
+    P: ProvideStock + Send,
+{
+    let direct = provider.find_fx_rate(base, quote, date).await?;
+    let inverse = provider
+        .find_fx_rate(quote, base, date)
+        .await?
+        .map(|rate| FxRateEntity {
+            base: String::from(base),
+            quote: String::from(quote),
+            date: rate.date,
+            rate: 1.0 / rate.rate,
+        });
+
+    Ok(match (direct, inverse) {
+        (Some(direct), Some(inverse)) if inverse.date > direct.date => Some(inverse),
+        (Some(direct), _) => Some(direct),
+        (None, inverse) => inverse,
+    })
+}
+
+/// Round an amount to the given number of decimals.
+pub fn round(amount: f64, decimals: i32) -> f64 {
+    let factor = 10f64.powi(decimals);
+    (amount * factor).round() / factor
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::model::MockProvideStock;
+
+    fn provider(rates: Vec<FxRateEntity>) -> MockProvideStock {
+        let mut provider = MockProvideStock::new();
+        provider
+            .expect_find_fx_rate()
+            .returning(move |base, quote, date| {
+                Ok(rates
+                    .iter()
+                    .filter(|rate| rate.base == base && rate.quote == quote && rate.date <= date)
+                    .max_by_key(|rate| rate.date)
+                    .cloned())
+            });
+        provider
+    }
+
+    fn rate(base: &str, quote: &str, day: u32, rate: f64) -> FxRateEntity {
+        FxRateEntity {
+            base: String::from(base),
+            quote: String::from(quote),
+            date: NaiveDate::from_ymd_opt(2021, 3, day).unwrap(),
+            rate,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_inverse_rate() {
+        let mut provider = provider(vec![rate("EUR", "USD", 1, 1.25)]);
+
+        let found = find_rate(
+            &mut provider,
+            "USD",
+            "EUR",
+            NaiveDate::from_ymd_opt(2021, 3, 4).unwrap(),
+            "USD",
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(found.rate, 0.8);
+        assert_eq!(found.date, NaiveDate::from_ymd_opt(2021, 3, 1).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_latest_of_direct_and_inverse_rate() {
+        let mut provider = provider(vec![
+            rate("EUR", "USD", 1, 1.25),
+            rate("USD", "EUR", 3, 0.5),
+        ]);
+
+        let found = find_rate(
+            &mut provider,
+            "EUR",
+            "USD",
+            NaiveDate::from_ymd_opt(2021, 3, 4).unwrap(),
+            "USD",
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(found.rate, 2.0);
+    }
+
+    #[tokio::test]
+    async fn test_cross_rate() {
+        let mut provider = provider(vec![
+            rate("EUR", "USD", 2, 1.25),
+            rate("USD", "JPY", 1, 100.0),
+        ]);
+
+        let found = find_rate(
+            &mut provider,
+            "EUR",
+            "JPY",
+            NaiveDate::from_ymd_opt(2021, 3, 4).unwrap(),
+            "USD",
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(found.rate, 125.0);
+        assert_eq!(found.date, NaiveDate::from_ymd_opt(2021, 3, 1).unwrap());
+
+        let found = find_rate(
+            &mut provider,
+            "EUR",
+            "GBP",
+            NaiveDate::from_ymd_opt(2021, 3, 4).unwrap(),
+            "USD",
+        )
+        .await
+        .unwrap();
+
+        assert!(found.is_none());
+    }
+
+    #[test]
+    fn test_round() {
+        assert_eq!(round(12.3456, 2), 12.35);
+        assert_eq!(round(1234.5, 0), 1235.0);
+    }
+}
@@ -1,14 +1,27 @@
+use async_graphql::connection::{Connection, CursorType, Edge};
 use async_graphql::extensions::Tracing;
+use async_graphql::guard::Guard;
 use async_graphql::*;
+use futures::future;
+use futures::stream::{Stream, StreamExt};
+use std::sync::Arc;
 use tracing::instrument;
 // use uuid::Uuid;
 
-use crate::api::model::{self, StockService};
+use crate::api::auth::{Permission, PermissionGuard, Principal};
+use crate::api::bus::{Bus, Event};
+use crate::api::import;
+use crate::api::loader::Loaders;
+use crate::api::metrics;
+use crate::api::model;
+use crate::api::validate;
+use crate::db::model as db;
 
 pub struct Query;
 
 #[Object]
 impl Query {
+    #[graphql(guard(PermissionGuard(permission = "Permission::Read")))]
     async fn list_currencies(&self, context: &Context<'_>) -> FieldResult<Vec<model::Currency>> {
         // let request_id = Uuid::new_v4();
         // let request_span = tracing::info_span!(
@@ -18,7 +31,94 @@ impl Query {
         let service = get_service_from_context(context)?;
         service.list_currencies().await.map_err(|e| e.extend())
     }
+    /// Currencies matching the filter, a page at a time. Pages are requested with
+    /// `first`/`after` going forward, or `last`/`before` going backward.
+    #[allow(clippy::too_many_arguments)]
     #[instrument(skip(self, context))]
+    #[graphql(guard(PermissionGuard(permission = "Permission::Read")))]
+    async fn currencies(
+        &self,
+        context: &Context<'_>,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+        #[graphql(default, desc = "Number of currencies skipped past the cursor")] offset: i32,
+        filter: Option<CurrencyFilter>,
+        sort: Option<CurrencySort>,
+    ) -> FieldResult<Connection<db::Keyset, model::Currency, ConnectionFields>> {
+        let service = get_service_from_context(context)?;
+        let filter = filter.map(db::CurrencyFilter::from).unwrap_or_default();
+        let sort = sort.unwrap_or_default();
+        let key = db::CurrencySortKey::from(sort.field);
+        let descending = sort.direction == SortDirection::Desc;
+        if offset < 0 {
+            return Err(FieldError::new(
+                "The \"offset\" parameter must be a non-negative number",
+            ));
+        }
+        if first.is_some() && last.is_some() {
+            return Err(FieldError::new(
+                "The \"first\" and \"last\" parameters cannot exist at the same time",
+            ));
+        }
+        if first.or(last).is_some_and(|size| size < 0) {
+            return Err(FieldError::new(
+                "The \"first\" and \"last\" parameters must be non-negative numbers",
+            ));
+        }
+        // Cursors are only valid in the order of the page they come from.
+        let (after, before) = validate::currency_cursors(
+            after.as_deref().map(db::Keyset::decode_cursor),
+            before.as_deref().map(db::Keyset::decode_cursor),
+            key,
+            descending,
+        )
+        .map_err(|e| e.extend())?;
+
+        let backward = last.is_some();
+        let size = last
+            .or(first)
+            .map_or(DEFAULT_PAGE_SIZE, |size| size as usize)
+            .min(MAX_PAGE_SIZE);
+        let page = db::Pagination {
+            after: after.clone(),
+            before: before.clone(),
+            backward,
+            limit: size as i64 + 1,
+            offset: offset as i64,
+        };
+        let model::Page {
+            mut items,
+            total_count,
+        } = service
+            .search_currencies(filter, key, descending, page)
+            .await
+            .map_err(|e| e.extend())?;
+        // One more currency than requested tells if there is another page.
+        let more = items.len() > size;
+        items.truncate(size);
+        let (has_previous, has_next) = if backward {
+            items.reverse();
+            (more, before.is_some())
+        } else {
+            (after.is_some() || offset > 0, more)
+        };
+        let mut connection = Connection::with_additional_fields(
+            has_previous,
+            has_next,
+            ConnectionFields { total_count },
+        );
+        connection.append(
+            items
+                .into_iter()
+                .map(|currency| Edge::new(currency_keyset(&currency, key, descending), currency)),
+        );
+        Ok(connection)
+    }
+
+    #[instrument(skip(self, context))]
+    #[graphql(guard(PermissionGuard(permission = "Permission::Read")))]
     async fn find_currency(
         &self,
         context: &Context<'_>,
@@ -33,6 +133,156 @@ impl Query {
         let service = get_service_from_context(context)?;
         service.find_currency(&code).await.map_err(|e| e.extend())
     }
+
+    /// All the securities, in one list: unlike currencies, they are not paged yet.
+    #[instrument(skip(self, context))]
+    #[graphql(guard(PermissionGuard(permission = "Permission::Read")))]
+    async fn list_securities(&self, context: &Context<'_>) -> FieldResult<Vec<model::Security>> {
+        let service = get_service_from_context(context)?;
+        service.list_securities().await.map_err(|e| e.extend())
+    }
+
+    #[instrument(skip(self, context))]
+    #[graphql(guard(PermissionGuard(permission = "Permission::Read")))]
+    async fn find_security_by_ticker(
+        &self,
+        context: &Context<'_>,
+        ticker: String,
+    ) -> FieldResult<Option<model::Security>> {
+        let service = get_service_from_context(context)?;
+        service
+            .find_security_by_ticker(&ticker)
+            .await
+            .map_err(|e| e.extend())
+    }
+
+    #[instrument(skip(self, context))]
+    #[graphql(guard(PermissionGuard(permission = "Permission::Read")))]
+    async fn find_security_by_isin(
+        &self,
+        context: &Context<'_>,
+        isin: String,
+    ) -> FieldResult<Option<model::Security>> {
+        let service = get_service_from_context(context)?;
+        service
+            .find_security_by_isin(&isin)
+            .await
+            .map_err(|e| e.extend())
+    }
+
+    /// All the exchanges, in one list: unlike currencies, they are not paged yet.
+    #[instrument(skip(self, context))]
+    #[graphql(guard(PermissionGuard(permission = "Permission::Read")))]
+    async fn list_exchanges(&self, context: &Context<'_>) -> FieldResult<Vec<model::Exchange>> {
+        let service = get_service_from_context(context)?;
+        service.list_exchanges().await.map_err(|e| e.extend())
+    }
+
+    #[instrument(skip(self, context))]
+    #[graphql(guard(PermissionGuard(permission = "Permission::Read")))]
+    async fn find_exchange(
+        &self,
+        context: &Context<'_>,
+        mic: String,
+    ) -> FieldResult<Option<model::Exchange>> {
+        let service = get_service_from_context(context)?;
+        service.find_exchange(&mic).await.map_err(|e| e.extend())
+    }
+
+    /// The price bars of a security between two dates (inclusive)
+    #[instrument(skip(self, context))]
+    #[graphql(guard(PermissionGuard(permission = "Permission::Read")))]
+    async fn price_history(
+        &self,
+        context: &Context<'_>,
+        ticker: String,
+        from: chrono::NaiveDate,
+        to: chrono::NaiveDate,
+        #[graphql(default)] interval: model::Interval,
+    ) -> FieldResult<Vec<model::PriceBar>> {
+        let service = get_service_from_context(context)?;
+        service
+            .price_history(&ticker, from, to, interval)
+            .await
+            .map_err(|e| e.extend())
+    }
+
+    /// The rate of a currency pair on the date, or the latest one before. Rates missing from
+    /// the store are derived from the inverse pair, or crossed through the pivot currency.
+    #[instrument(skip(self, context))]
+    #[graphql(guard(PermissionGuard(permission = "Permission::Read")))]
+    async fn fx_rate(
+        &self,
+        context: &Context<'_>,
+        base: String,
+        quote: String,
+        date: chrono::NaiveDate,
+        #[graphql(default = "USD")] pivot: String,
+    ) -> FieldResult<Option<model::FxRate>> {
+        let service = get_service_from_context(context)?;
+        service
+            .fx_rate(&base, &quote, date, &pivot)
+            .await
+            .map_err(|e| e.extend())
+    }
+
+    /// Convert an amount between currencies, rounded to the decimals of the target currency.
+    #[instrument(skip(self, context))]
+    #[graphql(guard(PermissionGuard(permission = "Permission::Read")))]
+    async fn convert(
+        &self,
+        context: &Context<'_>,
+        amount: f64,
+        from: String,
+        to: String,
+        date: chrono::NaiveDate,
+        #[graphql(default = "USD")] pivot: String,
+    ) -> FieldResult<Option<f64>> {
+        let service = get_service_from_context(context)?;
+        service
+            .convert(amount, &from, &to, date, &pivot)
+            .await
+            .map_err(|e| e.extend())
+    }
+
+    /// All the portfolios, in one list: unlike currencies, they are not paged yet.
+    #[instrument(skip(self, context))]
+    #[graphql(guard(PermissionGuard(permission = "Permission::Read")))]
+    async fn list_portfolios(&self, context: &Context<'_>) -> FieldResult<Vec<model::Portfolio>> {
+        let service = get_service_from_context(context)?;
+        service.list_portfolios().await.map_err(|e| e.extend())
+    }
+
+    #[instrument(skip(self, context))]
+    #[graphql(guard(PermissionGuard(permission = "Permission::Read")))]
+    async fn find_portfolio(
+        &self,
+        context: &Context<'_>,
+        id: uuid::Uuid,
+    ) -> FieldResult<Option<model::Portfolio>> {
+        let service = get_service_from_context(context)?;
+        service.find_portfolio(id).await.map_err(|e| e.extend())
+    }
+
+    /// The open positions of a portfolio, derived from its ledger
+    #[instrument(skip(self, context))]
+    #[graphql(guard(PermissionGuard(permission = "Permission::Read")))]
+    async fn positions(
+        &self,
+        context: &Context<'_>,
+        portfolio_id: uuid::Uuid,
+    ) -> FieldResult<Vec<model::Position>> {
+        let service = get_service_from_context(context)?;
+        service
+            .positions(portfolio_id)
+            .await
+            .map_err(|e| e.extend())
+    }
+
+    /// The identity of the caller
+    async fn whoami(&self, context: &Context<'_>) -> Principal {
+        get_principal_from_context(context)
+    }
 }
 
 pub struct Mutation;
@@ -40,6 +290,7 @@ pub struct Mutation;
 #[Object]
 impl Mutation {
     #[instrument(skip(self, context))]
+    #[graphql(guard(PermissionGuard(permission = "Permission::Admin")))]
     async fn add_currency(
         &self,
         context: &Context<'_>,
@@ -47,30 +298,458 @@ impl Mutation {
     ) -> FieldResult<model::Currency> {
         //info!("Request for adding a currency");
         //let service: &imp::StockServiceImpl = get_service_from_context(context)?;
+        validate::add_currency(&currency.code, &currency.name, currency.decimals)
+            .map_err(|e| e.extend())?;
         let service = get_service_from_context(context)?;
         service
             .add_currency(&currency.code, &currency.name, currency.decimals)
             .await
             .map_err(|e| e.extend())
     }
+
+    #[instrument(skip(self, context))]
+    #[graphql(guard(PermissionGuard(permission = "Permission::Admin")))]
+    async fn update_currency(
+        &self,
+        context: &Context<'_>,
+        code: String,
+        patch: CurrencyPatch,
+    ) -> FieldResult<model::Currency> {
+        validate::update_currency(&code, patch.name.as_deref(), patch.decimals)
+            .map_err(|e| e.extend())?;
+        let service = get_service_from_context(context)?;
+        service
+            .update_currency(&code, patch.name, patch.decimals)
+            .await
+            .map_err(|e| e.extend())
+    }
+
+    #[instrument(skip(self, context))]
+    #[graphql(guard(PermissionGuard(permission = "Permission::Admin")))]
+    async fn delete_currency(
+        &self,
+        context: &Context<'_>,
+        code: String,
+    ) -> FieldResult<model::Currency> {
+        let service = get_service_from_context(context)?;
+        service.delete_currency(&code).await.map_err(|e| e.extend())
+    }
+
+    /// Import currencies from a CSV file (`code,name,decimals` header) or an NDJSON file
+    /// (`.ndjson`, `.jsonl` or `.json`) of at most 16 MiB, with what became of each row.
+    #[instrument(skip(self, context, file))]
+    #[graphql(guard(PermissionGuard(permission = "Permission::Admin")))]
+    async fn import_currencies(
+        &self,
+        context: &Context<'_>,
+        file: Upload,
+        #[graphql(default)] on_conflict: import::OnConflict,
+    ) -> FieldResult<import::ImportReport> {
+        let upload = file.value(context)?;
+        let format = import::Format::from_file_name(&upload.filename);
+        // The upload waits in a temporary file, read off the async runtime.
+        let read = upload.into_read();
+        let data =
+            tokio::task::spawn_blocking(move || import::read_file(read, import::MAX_UPLOAD_SIZE))
+                .await?
+                .map_err(|e| e.extend())?;
+        let service = get_service_from_context(context)?;
+        service
+            .import_currencies(data, format, on_conflict)
+            .await
+            .map_err(|e| e.extend())
+    }
+
+    #[instrument(skip(self, context))]
+    #[graphql(guard(PermissionGuard(permission = "Permission::Admin")))]
+    async fn add_security(
+        &self,
+        context: &Context<'_>,
+        security: SecurityInput,
+    ) -> FieldResult<model::Security> {
+        validate::add_security(
+            &security.ticker,
+            &security.isin,
+            &security.name,
+            &security.exchange,
+            &security.currency,
+        )
+        .map_err(|e| e.extend())?;
+        let service = get_service_from_context(context)?;
+        service
+            .add_security(
+                &security.ticker,
+                &security.isin,
+                &security.name,
+                &security.exchange,
+                &security.currency,
+                security.asset_class,
+            )
+            .await
+            .map_err(|e| e.extend())
+    }
+
+    #[instrument(skip(self, context))]
+    #[graphql(guard(PermissionGuard(permission = "Permission::Admin")))]
+    async fn add_exchange(
+        &self,
+        context: &Context<'_>,
+        exchange: ExchangeInput,
+    ) -> FieldResult<model::Exchange> {
+        let trading_hours = exchange.trading_hours.into();
+        validate::add_exchange(
+            &exchange.mic,
+            &exchange.name,
+            &exchange.country,
+            &exchange.timezone,
+            &trading_hours,
+            &exchange.currency,
+        )
+        .map_err(|e| e.extend())?;
+        let service = get_service_from_context(context)?;
+        service
+            .add_exchange(
+                &exchange.mic,
+                &exchange.name,
+                &exchange.country,
+                &exchange.timezone,
+                trading_hours,
+                &exchange.currency,
+            )
+            .await
+            .map_err(|e| e.extend())
+    }
+
+    #[instrument(skip(self, context))]
+    #[graphql(guard(PermissionGuard(permission = "Permission::Admin")))]
+    async fn update_exchange(
+        &self,
+        context: &Context<'_>,
+        mic: String,
+        patch: ExchangePatch,
+    ) -> FieldResult<model::Exchange> {
+        let trading_hours = patch.trading_hours.map(model::TradingHours::from);
+        validate::update_exchange(
+            &mic,
+            patch.name.as_deref(),
+            patch.country.as_deref(),
+            patch.timezone.as_deref(),
+            trading_hours.as_ref(),
+            patch.currency.as_deref(),
+        )
+        .map_err(|e| e.extend())?;
+        let service = get_service_from_context(context)?;
+        service
+            .update_exchange(
+                &mic,
+                patch.name,
+                patch.country,
+                patch.timezone,
+                trading_hours,
+                patch.currency,
+            )
+            .await
+            .map_err(|e| e.extend())
+    }
+
+    #[instrument(skip(self, context))]
+    #[graphql(guard(PermissionGuard(permission = "Permission::Admin")))]
+    async fn delete_exchange(
+        &self,
+        context: &Context<'_>,
+        mic: String,
+    ) -> FieldResult<model::Exchange> {
+        let service = get_service_from_context(context)?;
+        service.delete_exchange(&mic).await.map_err(|e| e.extend())
+    }
+
+    /// Store daily bars for a security, returning the number of bars stored.
+    /// Existing bars for the same dates are replaced.
+    #[instrument(skip(self, context, bars))]
+    #[graphql(guard(PermissionGuard(permission = "Permission::Write")))]
+    async fn add_price_bars(
+        &self,
+        context: &Context<'_>,
+        ticker: String,
+        bars: Vec<PriceBarInput>,
+    ) -> FieldResult<u64> {
+        let bars = bars
+            .into_iter()
+            .map(model::PriceBar::from)
+            .collect::<Vec<_>>();
+        validate::add_price_bars(&ticker, &bars).map_err(|e| e.extend())?;
+        let service = get_service_from_context(context)?;
+        service
+            .add_price_bars(&ticker, bars)
+            .await
+            .map_err(|e| e.extend())
+    }
+
+    #[instrument(skip(self, context))]
+    #[graphql(guard(PermissionGuard(permission = "Permission::Write")))]
+    async fn add_fx_rate(
+        &self,
+        context: &Context<'_>,
+        rate: FxRateInput,
+    ) -> FieldResult<model::FxRate> {
+        validate::add_fx_rate(&rate.base, &rate.quote, rate.rate).map_err(|e| e.extend())?;
+        let service = get_service_from_context(context)?;
+        service
+            .add_fx_rate(&rate.base, &rate.quote, rate.date, rate.rate)
+            .await
+            .map_err(|e| e.extend())
+    }
+
+    #[instrument(skip(self, context))]
+    #[graphql(guard(PermissionGuard(permission = "Permission::Write")))]
+    async fn add_portfolio(
+        &self,
+        context: &Context<'_>,
+        portfolio: PortfolioInput,
+    ) -> FieldResult<model::Portfolio> {
+        validate::add_portfolio(&portfolio.name, &portfolio.currency).map_err(|e| e.extend())?;
+        let service = get_service_from_context(context)?;
+        service
+            .add_portfolio(&portfolio.name, &portfolio.currency)
+            .await
+            .map_err(|e| e.extend())
+    }
+
+    #[instrument(skip(self, context))]
+    #[graphql(guard(PermissionGuard(permission = "Permission::Write")))]
+    async fn add_transaction(
+        &self,
+        context: &Context<'_>,
+        portfolio_id: uuid::Uuid,
+        transaction: TransactionInput,
+    ) -> FieldResult<model::Transaction> {
+        let transaction = model::NewTransaction::from(transaction);
+        validate::add_transaction(&transaction).map_err(|e| e.extend())?;
+        let service = get_service_from_context(context)?;
+        service
+            .add_transaction(portfolio_id, transaction)
+            .await
+            .map_err(|e| e.extend())
+    }
+
+    #[instrument(skip(self, context))]
+    #[graphql(guard(PermissionGuard(permission = "Permission::Write")))]
+    async fn delete_transaction(
+        &self,
+        context: &Context<'_>,
+        id: uuid::Uuid,
+    ) -> FieldResult<model::Transaction> {
+        let service = get_service_from_context(context)?;
+        service.delete_transaction(id).await.map_err(|e| e.extend())
+    }
+}
+
+pub struct Subscription;
+
+#[Subscription]
+impl Subscription {
+    /// Bars stored for the given securities, or for all securities if none is given
+    #[graphql(guard(PermissionGuard(permission = "Permission::Read")))]
+    async fn price_updated(
+        &self,
+        context: &Context<'_>,
+        #[graphql(default)] tickers: Vec<String>,
+    ) -> FieldResult<impl Stream<Item = model::PriceUpdate>> {
+        let bus = get_bus_from_context(context)?;
+        Ok(bus.subscribe().filter_map(move |event| {
+            let update = match event {
+                Event::PriceUpdated(update)
+                    if tickers.is_empty() || tickers.contains(&update.ticker) =>
+                {
+                    Some(update)
+                }
+                _ => None,
+            };
+            future::ready(update)
+        }))
+    }
+
+    #[graphql(guard(PermissionGuard(permission = "Permission::Read")))]
+    async fn currency_changed(
+        &self,
+        context: &Context<'_>,
+    ) -> FieldResult<impl Stream<Item = model::CurrencyChange>> {
+        let bus = get_bus_from_context(context)?;
+        Ok(bus.subscribe().filter_map(|event| {
+            let change = match event {
+                Event::CurrencyChanged(change) => Some(change),
+                _ => None,
+            };
+            future::ready(change)
+        }))
+    }
+
+    /// Transactions added to the ledger of a portfolio
+    #[graphql(guard(PermissionGuard(permission = "Permission::Read")))]
+    async fn transaction_recorded(
+        &self,
+        context: &Context<'_>,
+        portfolio_id: uuid::Uuid,
+    ) -> FieldResult<impl Stream<Item = model::Transaction>> {
+        let bus = get_bus_from_context(context)?;
+        Ok(bus.subscribe().filter_map(move |event| {
+            let transaction = match event {
+                Event::TransactionRecorded(transaction)
+                    if transaction.portfolio_id == portfolio_id =>
+                {
+                    Some(transaction)
+                }
+                _ => None,
+            };
+            future::ready(transaction)
+        }))
+    }
 }
 
-pub type StocksSchema = Schema<Query, Mutation, EmptySubscription>;
+pub type StocksSchema = Schema<Query, Mutation, Subscription>;
 
-pub fn schema(service: Box<dyn StockService + Send + Sync>) -> StocksSchema {
-    Schema::build(Query, Mutation, EmptySubscription)
-        .extension(Tracing::default())
+/// The loaders of the schema do not reuse values, requests can bring their own with
+/// `Request::data(loaders(service))`.
+pub fn schema(service: model::Service, bus: Bus) -> StocksSchema {
+    let loaders = Loaders::new(service.clone());
+    loaders.disable_caching();
+    Schema::build(Query, Mutation, Subscription)
+        .extension(Tracing)
+        .extension(metrics::Metrics)
+        .data(Arc::new(loaders))
         .data(service)
+        .data(bus)
         .finish()
 }
 
-#[allow(clippy::borrowed_box)]
+/// Loaders for a single request, reusing the values they load.
+pub fn loaders(service: model::Service) -> Arc<Loaders> {
+    Arc::new(Loaders::new(service))
+}
+
 pub fn get_service_from_context<'ctx>(
     context: &'ctx Context,
-) -> Result<&'ctx Box<dyn StockService + Send + Sync>, async_graphql::Error>
-where
-{
-    context.data::<Box<dyn StockService + Send + Sync>>()
+) -> Result<&'ctx model::Service, async_graphql::Error> {
+    context.data::<model::Service>()
+}
+
+/// The loaders of the request if it has its own, otherwise those of the schema.
+pub fn get_loaders_from_context<'ctx>(
+    context: &'ctx Context,
+) -> Result<&'ctx Arc<Loaders>, async_graphql::Error> {
+    context.data::<Arc<Loaders>>()
+}
+
+pub fn get_bus_from_context<'ctx>(
+    context: &'ctx Context,
+) -> Result<&'ctx Bus, async_graphql::Error> {
+    context.data::<Bus>()
+}
+
+/// The caller, as authenticated by the server, anonymous if the request did not go through it.
+pub fn get_principal_from_context(context: &Context) -> Principal {
+    context
+        .data_opt::<Principal>()
+        .cloned()
+        .unwrap_or_else(Principal::anonymous)
+}
+
+/// Size of a page when neither `first` nor `last` is given.
+const DEFAULT_PAGE_SIZE: usize = 20;
+/// Largest page that can be requested.
+const MAX_PAGE_SIZE: usize = 100;
+
+/// Fields of a connection besides its edges and page info.
+#[derive(Debug, SimpleObject)]
+pub struct ConnectionFields {
+    /// Number of nodes in all the pages
+    total_count: i64,
+}
+
+/// Cursors are the position in the sorted list, encoded so clients treat them as opaque.
+impl CursorType for db::Keyset {
+    type Error = String;
+
+    fn decode_cursor(s: &str) -> Result<Self, Self::Error> {
+        hex::decode(s)
+            .map_err(|err| err.to_string())
+            .and_then(|bytes| serde_json::from_slice(&bytes).map_err(|err| err.to_string()))
+            .map_err(|err| format!("Invalid cursor: {}", err))
+    }
+
+    fn encode_cursor(&self) -> String {
+        hex::encode(serde_json::to_vec(self).expect("keyset serialization"))
+    }
+}
+
+fn currency_keyset(
+    currency: &model::Currency,
+    key: db::CurrencySortKey,
+    descending: bool,
+) -> db::Keyset {
+    db::Keyset {
+        sort: key,
+        descending,
+        key: match key {
+            db::CurrencySortKey::Code => currency.code.clone(),
+            db::CurrencySortKey::Name => currency.name.clone(),
+            db::CurrencySortKey::Decimals => currency.decimals.to_string(),
+        },
+        id: currency.code.clone(),
+    }
+}
+
+/// Filters on currencies. Missing fields match any currency.
+#[derive(Debug, InputObject)]
+struct CurrencyFilter {
+    code_prefix: Option<String>,
+    /// Case insensitive
+    name_contains: Option<String>,
+    decimals: Option<i32>,
+}
+
+impl From<CurrencyFilter> for db::CurrencyFilter {
+    fn from(input: CurrencyFilter) -> Self {
+        db::CurrencyFilter {
+            code_prefix: input.code_prefix,
+            name_contains: input.name_contains,
+            decimals: input.decimals,
+        }
+    }
+}
+
+#[derive(Debug, Default, Enum, Copy, Clone, Eq, PartialEq)]
+enum CurrencySortField {
+    #[default]
+    Code,
+    Name,
+    Decimals,
+}
+
+impl From<CurrencySortField> for db::CurrencySortKey {
+    fn from(field: CurrencySortField) -> Self {
+        match field {
+            CurrencySortField::Code => db::CurrencySortKey::Code,
+            CurrencySortField::Name => db::CurrencySortKey::Name,
+            CurrencySortField::Decimals => db::CurrencySortKey::Decimals,
+        }
+    }
+}
+
+#[derive(Debug, Default, Enum, Copy, Clone, Eq, PartialEq)]
+enum SortDirection {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// Order of the currencies, ties are broken by code.
+#[derive(Debug, Default, InputObject)]
+struct CurrencySort {
+    #[graphql(default)]
+    field: CurrencySortField,
+    #[graphql(default)]
+    direction: SortDirection,
 }
 
 #[derive(Debug, InputObject)]
@@ -80,6 +759,133 @@ struct CurrencyInput {
     decimals: i32,
 }
 
+/// Fields of a currency that can be modified. Missing fields are left unchanged.
+#[derive(Debug, InputObject)]
+struct CurrencyPatch {
+    name: Option<String>,
+    decimals: Option<i32>,
+}
+
+#[derive(Debug, InputObject)]
+struct SecurityInput {
+    ticker: String,
+    isin: String,
+    name: String,
+    /// MIC code of the exchange where the security is listed
+    exchange: String,
+    /// Code of the currency the security is traded in
+    currency: String,
+    asset_class: model::AssetClass,
+}
+
+#[derive(Debug, InputObject)]
+struct TradingHoursInput {
+    open: chrono::NaiveTime,
+    close: chrono::NaiveTime,
+}
+
+impl From<TradingHoursInput> for model::TradingHours {
+    fn from(input: TradingHoursInput) -> Self {
+        model::TradingHours {
+            open: input.open,
+            close: input.close,
+        }
+    }
+}
+
+#[derive(Debug, InputObject)]
+struct ExchangeInput {
+    /// ISO 10383 Market Identifier Code
+    mic: String,
+    name: String,
+    /// ISO 3166-1 alpha-2 country code
+    country: String,
+    /// IANA timezone name (eg Europe/Paris)
+    timezone: String,
+    /// Trading session, in the exchange's local time
+    trading_hours: TradingHoursInput,
+    /// Code of the default currency
+    currency: String,
+}
+
+/// Fields of an exchange that can be modified. Missing fields are left unchanged.
+#[derive(Debug, InputObject)]
+struct ExchangePatch {
+    name: Option<String>,
+    country: Option<String>,
+    timezone: Option<String>,
+    trading_hours: Option<TradingHoursInput>,
+    currency: Option<String>,
+}
+
+#[derive(Debug, InputObject)]
+struct PriceBarInput {
+    date: chrono::NaiveDate,
+    open: f64,
+    high: f64,
+    low: f64,
+    close: f64,
+    volume: i64,
+}
+
+impl From<PriceBarInput> for model::PriceBar {
+    fn from(input: PriceBarInput) -> Self {
+        model::PriceBar {
+            date: input.date,
+            open: input.open,
+            high: input.high,
+            low: input.low,
+            close: input.close,
+            volume: input.volume,
+        }
+    }
+}
+
+#[derive(Debug, InputObject)]
+struct FxRateInput {
+    base: String,
+    quote: String,
+    date: chrono::NaiveDate,
+    /// The price of one unit of base, in quote
+    rate: f64,
+}
+
+#[derive(Debug, InputObject)]
+struct PortfolioInput {
+    name: String,
+    /// Code of the currency the portfolio is valued in
+    currency: String,
+}
+
+/// A ledger entry. Amounts are in the currency of the portfolio.
+#[derive(Debug, InputObject)]
+struct TransactionInput {
+    kind: model::TransactionKind,
+    /// The security traded, or paying the dividend
+    ticker: Option<String>,
+    executed_at: chrono::DateTime<chrono::Utc>,
+    /// Units traded (buy and sell only)
+    #[graphql(default)]
+    quantity: f64,
+    /// Value of the trade, or cash amount of other transactions, excluding fees
+    amount: f64,
+    #[graphql(default)]
+    fees: f64,
+}
+
+impl From<TransactionInput> for model::NewTransaction {
+    fn from(input: TransactionInput) -> Self {
+        model::NewTransaction {
+            kind: input.kind,
+            ticker: input.ticker,
+            executed_at: input.executed_at,
+            quantity: input.quantity,
+            amount: input.amount,
+            fees: input.fees,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::model;
@@ -88,6 +894,15 @@ mod tests {
     use std::convert::Infallible;
     use warp::Filter;
 
+    fn principal(permissions: &[Permission]) -> Principal {
+        Principal {
+            subject: String::from("test"),
+            method: crate::api::auth::AuthMethod::ApiKey,
+            roles: Vec::new(),
+            permissions: permissions.to_vec(),
+        }
+    }
+
     // TODO How to create a function to return graphql_post, so we don't repeat it.
     #[tokio::test]
     async fn test_add_currency() {
@@ -103,10 +918,11 @@ mod tests {
                 })
             });
 
-        let schema = schema(Box::new(service));
+        let schema = schema(Arc::new(service), Bus::default());
 
         let graphql_post = async_graphql_warp::graphql(schema).and_then(
             |(schema, request): (StocksSchema, async_graphql::Request)| async move {
+                let request = request.data(principal(&[Permission::Admin]));
                 Ok::<_, Infallible>(async_graphql_warp::Response::from(
                     schema.execute(request).await,
                 ))
@@ -133,4 +949,410 @@ mod tests {
             serde_json::from_value(v["data"]["addCurrency"].to_owned()).expect("currency");
         assert_eq!(c.code, "EUR");
     }
+
+    #[tokio::test]
+    async fn test_update_currency() {
+        let mut service = model::MockStockService::new();
+        service
+            .expect_update_currency()
+            .withf(|code, name, decimals| {
+                code == "EUR" && name.as_deref() == Some("Euro") && decimals.is_none()
+            })
+            .times(1)
+            .returning(|code, name, _decimals| {
+                Ok(model::Currency {
+                    code: String::from(code),
+                    name: name.unwrap(),
+                    decimals: 2,
+                })
+            });
+
+        let schema = schema(Arc::new(service), Bus::default());
+
+        let request = async_graphql::Request::new(
+            r#"mutation updateCurrency($code: String!, $patch: CurrencyPatch!) { updateCurrency(code: $code, patch: $patch) { code, name, decimals } }"#,
+        )
+        .variables(Variables::from_value(value!({
+            "code": "EUR",
+            "patch": { "name": "Euro" }
+        })))
+        .data(principal(&[Permission::Admin]));
+
+        let resp = schema.execute(request).await;
+
+        assert!(resp.is_ok());
+        assert_eq!(
+            resp.data,
+            value!({ "updateCurrency": { "code": "EUR", "name": "Euro", "decimals": 2 } })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_security_currency() {
+        let mut service = model::MockStockService::new();
+        service
+            .expect_find_security_by_ticker()
+            .times(1)
+            .returning(|ticker| {
+                Ok(Some(model::Security {
+                    id: uuid::Uuid::new_v4(),
+                    ticker: String::from(ticker),
+                    isin: String::from("FR0000121014"),
+                    name: String::from("LVMH"),
+                    exchange: String::from("XPAR"),
+                    currency_code: String::from("EUR"),
+                    asset_class: model::AssetClass::Equity,
+                }))
+            });
+        service
+            .expect_find_currencies()
+            .withf(|codes| codes == ["EUR"])
+            .times(1)
+            .returning(|_| {
+                Ok(vec![model::Currency {
+                    code: String::from("EUR"),
+                    name: String::from("Euro"),
+                    decimals: 2,
+                }])
+            });
+
+        let schema = schema(Arc::new(service), Bus::default());
+
+        let request = async_graphql::Request::new(
+            r#"query findSecurityByTicker($ticker: String!) { findSecurityByTicker(ticker: $ticker) { ticker, assetClass, currency { code, name } } }"#,
+        )
+        .variables(Variables::from_value(value!({ "ticker": "MC.PA" })))
+        .data(principal(&[Permission::Read]));
+
+        let resp = schema.execute(request).await;
+
+        assert!(resp.is_ok(), "{:?}", resp.errors);
+        assert_eq!(
+            resp.data,
+            value!({
+                "findSecurityByTicker": {
+                    "ticker": "MC.PA",
+                    "assetClass": "EQUITY",
+                    "currency": { "code": "EUR", "name": "Euro" }
+                }
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_import_currencies_from_upload() {
+        let mut service = model::MockStockService::new();
+        service
+            .expect_import_currencies()
+            .withf(|data, format, on_conflict| {
+                data.as_slice() == b"code,name,decimals\nEUR,Euro,2\n"
+                    && *format == import::Format::Csv
+                    && *on_conflict == import::OnConflict::Update
+            })
+            .times(1)
+            .returning(|_, _, _| {
+                Ok(import::ImportReport {
+                    inserted: 1,
+                    rows: vec![import::RowReport {
+                        line: 2,
+                        key: Some(String::from("EUR")),
+                        outcome: import::Outcome::Inserted,
+                        message: None,
+                    }],
+                    ..Default::default()
+                })
+            });
+
+        let schema = schema(Arc::new(service), Bus::default());
+        let path = std::env::temp_dir().join(format!("currencies-{}.csv", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "code,name,decimals\nEUR,Euro,2\n").expect("upload");
+        let mut request = async_graphql::Request::new(
+            r#"mutation importCurrencies($file: Upload!) { importCurrencies(file: $file, onConflict: UPDATE) { inserted, rows { line, key, outcome } } }"#,
+        )
+        .variables(Variables::from_value(value!({ "file": null })))
+        .data(principal(&[Permission::Admin]));
+        request.set_upload(
+            "variables.file",
+            UploadValue {
+                filename: String::from("currencies.csv"),
+                content_type: Some(String::from("text/csv")),
+                content: std::fs::File::open(&path).expect("upload"),
+            },
+        );
+
+        let resp = schema.execute(request).await;
+        std::fs::remove_file(&path).expect("upload");
+
+        assert!(resp.is_ok(), "{:?}", resp.errors);
+        assert_eq!(
+            resp.data,
+            value!({
+                "importCurrencies": {
+                    "inserted": 1,
+                    "rows": [{ "line": 2, "key": "EUR", "outcome": "INSERTED" }]
+                }
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_nested_currencies_are_loaded_in_one_batch() {
+        let mut service = model::MockStockService::new();
+        service.expect_list_securities().times(1).returning(|| {
+            let security = |ticker: &str, currency: &str| model::Security {
+                id: uuid::Uuid::new_v4(),
+                ticker: String::from(ticker),
+                isin: String::from("US0378331005"),
+                name: String::from(ticker),
+                exchange: String::from("XNAS"),
+                currency_code: String::from(currency),
+                asset_class: model::AssetClass::Equity,
+            };
+            Ok(vec![
+                security("AAPL", "USD"),
+                security("MC.PA", "EUR"),
+                security("MSFT", "USD"),
+            ])
+        });
+        service.expect_find_currency().never();
+        service
+            .expect_find_currencies()
+            .withf(|codes| {
+                let mut codes = codes.to_vec();
+                codes.sort();
+                codes == ["EUR", "USD"]
+            })
+            .times(1)
+            .returning(|_| {
+                let currency = |code: &str| model::Currency {
+                    code: String::from(code),
+                    name: String::from(code),
+                    decimals: 2,
+                };
+                Ok(vec![currency("EUR"), currency("USD")])
+            });
+
+        let schema = schema(Arc::new(service), Bus::default());
+        let request =
+            async_graphql::Request::new(r#"{ listSecurities { ticker, currency { code } } }"#)
+                .data(principal(&[Permission::Read]));
+
+        let resp = schema.execute(request).await;
+
+        assert!(resp.is_ok());
+        assert_eq!(
+            resp.data,
+            value!({
+                "listSecurities": [
+                    { "ticker": "AAPL", "currency": { "code": "USD" } },
+                    { "ticker": "MC.PA", "currency": { "code": "EUR" } },
+                    { "ticker": "MSFT", "currency": { "code": "USD" } }
+                ]
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_transaction_recorded() {
+        let bus = Bus::default();
+        let schema = schema(Arc::new(model::MockStockService::new()), bus.clone());
+        let portfolio_id = uuid::Uuid::new_v4();
+
+        let request = async_graphql::Request::new(
+            r#"subscription transactionRecorded($portfolioId: UUID!) { transactionRecorded(portfolioId: $portfolioId) { ticker, quantity } }"#,
+        )
+        .variables(Variables::from_value(value!({
+            "portfolioId": portfolio_id.to_string(),
+        })))
+        .data(principal(&[Permission::Read]));
+        let mut stream = Box::pin(schema.execute_stream(request));
+        // Polling once registers the subscriber on the bus.
+        assert!(futures::poll!(stream.next()).is_pending());
+
+        let transaction = |portfolio_id, ticker: &str| model::Transaction {
+            id: uuid::Uuid::new_v4(),
+            portfolio_id,
+            kind: model::TransactionKind::Buy,
+            ticker: Some(String::from(ticker)),
+            executed_at: chrono::Utc::now(),
+            quantity: 10.0,
+            amount: 1000.0,
+            fees: 0.0,
+        };
+        bus.publish(Event::TransactionRecorded(transaction(
+            uuid::Uuid::new_v4(),
+            "MSFT",
+        )));
+        bus.publish(Event::TransactionRecorded(transaction(
+            portfolio_id,
+            "AAPL",
+        )));
+
+        let resp = stream.next().await.expect("response");
+
+        assert!(resp.is_ok());
+        assert_eq!(
+            resp.data,
+            value!({ "transactionRecorded": { "ticker": "AAPL", "quantity": 10.0 } })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reader_cannot_manage_currencies() {
+        let mut service = model::MockStockService::new();
+        service
+            .expect_find_currency()
+            .times(1)
+            .returning(|_| Ok(None));
+        service.expect_add_currency().never();
+
+        let schema = schema(Arc::new(service), Bus::default());
+        let reader = principal(&[Permission::Read]);
+
+        let found = schema
+            .execute(
+                async_graphql::Request::new(r#"{ findCurrency(code: "EUR") { code } }"#)
+                    .data(reader.clone()),
+            )
+            .await;
+        let added = schema
+            .execute(
+                async_graphql::Request::new(
+                    r#"mutation { addCurrency(currency: { code: "EUR", name: "Euro", decimals: 2 }) { code } }"#,
+                )
+                .data(reader),
+            )
+            .await;
+        let anonymous = schema
+            .execute(r#"{ findCurrency(code: "EUR") { code } }"#)
+            .await;
+
+        assert!(found.is_ok());
+        for resp in &[added, anonymous] {
+            let errors = serde_json::to_value(&resp.errors).expect("json");
+            assert_eq!(errors[0]["extensions"]["code"], "FORBIDDEN", "{}", errors);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_invalid_currency_is_rejected_by_field() {
+        let mut service = model::MockStockService::new();
+        service.expect_add_currency().never();
+
+        let schema = schema(Arc::new(service), Bus::default());
+        let request = async_graphql::Request::new(
+            r#"mutation { addCurrency(currency: { code: "euro", name: "Euro", decimals: -1 }) { code } }"#,
+        )
+        .data(principal(&[Permission::Admin]));
+
+        let resp = schema.execute(request).await;
+
+        let errors = serde_json::to_value(&resp.errors).expect("json");
+        let extensions = &errors[0]["extensions"];
+        assert_eq!(extensions["code"], "VALIDATION_FAILED");
+        assert_eq!(extensions["fields"][0]["field"], "code");
+        assert_eq!(extensions["fields"][1]["field"], "decimals");
+    }
+
+    #[tokio::test]
+    async fn test_currencies_are_paginated_with_cursors() {
+        let mut service = model::MockStockService::new();
+        service
+            .expect_search_currencies()
+            .withf(|filter, sort, descending, page| {
+                filter.code_prefix.as_deref() == Some("E")
+                    && *sort == db::CurrencySortKey::Name
+                    && *descending
+                    && page.after.as_ref().map(|after| after.id.as_str()) == Some("GBP")
+                    && page.limit == 3
+                    && !page.backward
+            })
+            .times(1)
+            .returning(|_, _, _, _| {
+                let currency = |code: &str, name: &str| model::Currency {
+                    code: String::from(code),
+                    name: String::from(name),
+                    decimals: 2,
+                };
+                Ok(model::Page {
+                    items: vec![
+                        currency("EUR", "Euro"),
+                        currency("EGP", "Egyptian pound"),
+                        currency("ETB", "Ethiopian birr"),
+                    ],
+                    total_count: 5,
+                })
+            });
+
+        let schema = schema(Arc::new(service), Bus::default());
+        let after = db::Keyset {
+            sort: db::CurrencySortKey::Name,
+            descending: true,
+            key: String::from("Pound sterling"),
+            id: String::from("GBP"),
+        }
+        .encode_cursor();
+        let request = async_graphql::Request::new(
+            r#"query currencies($after: String) { currencies(first: 2, after: $after, filter: { codePrefix: "E" }, sort: { field: NAME, direction: DESC }) { totalCount, pageInfo { hasPreviousPage, hasNextPage, endCursor }, edges { node { code } } } }"#,
+        )
+        .variables(Variables::from_value(value!({ "after": after })))
+        .data(principal(&[Permission::Read]));
+
+        let resp = schema.execute(request).await;
+
+        assert!(resp.is_ok());
+        let data = resp.data.into_json().expect("json");
+        let currencies = &data["currencies"];
+        assert_eq!(currencies["totalCount"], 5);
+        assert_eq!(currencies["pageInfo"]["hasPreviousPage"], true);
+        assert_eq!(currencies["pageInfo"]["hasNextPage"], true);
+        assert_eq!(currencies["edges"][1]["node"]["code"], "EGP");
+        let end = currencies["pageInfo"]["endCursor"]
+            .as_str()
+            .expect("cursor");
+        let end = db::Keyset::decode_cursor(end).expect("keyset");
+        assert_eq!(end.key, "Egyptian pound");
+        assert_eq!(end.id, "EGP");
+    }
+
+    #[tokio::test]
+    async fn test_cursors_of_another_sort_are_rejected() {
+        let schema = schema(Arc::new(model::MockStockService::new()), Bus::default());
+        let cursor = |sort, descending, key: &str| {
+            db::Keyset {
+                sort,
+                descending,
+                key: String::from(key),
+                id: String::from("EUR"),
+            }
+            .encode_cursor()
+        };
+        let query = |sort: &str, after: String| {
+            async_graphql::Request::new(format!(
+                r#"query currencies($after: String) {{ currencies(first: 2, after: $after, sort: {}) {{ totalCount }} }}"#,
+                sort
+            ))
+            .variables(Variables::from_value(value!({ "after": after })))
+            .data(principal(&[Permission::Read]))
+        };
+
+        for request in [
+            // A code is a valid name, but the cursor comes from the list sorted on codes.
+            query(
+                "{ field: NAME }",
+                cursor(db::CurrencySortKey::Code, false, "EUR"),
+            ),
+            query(
+                "{ field: NAME, direction: DESC }",
+                cursor(db::CurrencySortKey::Name, false, "Euro"),
+            ),
+            query("{ field: NAME }", String::from("not a cursor")),
+        ] {
+            let resp = schema.execute(request).await;
+
+            let err = serde_json::to_value(&resp.errors[0].extensions).expect("json");
+            assert_eq!(err["code"], "VALIDATION_FAILED");
+            assert_eq!(err["fields"][0]["field"], "after");
+        }
+    }
 }
@@ -0,0 +1,161 @@
+//! Liveness and readiness of the service, for the orchestrator.
+//!
+//! The service is live as soon as it answers. It is ready when the database answers within
+//! the configured timeout, and its schema has all the migrations of this build.
+
+use serde::Serialize;
+use sqlx::postgres::PgPool;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use crate::db::migrate;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Up,
+    Down,
+}
+
+/// The outcome of a single check.
+///
+/// Anyone may ask for it, so the error is a short reason, the details are only logged.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Check {
+    pub status: Status,
+    pub latency_ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<&'static str>,
+}
+
+/// Why a check failed: a short reason for the reply, and details for the logs.
+type Failure = (&'static str, String);
+
+/// The outcome of all the checks, up only if each one is.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Health {
+    pub status: Status,
+    pub checks: BTreeMap<&'static str, Check>,
+}
+
+impl Health {
+    fn new(checks: BTreeMap<&'static str, Check>) -> Self {
+        let status = if checks.values().all(|check| check.status == Status::Up) {
+            Status::Up
+        } else {
+            Status::Down
+        };
+        Health { status, checks }
+    }
+
+    pub fn is_up(&self) -> bool {
+        self.status == Status::Up
+    }
+}
+
+/// Run a check, failing it if it does not complete within `timeout`.
+async fn check<F>(name: &str, timeout: Duration, check: F) -> Check
+where
+    F: Future<Output = Result<(), Failure>>,
+{
+    let start = Instant::now();
+    let res = match tokio::time::timeout(timeout, check).await {
+        Ok(res) => res,
+        Err(_) => Err(("timed out", format!("no answer after {:?}", timeout))),
+    };
+    let latency_ms = start.elapsed().as_millis();
+    match res {
+        Ok(()) => Check {
+            status: Status::Up,
+            latency_ms,
+            error: None,
+        },
+        Err((reason, details)) => {
+            warn!("Health check {} is down: {}", name, details);
+            Check {
+                status: Status::Down,
+                latency_ms,
+                error: Some(reason),
+            }
+        }
+    }
+}
+
+/// The process is up, there is nothing else to check.
+pub fn live() -> Health {
+    Health::new(BTreeMap::new())
+}
+
+/// Check the database answers, and its schema is up to date, each within `timeout`.
+pub async fn ready(pool: &PgPool, timeout: Duration) -> Health {
+    let database = check("database", timeout, async {
+        sqlx::query("SELECT 1")
+            .execute(pool)
+            .await
+            .map(|_| ())
+            .map_err(|err| ("unreachable", err.to_string()))
+    })
+    .await;
+
+    let migrations = check("migrations", timeout, async {
+        let mut conn = pool
+            .acquire()
+            .await
+            .map_err(|err| ("unreachable", err.to_string()))?;
+        let pending = migrate::status(&mut conn)
+            .await
+            .map_err(|err| ("unknown", err.to_string()))?
+            .into_iter()
+            .filter(|migration| !migration.applied)
+            .map(|migration| format!("{}/{}", migration.version, migration.description))
+            .collect::<Vec<_>>();
+        if pending.is_empty() {
+            Ok(())
+        } else {
+            Err((
+                "pending",
+                format!("pending migrations: {}", pending.join(", ")),
+            ))
+        }
+    })
+    .await;
+
+    let mut checks = BTreeMap::new();
+    checks.insert("database", database);
+    checks.insert("migrations", migrations);
+    Health::new(checks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::get_database_url;
+
+    #[tokio::test]
+    async fn test_check_times_out() {
+        let check = check("sleep", Duration::from_millis(10), async {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            Ok(())
+        })
+        .await;
+
+        assert_eq!(check.status, Status::Down);
+        assert_eq!(check.error, Some("timed out"));
+    }
+
+    #[tokio::test]
+    async fn test_migrated_database_is_ready() {
+        let url = get_database_url();
+        let pool = PgPool::connect(&url).await.expect("pool");
+
+        let health = ready(&pool, Duration::from_secs(5)).await;
+
+        assert!(health.is_up(), "{:?}", health);
+        assert_eq!(
+            serde_json::to_value(&health).expect("json")["checks"]["database"]["status"],
+            "up"
+        );
+    }
+}
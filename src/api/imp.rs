@@ -1,15 +1,30 @@
 use async_graphql::*;
 use async_trait::async_trait;
+use chrono::NaiveDate;
 use snafu::ResultExt;
-use sqlx::postgres::PgPool;
+use sqlx::postgres::{PgConnection, PgPool};
 use sqlx::Acquire;
+use std::collections::HashMap;
+use tracing::error;
+use uuid::Uuid;
 
+use super::bus::{Bus, Event};
 use super::error;
+use super::fx;
+use super::import;
+use super::metrics;
 use super::model;
-use crate::db::model::ProvideStock;
+use super::validate;
+use crate::db::model::{
+    CurrencyFilter, CurrencySortKey, Pagination, PriceBarEntity, ProvideError, ProvideStock,
+    TransactionEntity,
+};
+use crate::pnl;
 
 pub struct StockServiceImpl {
     pub pool: PgPool,
+    /// Changes are published on the bus once committed
+    pub bus: Bus,
 }
 
 #[async_trait]
@@ -19,9 +34,11 @@ impl model::StockService for StockServiceImpl {
         async move {
             let pool = &self.pool;
 
-            let mut conn = pool.acquire().await.context(error::DBConnectionError {
-                msg: "could not acquire connection",
-            })?;
+            let mut conn = metrics::acquire(pool)
+                .await
+                .context(error::DBConnectionError {
+                    msg: "could not acquire connection",
+                })?;
 
             let mut tx = conn.begin().await.context(error::DBTransactionError {
                 msg: "could not initiate transaction",
@@ -45,6 +62,53 @@ impl model::StockService for StockServiceImpl {
         .await
     }
 
+    /// Retrieve a page of the currencies matching the filter, and how many match
+    async fn search_currencies(
+        &self,
+        filter: CurrencyFilter,
+        sort: CurrencySortKey,
+        descending: bool,
+        page: Pagination,
+    ) -> Result<model::Page<model::Currency>, error::Error> {
+        async move {
+            let pool = &self.pool;
+
+            let mut conn = metrics::acquire(pool)
+                .await
+                .context(error::DBConnectionError {
+                    msg: "could not acquire connection",
+                })?;
+
+            let mut tx = conn.begin().await.context(error::DBTransactionError {
+                msg: "could not initiate transaction",
+            })?;
+
+            let entities = tx
+                .search_currencies(&filter, sort, descending, &page)
+                .await
+                .context(error::DBProvideError {
+                    msg: "Could not search currencies",
+                })?;
+
+            let total_count =
+                tx.count_currencies(&filter)
+                    .await
+                    .context(error::DBProvideError {
+                        msg: "Could not count currencies",
+                    })?;
+
+            tx.commit().await.context(error::DBTransactionError {
+                msg: "could not commit transaction",
+            })?;
+
+            Ok(model::Page {
+                items: entities.into_iter().map(model::Currency::from).collect(),
+                total_count,
+            })
+        }
+        .await
+    }
+
     async fn add_currency(
         &self,
         code: &str,
@@ -52,11 +116,15 @@ impl model::StockService for StockServiceImpl {
         decimals: i32,
     ) -> Result<model::Currency, error::Error> {
         async move {
+            validate::add_currency(code, name, decimals)?;
+
             let pool = &self.pool;
 
-            let mut conn = pool.acquire().await.context(error::DBConnectionError {
-                msg: "could not acquire connection",
-            })?;
+            let mut conn = metrics::acquire(pool)
+                .await
+                .context(error::DBConnectionError {
+                    msg: "could not acquire connection",
+                })?;
 
             let mut tx = conn.begin().await.context(error::DBTransactionError {
                 msg: "could not initiate transaction",
@@ -75,6 +143,12 @@ impl model::StockService for StockServiceImpl {
                 msg: "could not commit transaction",
             })?;
 
+            self.bus
+                .publish(Event::CurrencyChanged(model::CurrencyChange {
+                    kind: model::ChangeKind::Added,
+                    currency: currency.clone(),
+                }));
+
             Ok(currency)
         }
         .await
@@ -85,9 +159,11 @@ impl model::StockService for StockServiceImpl {
         async move {
             let pool = &self.pool;
 
-            let mut conn = pool.acquire().await.context(error::DBConnectionError {
-                msg: "could not acquire connection",
-            })?;
+            let mut conn = metrics::acquire(pool)
+                .await
+                .context(error::DBConnectionError {
+                    msg: "could not acquire connection",
+                })?;
 
             let mut tx = conn.begin().await.context(error::DBTransactionError {
                 msg: "could not initiate transaction",
@@ -110,4 +186,1184 @@ impl model::StockService for StockServiceImpl {
         }
         .await
     }
+
+    /// Retrieve the currencies with the given codes
+    async fn find_currencies(
+        &self,
+        codes: &[String],
+    ) -> Result<Vec<model::Currency>, error::Error> {
+        async move {
+            let pool = &self.pool;
+
+            let mut conn = metrics::acquire(pool)
+                .await
+                .context(error::DBConnectionError {
+                    msg: "could not acquire connection",
+                })?;
+
+            let mut tx = conn.begin().await.context(error::DBTransactionError {
+                msg: "could not initiate transaction",
+            })?;
+
+            let entities = tx
+                .find_currencies(codes)
+                .await
+                .context(error::DBProvideError {
+                    msg: "Could not find currencies",
+                })?;
+
+            tx.commit().await.context(error::DBTransactionError {
+                msg: "could not commit transaction",
+            })?;
+
+            Ok(entities.into_iter().map(model::Currency::from).collect())
+        }
+        .await
+    }
+
+    /// Update a currency's name and / or decimals
+    async fn update_currency(
+        &self,
+        code: &str,
+        name: Option<String>,
+        decimals: Option<i32>,
+    ) -> Result<model::Currency, error::Error> {
+        async move {
+            validate::update_currency(code, name.as_deref(), decimals)?;
+
+            let pool = &self.pool;
+
+            let mut conn = metrics::acquire(pool)
+                .await
+                .context(error::DBConnectionError {
+                    msg: "could not acquire connection",
+                })?;
+
+            let mut tx = conn.begin().await.context(error::DBTransactionError {
+                msg: "could not initiate transaction",
+            })?;
+
+            let entity =
+                tx.update_currency(code, name, decimals)
+                    .await
+                    .context(error::DBProvideError {
+                        msg: "Could not update currency",
+                    })?;
+
+            let currency = model::Currency::from(entity);
+
+            tx.commit().await.context(error::DBTransactionError {
+                msg: "could not commit transaction",
+            })?;
+
+            self.bus
+                .publish(Event::CurrencyChanged(model::CurrencyChange {
+                    kind: model::ChangeKind::Updated,
+                    currency: currency.clone(),
+                }));
+
+            Ok(currency)
+        }
+        .await
+    }
+
+    /// Delete a currency by code
+    async fn delete_currency(&self, code: &str) -> Result<model::Currency, error::Error> {
+        async move {
+            let pool = &self.pool;
+
+            let mut conn = metrics::acquire(pool)
+                .await
+                .context(error::DBConnectionError {
+                    msg: "could not acquire connection",
+                })?;
+
+            let mut tx = conn.begin().await.context(error::DBTransactionError {
+                msg: "could not initiate transaction",
+            })?;
+
+            let entity = tx
+                .delete_currency(code)
+                .await
+                .context(error::DBProvideError {
+                    msg: "Could not delete currency",
+                })?;
+
+            let currency = model::Currency::from(entity);
+
+            tx.commit().await.context(error::DBTransactionError {
+                msg: "could not commit transaction",
+            })?;
+
+            self.bus
+                .publish(Event::CurrencyChanged(model::CurrencyChange {
+                    kind: model::ChangeKind::Deleted,
+                    currency: currency.clone(),
+                }));
+
+            Ok(currency)
+        }
+        .await
+    }
+
+    /// Import currencies from a CSV or NDJSON file, announcing those inserted or updated
+    async fn import_currencies(
+        &self,
+        data: Vec<u8>,
+        format: import::Format,
+        on_conflict: import::OnConflict,
+    ) -> Result<import::ImportReport, error::Error> {
+        async move {
+            let rows = import::parse(&data, format)?;
+
+            let pool = &self.pool;
+
+            let mut conn = metrics::acquire(pool)
+                .await
+                .context(error::DBConnectionError {
+                    msg: "could not acquire connection",
+                })?;
+
+            // When the store fails, the report of the rows so far tells clients why the import
+            // stopped, the error itself is for the logs.
+            let imported =
+                import::import_currencies(&mut conn, rows, on_conflict, import::BATCH_SIZE).await;
+            if let Some(err) = &imported.error {
+                if let error::Error::DBProvideError { source, .. } = err {
+                    metrics::record_provide_error(source);
+                }
+                error!("Import of currencies aborted: {}", err);
+            }
+
+            for (outcome, entity) in imported.changed {
+                let kind = match outcome {
+                    import::Outcome::Inserted => model::ChangeKind::Added,
+                    _ => model::ChangeKind::Updated,
+                };
+                self.bus
+                    .publish(Event::CurrencyChanged(model::CurrencyChange {
+                        kind,
+                        currency: model::Currency::from(entity),
+                    }));
+            }
+
+            Ok(imported.report)
+        }
+        .await
+    }
+
+    /// Retrieve all securities
+    async fn list_securities(&self) -> Result<Vec<model::Security>, error::Error> {
+        async move {
+            let pool = &self.pool;
+
+            let mut conn = metrics::acquire(pool)
+                .await
+                .context(error::DBConnectionError {
+                    msg: "could not acquire connection",
+                })?;
+
+            let mut tx = conn.begin().await.context(error::DBTransactionError {
+                msg: "could not initiate transaction",
+            })?;
+
+            let entities = tx.list_securities().await.context(error::DBProvideError {
+                msg: "Could not get all securities",
+            })?;
+
+            let securities = entities
+                .into_iter()
+                .map(model::Security::from)
+                .collect::<Vec<_>>();
+
+            tx.commit().await.context(error::DBTransactionError {
+                msg: "could not commit transaction",
+            })?;
+
+            Ok(securities)
+        }
+        .await
+    }
+
+    async fn add_security(
+        &self,
+        ticker: &str,
+        isin: &str,
+        name: &str,
+        exchange: &str,
+        currency: &str,
+        asset_class: model::AssetClass,
+    ) -> Result<model::Security, error::Error> {
+        async move {
+            validate::add_security(ticker, isin, name, exchange, currency)?;
+
+            let pool = &self.pool;
+
+            let mut conn = metrics::acquire(pool)
+                .await
+                .context(error::DBConnectionError {
+                    msg: "could not acquire connection",
+                })?;
+
+            let mut tx = conn.begin().await.context(error::DBTransactionError {
+                msg: "could not initiate transaction",
+            })?;
+
+            let entity = tx
+                .add_security(ticker, isin, name, exchange, currency, asset_class.into())
+                .await
+                .context(error::DBProvideError {
+                    msg: "Could not add security",
+                })?;
+
+            let security = model::Security::from(entity);
+
+            tx.commit().await.context(error::DBTransactionError {
+                msg: "could not commit transaction",
+            })?;
+
+            Ok(security)
+        }
+        .await
+    }
+
+    /// Find a security by ticker
+    async fn find_security_by_ticker(
+        &self,
+        ticker: &str,
+    ) -> Result<Option<model::Security>, error::Error> {
+        async move {
+            let pool = &self.pool;
+
+            let mut conn = metrics::acquire(pool)
+                .await
+                .context(error::DBConnectionError {
+                    msg: "could not acquire connection",
+                })?;
+
+            let mut tx = conn.begin().await.context(error::DBTransactionError {
+                msg: "could not initiate transaction",
+            })?;
+
+            let entity =
+                tx.find_security_by_ticker(ticker)
+                    .await
+                    .context(error::DBProvideError {
+                        msg: "Could not find security",
+                    })?;
+
+            let security = entity.map(model::Security::from);
+
+            tx.commit().await.context(error::DBTransactionError {
+                msg: "could not commit transaction",
+            })?;
+
+            Ok(security)
+        }
+        .await
+    }
+
+    /// Retrieve the securities with the given tickers
+    async fn find_securities_by_ticker(
+        &self,
+        tickers: &[String],
+    ) -> Result<Vec<model::Security>, error::Error> {
+        async move {
+            let pool = &self.pool;
+
+            let mut conn = metrics::acquire(pool)
+                .await
+                .context(error::DBConnectionError {
+                    msg: "could not acquire connection",
+                })?;
+
+            let mut tx = conn.begin().await.context(error::DBTransactionError {
+                msg: "could not initiate transaction",
+            })?;
+
+            let entities =
+                tx.find_securities_by_ticker(tickers)
+                    .await
+                    .context(error::DBProvideError {
+                        msg: "Could not find securities",
+                    })?;
+
+            tx.commit().await.context(error::DBTransactionError {
+                msg: "could not commit transaction",
+            })?;
+
+            Ok(entities.into_iter().map(model::Security::from).collect())
+        }
+        .await
+    }
+
+    /// Find a security by ISIN
+    async fn find_security_by_isin(
+        &self,
+        isin: &str,
+    ) -> Result<Option<model::Security>, error::Error> {
+        async move {
+            let pool = &self.pool;
+
+            let mut conn = metrics::acquire(pool)
+                .await
+                .context(error::DBConnectionError {
+                    msg: "could not acquire connection",
+                })?;
+
+            let mut tx = conn.begin().await.context(error::DBTransactionError {
+                msg: "could not initiate transaction",
+            })?;
+
+            let entity = tx
+                .find_security_by_isin(isin)
+                .await
+                .context(error::DBProvideError {
+                    msg: "Could not find security",
+                })?;
+
+            let security = entity.map(model::Security::from);
+
+            tx.commit().await.context(error::DBTransactionError {
+                msg: "could not commit transaction",
+            })?;
+
+            Ok(security)
+        }
+        .await
+    }
+
+    /// Retrieve all exchanges
+    async fn list_exchanges(&self) -> Result<Vec<model::Exchange>, error::Error> {
+        async move {
+            let pool = &self.pool;
+
+            let mut conn = metrics::acquire(pool)
+                .await
+                .context(error::DBConnectionError {
+                    msg: "could not acquire connection",
+                })?;
+
+            let mut tx = conn.begin().await.context(error::DBTransactionError {
+                msg: "could not initiate transaction",
+            })?;
+
+            let entities = tx.list_exchanges().await.context(error::DBProvideError {
+                msg: "Could not get all exchanges",
+            })?;
+
+            let exchanges = entities
+                .into_iter()
+                .map(model::Exchange::from)
+                .collect::<Vec<_>>();
+
+            tx.commit().await.context(error::DBTransactionError {
+                msg: "could not commit transaction",
+            })?;
+
+            Ok(exchanges)
+        }
+        .await
+    }
+
+    async fn add_exchange(
+        &self,
+        mic: &str,
+        name: &str,
+        country: &str,
+        timezone: &str,
+        trading_hours: model::TradingHours,
+        currency: &str,
+    ) -> Result<model::Exchange, error::Error> {
+        async move {
+            validate::add_exchange(mic, name, country, timezone, &trading_hours, currency)?;
+
+            let pool = &self.pool;
+
+            let mut conn = metrics::acquire(pool)
+                .await
+                .context(error::DBConnectionError {
+                    msg: "could not acquire connection",
+                })?;
+
+            let mut tx = conn.begin().await.context(error::DBTransactionError {
+                msg: "could not initiate transaction",
+            })?;
+
+            let entity = tx
+                .add_exchange(mic, name, country, timezone, trading_hours.into(), currency)
+                .await
+                .context(error::DBProvideError {
+                    msg: "Could not add exchange",
+                })?;
+
+            let exchange = model::Exchange::from(entity);
+
+            tx.commit().await.context(error::DBTransactionError {
+                msg: "could not commit transaction",
+            })?;
+
+            Ok(exchange)
+        }
+        .await
+    }
+
+    /// Find an exchange by MIC
+    async fn find_exchange(&self, mic: &str) -> Result<Option<model::Exchange>, error::Error> {
+        async move {
+            let pool = &self.pool;
+
+            let mut conn = metrics::acquire(pool)
+                .await
+                .context(error::DBConnectionError {
+                    msg: "could not acquire connection",
+                })?;
+
+            let mut tx = conn.begin().await.context(error::DBTransactionError {
+                msg: "could not initiate transaction",
+            })?;
+
+            let entity = tx.find_exchange(mic).await.context(error::DBProvideError {
+                msg: "Could not find exchange",
+            })?;
+
+            let exchange = entity.map(model::Exchange::from);
+
+            tx.commit().await.context(error::DBTransactionError {
+                msg: "could not commit transaction",
+            })?;
+
+            Ok(exchange)
+        }
+        .await
+    }
+
+    /// Retrieve the exchanges with the given MIC codes
+    async fn find_exchanges(&self, mics: &[String]) -> Result<Vec<model::Exchange>, error::Error> {
+        async move {
+            let pool = &self.pool;
+
+            let mut conn = metrics::acquire(pool)
+                .await
+                .context(error::DBConnectionError {
+                    msg: "could not acquire connection",
+                })?;
+
+            let mut tx = conn.begin().await.context(error::DBTransactionError {
+                msg: "could not initiate transaction",
+            })?;
+
+            let entities = tx
+                .find_exchanges(mics)
+                .await
+                .context(error::DBProvideError {
+                    msg: "Could not find exchanges",
+                })?;
+
+            tx.commit().await.context(error::DBTransactionError {
+                msg: "could not commit transaction",
+            })?;
+
+            Ok(entities.into_iter().map(model::Exchange::from).collect())
+        }
+        .await
+    }
+
+    /// Update an exchange
+    async fn update_exchange(
+        &self,
+        mic: &str,
+        name: Option<String>,
+        country: Option<String>,
+        timezone: Option<String>,
+        trading_hours: Option<model::TradingHours>,
+        currency: Option<String>,
+    ) -> Result<model::Exchange, error::Error> {
+        async move {
+            validate::update_exchange(
+                mic,
+                name.as_deref(),
+                country.as_deref(),
+                timezone.as_deref(),
+                trading_hours.as_ref(),
+                currency.as_deref(),
+            )?;
+
+            let pool = &self.pool;
+
+            let mut conn = metrics::acquire(pool)
+                .await
+                .context(error::DBConnectionError {
+                    msg: "could not acquire connection",
+                })?;
+
+            let mut tx = conn.begin().await.context(error::DBTransactionError {
+                msg: "could not initiate transaction",
+            })?;
+
+            let entity = tx
+                .update_exchange(
+                    mic,
+                    name,
+                    country,
+                    timezone,
+                    trading_hours.map(Into::into),
+                    currency,
+                )
+                .await
+                .context(error::DBProvideError {
+                    msg: "Could not update exchange",
+                })?;
+
+            let exchange = model::Exchange::from(entity);
+
+            tx.commit().await.context(error::DBTransactionError {
+                msg: "could not commit transaction",
+            })?;
+
+            Ok(exchange)
+        }
+        .await
+    }
+
+    /// Delete an exchange by MIC
+    async fn delete_exchange(&self, mic: &str) -> Result<model::Exchange, error::Error> {
+        async move {
+            let pool = &self.pool;
+
+            let mut conn = metrics::acquire(pool)
+                .await
+                .context(error::DBConnectionError {
+                    msg: "could not acquire connection",
+                })?;
+
+            let mut tx = conn.begin().await.context(error::DBTransactionError {
+                msg: "could not initiate transaction",
+            })?;
+
+            let entity = tx
+                .delete_exchange(mic)
+                .await
+                .context(error::DBProvideError {
+                    msg: "Could not delete exchange",
+                })?;
+
+            let exchange = model::Exchange::from(entity);
+
+            tx.commit().await.context(error::DBTransactionError {
+                msg: "could not commit transaction",
+            })?;
+
+            Ok(exchange)
+        }
+        .await
+    }
+
+    /// Store daily bars for a security
+    async fn add_price_bars(
+        &self,
+        ticker: &str,
+        bars: Vec<model::PriceBar>,
+    ) -> Result<u64, error::Error> {
+        async move {
+            validate::add_price_bars(ticker, &bars)?;
+
+            let pool = &self.pool;
+
+            let mut conn = metrics::acquire(pool)
+                .await
+                .context(error::DBConnectionError {
+                    msg: "could not acquire connection",
+                })?;
+
+            let mut tx = conn.begin().await.context(error::DBTransactionError {
+                msg: "could not initiate transaction",
+            })?;
+
+            let entities = bars
+                .into_iter()
+                .map(|bar| PriceBarEntity {
+                    ticker: String::from(ticker),
+                    date: bar.date,
+                    open: bar.open,
+                    high: bar.high,
+                    low: bar.low,
+                    close: bar.close,
+                    volume: bar.volume,
+                })
+                .collect::<Vec<_>>();
+
+            let count = tx
+                .add_price_bars(&entities)
+                .await
+                .context(error::DBProvideError {
+                    msg: "Could not add price bars",
+                })?;
+
+            tx.commit().await.context(error::DBTransactionError {
+                msg: "could not commit transaction",
+            })?;
+
+            for entity in entities {
+                self.bus.publish(Event::PriceUpdated(model::PriceUpdate {
+                    ticker: entity.ticker.clone(),
+                    bar: model::PriceBar::from(entity),
+                }));
+            }
+
+            Ok(count)
+        }
+        .await
+    }
+
+    /// Retrieve the price history of a security, aggregating daily bars if needed
+    async fn price_history(
+        &self,
+        ticker: &str,
+        from: NaiveDate,
+        to: NaiveDate,
+        interval: model::Interval,
+    ) -> Result<Vec<model::PriceBar>, error::Error> {
+        async move {
+            let pool = &self.pool;
+
+            let mut conn = metrics::acquire(pool)
+                .await
+                .context(error::DBConnectionError {
+                    msg: "could not acquire connection",
+                })?;
+
+            let mut tx = conn.begin().await.context(error::DBTransactionError {
+                msg: "could not initiate transaction",
+            })?;
+
+            let entities =
+                tx.find_price_bars(ticker, from, to)
+                    .await
+                    .context(error::DBProvideError {
+                        msg: "Could not find price bars",
+                    })?;
+
+            let bars = entities
+                .into_iter()
+                .map(model::PriceBar::from)
+                .collect::<Vec<_>>();
+
+            tx.commit().await.context(error::DBTransactionError {
+                msg: "could not commit transaction",
+            })?;
+
+            Ok(model::aggregate_bars(bars, interval))
+        }
+        .await
+    }
+
+    /// Store the rate of a currency pair
+    async fn add_fx_rate(
+        &self,
+        base: &str,
+        quote: &str,
+        date: NaiveDate,
+        rate: f64,
+    ) -> Result<model::FxRate, error::Error> {
+        async move {
+            validate::add_fx_rate(base, quote, rate)?;
+
+            let pool = &self.pool;
+
+            let mut conn = metrics::acquire(pool)
+                .await
+                .context(error::DBConnectionError {
+                    msg: "could not acquire connection",
+                })?;
+
+            let mut tx = conn.begin().await.context(error::DBTransactionError {
+                msg: "could not initiate transaction",
+            })?;
+
+            let entity =
+                tx.add_fx_rate(base, quote, date, rate)
+                    .await
+                    .context(error::DBProvideError {
+                        msg: "Could not add fx rate",
+                    })?;
+
+            let rate = model::FxRate::from(entity);
+
+            tx.commit().await.context(error::DBTransactionError {
+                msg: "could not commit transaction",
+            })?;
+
+            Ok(rate)
+        }
+        .await
+    }
+
+    /// Find the rate of a currency pair, deriving it from inverse or cross rates if needed
+    async fn fx_rate(
+        &self,
+        base: &str,
+        quote: &str,
+        date: NaiveDate,
+        pivot: &str,
+    ) -> Result<Option<model::FxRate>, error::Error> {
+        async move {
+            let pool = &self.pool;
+
+            let mut conn = metrics::acquire(pool)
+                .await
+                .context(error::DBConnectionError {
+                    msg: "could not acquire connection",
+                })?;
+
+            let mut tx = conn.begin().await.context(error::DBTransactionError {
+                msg: "could not initiate transaction",
+            })?;
+
+            let entity = fx::find_rate(&mut *tx, base, quote, date, pivot)
+                .await
+                .context(error::DBProvideError {
+                    msg: "Could not find fx rate",
+                })?;
+
+            let rate = entity.map(model::FxRate::from);
+
+            tx.commit().await.context(error::DBTransactionError {
+                msg: "could not commit transaction",
+            })?;
+
+            Ok(rate)
+        }
+        .await
+    }
+
+    /// Convert an amount, rounded to the decimals of the target currency
+    async fn convert(
+        &self,
+        amount: f64,
+        from: &str,
+        to: &str,
+        date: NaiveDate,
+        pivot: &str,
+    ) -> Result<Option<f64>, error::Error> {
+        async move {
+            let pool = &self.pool;
+
+            let mut conn = metrics::acquire(pool)
+                .await
+                .context(error::DBConnectionError {
+                    msg: "could not acquire connection",
+                })?;
+
+            let mut tx = conn.begin().await.context(error::DBTransactionError {
+                msg: "could not initiate transaction",
+            })?;
+
+            let currency = tx
+                .find_currency(to)
+                .await
+                .and_then(|currency| currency.ok_or(ProvideError::NotFound))
+                .context(error::DBProvideError {
+                    msg: "Could not find target currency",
+                })?;
+
+            let entity = fx::find_rate(&mut *tx, from, to, date, pivot)
+                .await
+                .context(error::DBProvideError {
+                    msg: "Could not find fx rate",
+                })?;
+
+            let converted = entity.map(|rate| fx::round(amount * rate.rate, currency.decimals));
+
+            tx.commit().await.context(error::DBTransactionError {
+                msg: "could not commit transaction",
+            })?;
+
+            Ok(converted)
+        }
+        .await
+    }
+
+    /// Retrieve all portfolios
+    async fn list_portfolios(&self) -> Result<Vec<model::Portfolio>, error::Error> {
+        async move {
+            let pool = &self.pool;
+
+            let mut conn = metrics::acquire(pool)
+                .await
+                .context(error::DBConnectionError {
+                    msg: "could not acquire connection",
+                })?;
+
+            let mut tx = conn.begin().await.context(error::DBTransactionError {
+                msg: "could not initiate transaction",
+            })?;
+
+            let entities = tx.list_portfolios().await.context(error::DBProvideError {
+                msg: "Could not get all portfolios",
+            })?;
+
+            let portfolios = entities
+                .into_iter()
+                .map(model::Portfolio::from)
+                .collect::<Vec<_>>();
+
+            tx.commit().await.context(error::DBTransactionError {
+                msg: "could not commit transaction",
+            })?;
+
+            Ok(portfolios)
+        }
+        .await
+    }
+
+    async fn add_portfolio(
+        &self,
+        name: &str,
+        currency: &str,
+    ) -> Result<model::Portfolio, error::Error> {
+        async move {
+            validate::add_portfolio(name, currency)?;
+
+            let pool = &self.pool;
+
+            let mut conn = metrics::acquire(pool)
+                .await
+                .context(error::DBConnectionError {
+                    msg: "could not acquire connection",
+                })?;
+
+            let mut tx = conn.begin().await.context(error::DBTransactionError {
+                msg: "could not initiate transaction",
+            })?;
+
+            let entity = tx
+                .add_portfolio(name, currency)
+                .await
+                .context(error::DBProvideError {
+                    msg: "Could not add portfolio",
+                })?;
+
+            let portfolio = model::Portfolio::from(entity);
+
+            tx.commit().await.context(error::DBTransactionError {
+                msg: "could not commit transaction",
+            })?;
+
+            Ok(portfolio)
+        }
+        .await
+    }
+
+    /// Find a portfolio by id
+    async fn find_portfolio(&self, id: Uuid) -> Result<Option<model::Portfolio>, error::Error> {
+        async move {
+            let pool = &self.pool;
+
+            let mut conn = metrics::acquire(pool)
+                .await
+                .context(error::DBConnectionError {
+                    msg: "could not acquire connection",
+                })?;
+
+            let mut tx = conn.begin().await.context(error::DBTransactionError {
+                msg: "could not initiate transaction",
+            })?;
+
+            let entity = tx.find_portfolio(id).await.context(error::DBProvideError {
+                msg: "Could not find portfolio",
+            })?;
+
+            let portfolio = entity.map(model::Portfolio::from);
+
+            tx.commit().await.context(error::DBTransactionError {
+                msg: "could not commit transaction",
+            })?;
+
+            Ok(portfolio)
+        }
+        .await
+    }
+
+    /// Record a transaction. Selling more units than held at the time of the sale is refused.
+    async fn add_transaction(
+        &self,
+        portfolio: Uuid,
+        transaction: model::NewTransaction,
+    ) -> Result<model::Transaction, error::Error> {
+        async move {
+            validate::add_transaction(&transaction)?;
+
+            let pool = &self.pool;
+
+            let mut conn = metrics::acquire(pool)
+                .await
+                .context(error::DBConnectionError {
+                    msg: "could not acquire connection",
+                })?;
+
+            let mut tx = conn.begin().await.context(error::DBTransactionError {
+                msg: "could not initiate transaction",
+            })?;
+
+            // Held until the end of the transaction, so that concurrent changes to the ledger
+            // are checked one after the other. An unknown portfolio is reported by the insert.
+            let _portfolio = tx
+                .lock_portfolio(portfolio)
+                .await
+                .context(error::DBProvideError {
+                    msg: "Could not lock portfolio",
+                })?;
+
+            let entity = TransactionEntity {
+                id: Uuid::new_v4(),
+                portfolio,
+                kind: transaction.kind.into(),
+                ticker: transaction.ticker,
+                executed_at: transaction.executed_at,
+                quantity: transaction.quantity,
+                amount: transaction.amount,
+                fees: transaction.fees,
+            };
+
+            let entity = tx
+                .add_transaction(&entity)
+                .await
+                .context(error::DBProvideError {
+                    msg: "Could not add transaction",
+                })?;
+
+            let transaction = model::Transaction::from(entity);
+
+            if transaction.kind == model::TransactionKind::Sell {
+                check_ledger(&mut tx, portfolio, "Could not add transaction").await?;
+            }
+
+            tx.commit().await.context(error::DBTransactionError {
+                msg: "could not commit transaction",
+            })?;
+
+            self.bus
+                .publish(Event::TransactionRecorded(transaction.clone()));
+
+            Ok(transaction)
+        }
+        .await
+    }
+
+    /// Retrieve the ledger of a portfolio
+    async fn list_transactions(
+        &self,
+        portfolio: Uuid,
+    ) -> Result<Vec<model::Transaction>, error::Error> {
+        async move {
+            let pool = &self.pool;
+
+            let mut conn = metrics::acquire(pool)
+                .await
+                .context(error::DBConnectionError {
+                    msg: "could not acquire connection",
+                })?;
+
+            let mut tx = conn.begin().await.context(error::DBTransactionError {
+                msg: "could not initiate transaction",
+            })?;
+
+            let entities =
+                tx.list_transactions(portfolio)
+                    .await
+                    .context(error::DBProvideError {
+                        msg: "Could not get portfolio transactions",
+                    })?;
+
+            let transactions = entities
+                .into_iter()
+                .map(model::Transaction::from)
+                .collect::<Vec<_>>();
+
+            tx.commit().await.context(error::DBTransactionError {
+                msg: "could not commit transaction",
+            })?;
+
+            Ok(transactions)
+        }
+        .await
+    }
+
+    /// Delete a transaction by id. Deleting a buy that later sells depend on is refused.
+    async fn delete_transaction(&self, id: Uuid) -> Result<model::Transaction, error::Error> {
+        async move {
+            let pool = &self.pool;
+
+            let mut conn = metrics::acquire(pool)
+                .await
+                .context(error::DBConnectionError {
+                    msg: "could not acquire connection",
+                })?;
+
+            let mut tx = conn.begin().await.context(error::DBTransactionError {
+                msg: "could not initiate transaction",
+            })?;
+
+            let entity = tx
+                .delete_transaction(id)
+                .await
+                .context(error::DBProvideError {
+                    msg: "Could not delete transaction",
+                })?;
+
+            let transaction = model::Transaction::from(entity);
+
+            // The later sells may have relied on the units of a deleted buy.
+            if transaction.kind == model::TransactionKind::Buy {
+                let _portfolio = tx.lock_portfolio(transaction.portfolio_id).await.context(
+                    error::DBProvideError {
+                        msg: "Could not lock portfolio",
+                    },
+                )?;
+                check_ledger(
+                    &mut tx,
+                    transaction.portfolio_id,
+                    "Could not delete transaction",
+                )
+                .await?;
+            }
+
+            tx.commit().await.context(error::DBTransactionError {
+                msg: "could not commit transaction",
+            })?;
+
+            Ok(transaction)
+        }
+        .await
+    }
+
+    /// Derive the open positions of a portfolio from its ledger
+    async fn positions(&self, portfolio: Uuid) -> Result<Vec<model::Position>, error::Error> {
+        let transactions = self.list_transactions(portfolio).await?;
+        Ok(model::positions(&transactions))
+    }
+
+    /// Compute the gains of a portfolio as of a date.
+    ///
+    /// The open positions are valued at the latest price on or before the date, converted
+    /// into the currency of the portfolio. Positions without a price or a rate have no
+    /// unrealized gain.
+    async fn performance(
+        &self,
+        portfolio: Uuid,
+        method: model::CostMethod,
+        as_of: NaiveDate,
+    ) -> Result<model::Performance, error::Error> {
+        async move {
+            let pool = &self.pool;
+
+            let mut conn = metrics::acquire(pool)
+                .await
+                .context(error::DBConnectionError {
+                    msg: "could not acquire connection",
+                })?;
+
+            let mut tx = conn.begin().await.context(error::DBTransactionError {
+                msg: "could not initiate transaction",
+            })?;
+
+            let entity = tx
+                .find_portfolio(portfolio)
+                .await
+                .and_then(|entity| entity.ok_or(ProvideError::NotFound))
+                .context(error::DBProvideError {
+                    msg: "Could not find portfolio",
+                })?;
+
+            let events = tx
+                .list_transactions(portfolio)
+                .await
+                .context(error::DBProvideError {
+                    msg: "Could not get portfolio transactions",
+                })?
+                .into_iter()
+                .map(model::Transaction::from)
+                .filter(|transaction| transaction.executed_at.date_naive() <= as_of)
+                .filter_map(|transaction| transaction.event())
+                .collect::<Vec<_>>();
+
+            let ledger = pnl::Ledger::replay(method.into(), &events);
+
+            let mut prices = HashMap::new();
+            for holding in ledger.holdings() {
+                let security = tx.find_security_by_ticker(&holding.ticker).await.context(
+                    error::DBProvideError {
+                        msg: "Could not find security",
+                    },
+                )?;
+                let bar = tx
+                    .find_latest_price_bar(&holding.ticker, as_of)
+                    .await
+                    .context(error::DBProvideError {
+                        msg: "Could not find latest price",
+                    })?;
+                let (security, bar) = match (security, bar) {
+                    (Some(security), Some(bar)) => (security, bar),
+                    _ => continue,
+                };
+                let rate = fx::find_rate(
+                    &mut *tx,
+                    &security.currency,
+                    &entity.currency,
+                    as_of,
+                    fx::DEFAULT_PIVOT,
+                )
+                .await
+                .context(error::DBProvideError {
+                    msg: "Could not find fx rate",
+                })?;
+                if let Some(rate) = rate {
+                    prices.insert(holding.ticker, bar.close * rate.rate);
+                }
+            }
+
+            let performance = model::Performance::new(ledger.performance(&prices), as_of);
+
+            tx.commit().await.context(error::DBTransactionError {
+                msg: "could not commit transaction",
+            })?;
+
+            Ok(performance)
+        }
+        .await
+    }
+}
+
+/// Refuse the change just made to the ledger if a sell now exceeds the units held when it is
+/// executed.
+async fn check_ledger(
+    conn: &mut PgConnection,
+    portfolio: Uuid,
+    msg: &str,
+) -> Result<(), error::Error> {
+    let ledger = conn
+        .list_transactions(portfolio)
+        .await
+        .context(error::DBProvideError {
+            msg: "Could not get portfolio transactions",
+        })?
+        .into_iter()
+        .map(model::Transaction::from)
+        .collect::<Vec<_>>();
+
+    match model::find_oversell(&ledger) {
+        Some(oversell) => Err(ProvideError::ModelViolation {
+            details: format!(
+                "Cannot sell {} units of {} on {}, only {} held",
+                oversell.quantity,
+                oversell.ticker,
+                oversell.executed_at.to_rfc3339(),
+                oversell.held
+            ),
+            field: Some(String::from("quantity")),
+        })
+        .context(error::DBProvideError { msg }),
+        None => Ok(()),
+    }
 }
@@ -0,0 +1,1030 @@
+//! Bulk import of currencies and daily prices, from CSV or NDJSON.
+//!
+//! Each row is read and validated on its own: a bad row is reported as failed, and the import
+//! goes on with the next one. Rows are written in batches, each in one transaction. A row whose
+//! key is already stored, with other values, is skipped, updated, or stops the import, depending
+//! on `OnConflict`. When the import stops, the batch of the conflicting row is rolled back, and
+//! the rows after it are not imported. Rows identical to what is stored are always skipped.
+//!
+//! Each row is written in a savepoint, so that a row the store refuses, eg for a constraint,
+//! fails alone. Any other failure of the store, such as a lost connection, rolls back the batch
+//! in progress and stops the import, whose report still covers every row.
+
+use async_graphql::{Enum, SimpleObject};
+use chrono::NaiveDate;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
+use sqlx::postgres::PgConnection;
+use sqlx::Connection;
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::str::FromStr;
+
+use super::error;
+use super::model::PriceBar;
+use super::validate::{self, Violation};
+use crate::db::model::{CurrencyEntity, PriceBarEntity, ProvideError, ProvideResult, ProvideStock};
+
+/// Rows written in each transaction, unless told otherwise.
+pub const BATCH_SIZE: usize = 500;
+/// Largest file imported through GraphQL, in bytes.
+pub const MAX_UPLOAD_SIZE: u64 = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    Ndjson,
+}
+
+impl Format {
+    /// NDJSON for `.ndjson`, `.jsonl` and `.json` files, CSV otherwise.
+    pub fn from_file_name(name: &str) -> Format {
+        let name = name.to_lowercase();
+        if [".ndjson", ".jsonl", ".json"]
+            .iter()
+            .any(|extension| name.ends_with(extension))
+        {
+            Format::Ndjson
+        } else {
+            Format::Csv
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "ndjson" => Ok(Format::Ndjson),
+            _ => Err(format!("Unknown format '{}', expected csv or ndjson", s)),
+        }
+    }
+}
+
+/// What to do with a row whose key is already stored, with other values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Enum, Serialize)]
+pub enum OnConflict {
+    #[default]
+    Skip,
+    Update,
+    Fail,
+}
+
+impl FromStr for OnConflict {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(OnConflict::Skip),
+            "update" => Ok(OnConflict::Update),
+            "fail" => Ok(OnConflict::Fail),
+            _ => Err(format!(
+                "Unknown conflict mode '{}', expected skip, update or fail",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Enum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Inserted,
+    Updated,
+    Skipped,
+    Failed,
+}
+
+/// What became of a row of the file.
+#[derive(Debug, Clone, PartialEq, Serialize, SimpleObject)]
+pub struct RowReport {
+    /// Line of the row in the file
+    pub line: usize,
+    /// Code of the currency, or ticker and date of the price, when the row could be read
+    pub key: Option<String>,
+    pub outcome: Outcome,
+    /// Why the row was skipped or failed
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, SimpleObject)]
+pub struct ImportReport {
+    pub inserted: usize,
+    pub updated: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub rows: Vec<RowReport>,
+    /// Why the import stopped before the end of the file, when the store failed
+    pub aborted: Option<String>,
+}
+
+impl ImportReport {
+    fn push(&mut self, row: RowReport) {
+        match row.outcome {
+            Outcome::Inserted => self.inserted += 1,
+            Outcome::Updated => self.updated += 1,
+            Outcome::Skipped => self.skipped += 1,
+            Outcome::Failed => self.failed += 1,
+        }
+        self.rows.push(row);
+    }
+}
+
+/// The report, and the entities inserted or updated, to announce them.
+pub struct Imported<T> {
+    pub report: ImportReport,
+    pub changed: Vec<(Outcome, T)>,
+    /// The failure of the store which stopped the import, if any
+    pub error: Option<error::Error>,
+}
+
+/// A row of the file, or why it could not be read.
+#[derive(Debug)]
+pub struct Row<T> {
+    pub line: usize,
+    pub record: Result<T, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct CurrencyRecord {
+    pub code: String,
+    pub name: String,
+    pub decimals: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct PriceRecord {
+    pub ticker: String,
+    pub date: NaiveDate,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: i64,
+}
+
+/// Read the rows of a file. CSV files start with a header naming the fields, NDJSON files
+/// hold an object per line.
+///
+/// Only a file that cannot be read at all is an error, a row that cannot be read is returned
+/// with the reason.
+pub fn parse<T: DeserializeOwned>(
+    data: &[u8],
+    format: Format,
+) -> Result<Vec<Row<T>>, error::Error> {
+    match format {
+        Format::Csv => parse_csv(data),
+        Format::Ndjson => parse_ndjson(data),
+    }
+}
+
+fn parse_csv<T: DeserializeOwned>(data: &[u8]) -> Result<Vec<Row<T>>, error::Error> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(data);
+    let headers = reader
+        .headers()
+        .map_err(|err| invalid_file(err.to_string()))?
+        .clone();
+    let mut rows = Vec::new();
+    let mut record = csv::StringRecord::new();
+    loop {
+        match reader.read_record(&mut record) {
+            Ok(false) => break,
+            Ok(true) => rows.push(Row {
+                line: line_of(data, record.position()),
+                record: record
+                    .deserialize(Some(&headers))
+                    .map_err(|err| match err.kind() {
+                        csv::ErrorKind::Deserialize { err, .. } => err.to_string(),
+                        _ => err.to_string(),
+                    }),
+            }),
+            Err(err) if err.is_io_error() => return Err(invalid_file(err.to_string())),
+            Err(err) => rows.push(Row {
+                line: line_of(data, err.position()),
+                record: Err(err.to_string()),
+            }),
+        }
+    }
+    Ok(rows)
+}
+
+/// The line of a record, counted from the bytes: the reader does not count blank lines, and
+/// records start where the previous one ended, before the blank lines.
+fn line_of(data: &[u8], position: Option<&csv::Position>) -> usize {
+    position.map_or(0, |pos| {
+        let start = (pos.byte() as usize).min(data.len());
+        let blank = data[start..]
+            .iter()
+            .take_while(|b| **b == b'\n' || **b == b'\r')
+            .count();
+        data[..start + blank]
+            .iter()
+            .filter(|b| **b == b'\n')
+            .count()
+            + 1
+    })
+}
+
+fn parse_ndjson<T: DeserializeOwned>(data: &[u8]) -> Result<Vec<Row<T>>, error::Error> {
+    let data = std::str::from_utf8(data).map_err(|err| invalid_file(err.to_string()))?;
+    Ok(data
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| Row {
+            line: index + 1,
+            record: serde_json::from_str(line).map_err(|err| err.to_string()),
+        })
+        .collect())
+}
+
+/// Read a whole file, refusing those larger than `max` bytes. This blocks.
+pub fn read_file(read: impl Read, max: u64) -> Result<Vec<u8>, error::Error> {
+    let mut data = Vec::new();
+    read.take(max + 1)
+        .read_to_end(&mut data)
+        .map_err(|err| invalid_file(err.to_string()))?;
+    if data.len() as u64 > max {
+        return Err(invalid_file(format!("must be at most {} bytes", max)));
+    }
+    Ok(data)
+}
+
+fn invalid_file(message: String) -> error::Error {
+    error::Error::ValidationError {
+        violations: vec![Violation {
+            field: String::from("file"),
+            message,
+        }],
+    }
+}
+
+/// The violations of a row, on one line.
+fn violations(err: error::Error) -> String {
+    match err {
+        error::Error::ValidationError { violations } => violations
+            .iter()
+            .map(|violation| format!("{}: {}", violation.field, violation.message))
+            .collect::<Vec<_>>()
+            .join(", "),
+        err => err.to_string(),
+    }
+}
+
+fn row_report(
+    line: usize,
+    key: Option<&str>,
+    outcome: Outcome,
+    message: Option<String>,
+) -> RowReport {
+    RowReport {
+        line,
+        key: key.map(String::from),
+        outcome,
+        message,
+    }
+}
+
+/// Undo the rows of a batch rolled back, and skip the rows which were not looked at.
+fn roll_back<T>(
+    reports: &mut Vec<RowReport>,
+    rest: &[Row<T>],
+    key: impl Fn(&T) -> String,
+    rolled_back: &str,
+    stopped: &str,
+) {
+    for report in reports.iter_mut() {
+        if let Outcome::Inserted | Outcome::Updated = report.outcome {
+            report.outcome = Outcome::Failed;
+            report.message = Some(format!("rolled back, {}", rolled_back));
+        }
+    }
+    reports.extend(rest.iter().map(|row| not_imported(row, &key, stopped)));
+}
+
+fn not_imported<T>(row: &Row<T>, key: impl Fn(&T) -> String, reason: &str) -> RowReport {
+    RowReport {
+        line: row.line,
+        key: row.record.as_ref().ok().map(key),
+        outcome: Outcome::Skipped,
+        message: Some(format!("not imported, {}", reason)),
+    }
+}
+
+/// Why the store refused a row, or the error itself when it is not about the row, eg a lost
+/// connection, which stops the import. As in error messages, the details from the database are
+/// only given when debugging.
+fn row_error(err: ProvideError) -> Result<String, ProvideError> {
+    if let Some(message) = error::violation_message(&err) {
+        return Ok(message);
+    }
+    match err {
+        ProvideError::NotFound => Ok(String::from("not found")),
+        // Invalid data (class 22) or constraint violation (class 23).
+        ProvideError::UnHandledError {
+            source: sqlx::Error::Database(err),
+        } if err
+            .code()
+            .is_some_and(|code| code.starts_with("22") || code.starts_with("23")) =>
+        {
+            let code = err.code().unwrap_or_default();
+            Ok(if error::debug() {
+                format!("rejected by the database ({}): {}", code, err.message())
+            } else {
+                format!("rejected by the database ({})", code)
+            })
+        }
+        err => Err(err),
+    }
+}
+
+fn currency_key(record: &CurrencyRecord) -> String {
+    record.code.clone()
+}
+
+fn price_key(record: &PriceRecord) -> String {
+    format!("{} {}", record.ticker, record.date)
+}
+
+/// What became of a row, when the store did not refuse it.
+enum Written<T> {
+    Changed(Outcome, T),
+    Skipped(&'static str),
+    /// Stored with other values, and the import must stop
+    Conflict,
+}
+
+/// The reports of the rows of a batch, and the entities written.
+struct Batch<T> {
+    reports: Vec<RowReport>,
+    changed: Vec<(Outcome, T)>,
+    /// The line of the row in conflict, when the batch was rolled back because of it
+    conflict: Option<usize>,
+}
+
+impl<T> Default for Batch<T> {
+    fn default() -> Self {
+        Batch {
+            reports: Vec::new(),
+            changed: Vec::new(),
+            conflict: None,
+        }
+    }
+}
+
+impl<T> Default for Imported<T> {
+    fn default() -> Self {
+        Imported {
+            report: ImportReport::default(),
+            changed: Vec::new(),
+            error: None,
+        }
+    }
+}
+
+impl<T> Imported<T> {
+    /// Add the outcome of a batch. When the store failed, the batch was rolled back, and the
+    /// import stops.
+    ///
+    /// Returns why the rows of the next batches are not imported, when the import stops.
+    fn add_batch<R>(
+        &mut self,
+        batch: &[Row<R>],
+        key: fn(&R) -> String,
+        mut outcome: Batch<T>,
+        res: Result<(), error::Error>,
+    ) -> Option<String> {
+        let stopped = match res {
+            Ok(()) => match outcome.conflict {
+                Some(line) => Some(format!("the import stopped at line {}", line)),
+                None => {
+                    self.changed.append(&mut outcome.changed);
+                    None
+                }
+            },
+            Err(err) => {
+                let reason = "the import was aborted";
+                let rest = &batch[outcome.reports.len()..];
+                roll_back(&mut outcome.reports, rest, key, reason, reason);
+                self.report.aborted = Some(err.message());
+                self.error = Some(err);
+                Some(String::from(reason))
+            }
+        };
+        for row in outcome.reports {
+            self.report.push(row);
+        }
+        stopped
+    }
+
+    fn skip_batch<R>(&mut self, batch: &[Row<R>], key: fn(&R) -> String, reason: &str) {
+        for row in batch {
+            self.report.push(not_imported(row, key, reason));
+        }
+    }
+}
+
+/// Import currencies, batch after batch, until the end of the file or a conflict which stops
+/// the import. When the store fails, eg when the connection is lost, the batch in progress is
+/// rolled back, and the import stops with the report so far and the error.
+pub async fn import_currencies(
+    conn: &mut PgConnection,
+    rows: Vec<Row<CurrencyRecord>>,
+    on_conflict: OnConflict,
+    batch_size: usize,
+) -> Imported<CurrencyEntity> {
+    let mut imported = Imported::default();
+    let mut stopped: Option<String> = None;
+
+    for batch in rows.chunks(batch_size.max(1)) {
+        if let Some(reason) = &stopped {
+            imported.skip_batch(batch, currency_key, reason);
+            continue;
+        }
+        let mut outcome = Batch::default();
+        let res = import_currency_batch(conn, batch, on_conflict, &mut outcome).await;
+        stopped = imported.add_batch(batch, currency_key, outcome, res);
+    }
+
+    imported
+}
+
+/// Write a batch of currencies in one transaction, and each row in a savepoint, so that a row
+/// the store refuses fails alone.
+async fn import_currency_batch(
+    conn: &mut PgConnection,
+    batch: &[Row<CurrencyRecord>],
+    on_conflict: OnConflict,
+    outcome: &mut Batch<CurrencyEntity>,
+) -> Result<(), error::Error> {
+    let mut tx = conn.begin().await.context(error::DBTransactionError {
+        msg: "could not initiate transaction",
+    })?;
+
+    for (index, row) in batch.iter().enumerate() {
+        let record = match &row.record {
+            Ok(record) => record,
+            Err(message) => {
+                outcome.reports.push(row_report(
+                    row.line,
+                    None,
+                    Outcome::Failed,
+                    Some(message.clone()),
+                ));
+                continue;
+            }
+        };
+        let key = Some(record.code.as_str());
+        if let Err(err) = validate::add_currency(&record.code, &record.name, record.decimals) {
+            outcome.reports.push(row_report(
+                row.line,
+                key,
+                Outcome::Failed,
+                Some(violations(err)),
+            ));
+            continue;
+        }
+
+        let mut savepoint = tx.begin().await.context(error::DBTransactionError {
+            msg: "could not create savepoint",
+        })?;
+        let written = match write_currency(&mut savepoint, record, on_conflict).await {
+            Ok(written) => {
+                savepoint
+                    .commit()
+                    .await
+                    .context(error::DBTransactionError {
+                        msg: "could not release savepoint",
+                    })?;
+                written
+            }
+            Err(err) => {
+                let message = row_error(err).context(error::DBProvideError {
+                    msg: "Could not import currency",
+                })?;
+                savepoint
+                    .rollback()
+                    .await
+                    .context(error::DBTransactionError {
+                        msg: "could not roll back to savepoint",
+                    })?;
+                let report = row_report(row.line, key, Outcome::Failed, Some(message));
+                outcome.reports.push(report);
+                continue;
+            }
+        };
+
+        match written {
+            Written::Changed(changed, currency) => {
+                outcome
+                    .reports
+                    .push(row_report(row.line, key, changed, None));
+                outcome.changed.push((changed, currency));
+            }
+            Written::Skipped(message) => {
+                let message = Some(String::from(message));
+                outcome
+                    .reports
+                    .push(row_report(row.line, key, Outcome::Skipped, message));
+            }
+            Written::Conflict => {
+                let message = Some(String::from("already exists"));
+                outcome
+                    .reports
+                    .push(row_report(row.line, key, Outcome::Failed, message));
+                roll_back(
+                    &mut outcome.reports,
+                    &batch[index + 1..],
+                    currency_key,
+                    &format!("line {} is in conflict", row.line),
+                    &format!("the import stopped at line {}", row.line),
+                );
+                outcome.conflict = Some(row.line);
+                tx.rollback().await.context(error::DBTransactionError {
+                    msg: "could not roll back transaction",
+                })?;
+                return Ok(());
+            }
+        }
+    }
+
+    tx.commit().await.context(error::DBTransactionError {
+        msg: "could not commit transaction",
+    })
+}
+
+async fn write_currency(
+    conn: &mut PgConnection,
+    record: &CurrencyRecord,
+    on_conflict: OnConflict,
+) -> ProvideResult<Written<CurrencyEntity>> {
+    match conn.find_currency(&record.code).await? {
+        None => {
+            let currency = conn
+                .add_currency(&record.code, &record.name, record.decimals)
+                .await?;
+            Ok(Written::Changed(Outcome::Inserted, currency))
+        }
+        Some(existing) if existing.name == record.name && existing.decimals == record.decimals => {
+            Ok(Written::Skipped("unchanged"))
+        }
+        Some(_) => match on_conflict {
+            OnConflict::Skip => Ok(Written::Skipped("already exists")),
+            OnConflict::Update => {
+                let currency = conn
+                    .update_currency(
+                        &record.code,
+                        Some(record.name.clone()),
+                        Some(record.decimals),
+                    )
+                    .await?;
+                Ok(Written::Changed(Outcome::Updated, currency))
+            }
+            OnConflict::Fail => Ok(Written::Conflict),
+        },
+    }
+}
+
+/// Import daily bars, as currencies are. The securities must already be stored.
+pub async fn import_prices(
+    conn: &mut PgConnection,
+    rows: Vec<Row<PriceRecord>>,
+    on_conflict: OnConflict,
+    batch_size: usize,
+) -> Imported<PriceBarEntity> {
+    let mut imported = Imported::default();
+    let mut stopped: Option<String> = None;
+    let mut securities = HashSet::new();
+
+    for batch in rows.chunks(batch_size.max(1)) {
+        if let Some(reason) = &stopped {
+            imported.skip_batch(batch, price_key, reason);
+            continue;
+        }
+        let mut outcome = Batch::default();
+        let res = import_price_batch(conn, batch, on_conflict, &mut securities, &mut outcome).await;
+        stopped = imported.add_batch(batch, price_key, outcome, res);
+    }
+
+    imported
+}
+
+/// Read the rows of a batch of bars in one transaction, each in a savepoint, then write them
+/// together. When the store refuses some of them, they are written one by one, so that only
+/// those refused fail.
+async fn import_price_batch(
+    conn: &mut PgConnection,
+    batch: &[Row<PriceRecord>],
+    on_conflict: OnConflict,
+    securities: &mut HashSet<String>,
+    outcome: &mut Batch<PriceBarEntity>,
+) -> Result<(), error::Error> {
+    let mut tx = conn.begin().await.context(error::DBTransactionError {
+        msg: "could not initiate transaction",
+    })?;
+    // The bars of the batch are written together, the last row of a key wins.
+    let mut pending: HashMap<(String, NaiveDate), (Outcome, PriceBarEntity)> = HashMap::new();
+
+    for (index, row) in batch.iter().enumerate() {
+        let record = match &row.record {
+            Ok(record) => record,
+            Err(message) => {
+                outcome.reports.push(row_report(
+                    row.line,
+                    None,
+                    Outcome::Failed,
+                    Some(message.clone()),
+                ));
+                continue;
+            }
+        };
+        let key = price_key(record);
+        let key = Some(key.as_str());
+        let bar = PriceBar {
+            date: record.date,
+            open: record.open,
+            high: record.high,
+            low: record.low,
+            close: record.close,
+            volume: record.volume,
+        };
+        if let Err(err) = validate::price_bar(&record.ticker, &bar) {
+            outcome.reports.push(row_report(
+                row.line,
+                key,
+                Outcome::Failed,
+                Some(violations(err)),
+            ));
+            continue;
+        }
+
+        let entity = PriceBarEntity {
+            ticker: record.ticker.clone(),
+            date: record.date,
+            open: record.open,
+            high: record.high,
+            low: record.low,
+            close: record.close,
+            volume: record.volume,
+        };
+        let pending_key = (record.ticker.clone(), record.date);
+        let existing = match pending.get(&pending_key) {
+            Some((_, bar)) => Ok(Some(Some(bar.clone()))),
+            None => {
+                let mut savepoint = tx.begin().await.context(error::DBTransactionError {
+                    msg: "could not create savepoint",
+                })?;
+                match find_price_bar(&mut savepoint, record, securities).await {
+                    Ok(existing) => {
+                        savepoint
+                            .commit()
+                            .await
+                            .context(error::DBTransactionError {
+                                msg: "could not release savepoint",
+                            })?;
+                        Ok(existing)
+                    }
+                    Err(err) => {
+                        let message = row_error(err).context(error::DBProvideError {
+                            msg: "Could not find price bars",
+                        })?;
+                        savepoint
+                            .rollback()
+                            .await
+                            .context(error::DBTransactionError {
+                                msg: "could not roll back to savepoint",
+                            })?;
+                        Err(message)
+                    }
+                }
+            }
+        };
+        let existing = match existing {
+            Ok(Some(existing)) => existing,
+            Ok(None) => {
+                let message = Some(format!("unknown security {}", record.ticker));
+                outcome
+                    .reports
+                    .push(row_report(row.line, key, Outcome::Failed, message));
+                continue;
+            }
+            Err(message) => {
+                let report = row_report(row.line, key, Outcome::Failed, Some(message));
+                outcome.reports.push(report);
+                continue;
+            }
+        };
+        match existing {
+            None => {
+                outcome
+                    .reports
+                    .push(row_report(row.line, key, Outcome::Inserted, None));
+                pending.insert(pending_key, (Outcome::Inserted, entity));
+            }
+            Some(existing) if existing == entity => {
+                let message = Some(String::from("unchanged"));
+                outcome
+                    .reports
+                    .push(row_report(row.line, key, Outcome::Skipped, message));
+            }
+            Some(_) => match on_conflict {
+                OnConflict::Skip => {
+                    let message = Some(String::from("already exists"));
+                    outcome
+                        .reports
+                        .push(row_report(row.line, key, Outcome::Skipped, message));
+                }
+                OnConflict::Update => {
+                    outcome
+                        .reports
+                        .push(row_report(row.line, key, Outcome::Updated, None));
+                    let changed = match pending.get(&pending_key) {
+                        Some((Outcome::Inserted, _)) => Outcome::Inserted,
+                        _ => Outcome::Updated,
+                    };
+                    pending.insert(pending_key, (changed, entity));
+                }
+                OnConflict::Fail => {
+                    let message = Some(String::from("already exists"));
+                    outcome
+                        .reports
+                        .push(row_report(row.line, key, Outcome::Failed, message));
+                    roll_back(
+                        &mut outcome.reports,
+                        &batch[index + 1..],
+                        price_key,
+                        &format!("line {} is in conflict", row.line),
+                        &format!("the import stopped at line {}", row.line),
+                    );
+                    outcome.conflict = Some(row.line);
+                    tx.rollback().await.context(error::DBTransactionError {
+                        msg: "could not roll back transaction",
+                    })?;
+                    return Ok(());
+                }
+            },
+        }
+    }
+
+    let pending = pending.into_values().collect::<Vec<_>>();
+    let bars = pending
+        .iter()
+        .map(|(_, bar)| bar.clone())
+        .collect::<Vec<_>>();
+    if write_price_bars(&mut tx, &bars).await?.is_ok() {
+        outcome.changed.extend(pending);
+    } else {
+        for (changed, bar) in pending {
+            match write_price_bars(&mut tx, std::slice::from_ref(&bar)).await? {
+                Ok(()) => outcome.changed.push((changed, bar)),
+                Err(message) => {
+                    let key = format!("{} {}", bar.ticker, bar.date);
+                    for report in outcome.reports.iter_mut() {
+                        let written =
+                            matches!(report.outcome, Outcome::Inserted | Outcome::Updated);
+                        if written && report.key.as_deref() == Some(key.as_str()) {
+                            report.outcome = Outcome::Failed;
+                            report.message = Some(message.clone());
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    tx.commit().await.context(error::DBTransactionError {
+        msg: "could not commit transaction",
+    })
+}
+
+/// The bar stored for the row, `None` when its security is unknown.
+async fn find_price_bar(
+    conn: &mut PgConnection,
+    record: &PriceRecord,
+    securities: &mut HashSet<String>,
+) -> ProvideResult<Option<Option<PriceBarEntity>>> {
+    if !securities.contains(&record.ticker) {
+        if conn
+            .find_security_by_ticker(&record.ticker)
+            .await?
+            .is_none()
+        {
+            return Ok(None);
+        }
+        securities.insert(record.ticker.clone());
+    }
+    let mut bars = conn
+        .find_price_bars(&record.ticker, record.date, record.date)
+        .await?;
+    Ok(Some(bars.pop()))
+}
+
+/// Write bars in a savepoint, or tell why the store refused them.
+async fn write_price_bars(
+    conn: &mut PgConnection,
+    bars: &[PriceBarEntity],
+) -> Result<Result<(), String>, error::Error> {
+    if bars.is_empty() {
+        return Ok(Ok(()));
+    }
+    let mut savepoint = conn.begin().await.context(error::DBTransactionError {
+        msg: "could not create savepoint",
+    })?;
+    match savepoint.add_price_bars(bars).await {
+        Ok(_) => {
+            savepoint
+                .commit()
+                .await
+                .context(error::DBTransactionError {
+                    msg: "could not release savepoint",
+                })?;
+            Ok(Ok(()))
+        }
+        Err(err) => {
+            let message = row_error(err).context(error::DBProvideError {
+                msg: "Could not add price bars",
+            })?;
+            savepoint
+                .rollback()
+                .await
+                .context(error::DBTransactionError {
+                    msg: "could not roll back to savepoint",
+                })?;
+            Ok(Err(message))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::get_database_url;
+    use sqlx::postgres::PgPoolOptions;
+    use std::time::Duration;
+
+    #[test]
+    fn test_rows_are_read_one_by_one() {
+        let csv = "code,name,decimals\nEUR,Euro,2\nUSD,US Dollar,two\n\n\"JPY\", Yen ,0\n";
+        let rows = parse::<CurrencyRecord>(csv.as_bytes(), Format::Csv).expect("csv");
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0].line, 2);
+        assert_eq!(rows[0].record.as_ref().expect("eur").name, "Euro");
+        assert_eq!(rows[1].line, 3);
+        assert!(rows[1].record.is_err());
+        assert_eq!(rows[2].line, 5);
+        assert_eq!(rows[2].record.as_ref().expect("jpy").name, "Yen");
+
+        let ndjson = "{\"code\":\"EUR\",\"name\":\"Euro\",\"decimals\":2}\n\n{\"code\":\"USD\"}\n";
+        let rows = parse::<CurrencyRecord>(ndjson.as_bytes(), Format::Ndjson).expect("ndjson");
+        assert_eq!(rows.len(), 2);
+        assert!(rows[0].record.is_ok());
+        assert_eq!(rows[1].line, 3);
+        assert!(rows[1].record.is_err());
+
+        assert_eq!(Format::from_file_name("prices.NDJSON"), Format::Ndjson);
+        assert_eq!(Format::from_file_name("currencies.csv"), Format::Csv);
+    }
+
+    #[test]
+    fn test_files_are_read_up_to_a_size() {
+        let data = b"code,name,decimals\nEUR,Euro,2\n";
+
+        assert_eq!(
+            read_file(&data[..], data.len() as u64).expect("file"),
+            data.to_vec()
+        );
+        assert!(matches!(
+            read_file(&data[..], data.len() as u64 - 1),
+            Err(error::Error::ValidationError { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_import_currencies_by_conflict_mode() {
+        let url = get_database_url();
+        let pool = PgPoolOptions::new()
+            .connect_timeout(Duration::new(2, 0))
+            .connect(&url)
+            .await
+            .expect("Database connection");
+        let mut conn = pool.acquire().await.expect("connection");
+        // The batches are nested in this transaction, which is not committed.
+        let mut tx = conn.begin().await.expect("transaction");
+
+        let csv = "code,name,decimals\nQIA,Import A,2\nQIB,Import B,2\nqic,Import C,2\n";
+        let rows = parse(csv.as_bytes(), Format::Csv).expect("csv");
+        let imported = import_currencies(&mut tx, rows, OnConflict::Skip, 2).await;
+        assert!(imported.error.is_none());
+        assert_eq!(imported.report.inserted, 2);
+        assert_eq!(imported.report.failed, 1);
+        assert_eq!(
+            imported.report.rows[2].message.as_deref(),
+            Some("code: 'qic' is not 3 uppercase letters")
+        );
+        assert_eq!(imported.changed.len(), 2);
+
+        let csv = "code,name,decimals\nQIA,Import A,2\nQIB,Import Bee,3\n";
+        let rows = parse(csv.as_bytes(), Format::Csv).expect("csv");
+        let imported = import_currencies(&mut tx, rows, OnConflict::Update, 10).await;
+        let outcomes: Vec<_> = imported.report.rows.iter().map(|row| row.outcome).collect();
+        assert_eq!(outcomes, [Outcome::Skipped, Outcome::Updated]);
+
+        let csv =
+            "code,name,decimals\nQID,Import D,2\nQIE,Import E,2\nQIA,Import Aa,2\nQIF,Import F,2\n";
+        let rows = parse(csv.as_bytes(), Format::Csv).expect("csv");
+        let imported = import_currencies(&mut tx, rows, OnConflict::Fail, 2).await;
+        let outcomes: Vec<_> = imported.report.rows.iter().map(|row| row.outcome).collect();
+        assert_eq!(
+            outcomes,
+            [
+                Outcome::Inserted,
+                Outcome::Inserted,
+                Outcome::Failed,
+                Outcome::Skipped
+            ]
+        );
+        assert!(tx.find_currency("QIE").await.expect("find").is_some());
+        assert!(tx.find_currency("QIF").await.expect("find").is_none());
+        let stored = tx.find_currency("QIA").await.expect("find").expect("QIA");
+        assert_eq!(stored.name, "Import A");
+    }
+
+    #[tokio::test]
+    async fn test_rows_refused_by_the_store_fail_alone_and_failures_stop_the_import() {
+        let url = get_database_url();
+        let pool = PgPoolOptions::new()
+            .connect_timeout(Duration::new(2, 0))
+            .connect(&url)
+            .await
+            .expect("Database connection");
+        let mut conn = pool.acquire().await.expect("connection");
+        let mut tx = conn.begin().await.expect("transaction");
+        // Refuses QIR, as a constraint the validation does not know about would, and fails on QIZ.
+        sqlx::query(
+            r#"CREATE FUNCTION pg_temp.refuse_qir() RETURNS TRIGGER AS $$
+            BEGIN
+              IF NEW.code = 'QIR' THEN
+                RAISE EXCEPTION 'QIR is refused' USING ERRCODE = 'check_violation';
+              ELSIF NEW.code = 'QIZ' THEN
+                RAISE EXCEPTION 'the store is down';
+              END IF;
+              RETURN NEW;
+            END;
+            $$ LANGUAGE plpgsql"#,
+        )
+        .execute(&mut tx)
+        .await
+        .expect("function");
+        sqlx::query(
+            r#"CREATE TRIGGER refuse_qir BEFORE INSERT ON main.currencies
+            FOR EACH ROW EXECUTE FUNCTION pg_temp.refuse_qir()"#,
+        )
+        .execute(&mut tx)
+        .await
+        .expect("trigger");
+
+        let csv = "code,name,decimals\nQIP,Import P,2\nQIR,Import R,2\nQIS,Import S,2\n";
+        let rows = parse(csv.as_bytes(), Format::Csv).expect("csv");
+        let imported = import_currencies(&mut tx, rows, OnConflict::Skip, 10).await;
+
+        assert!(imported.error.is_none());
+        let outcomes: Vec<_> = imported.report.rows.iter().map(|row| row.outcome).collect();
+        assert_eq!(
+            outcomes,
+            [Outcome::Inserted, Outcome::Failed, Outcome::Inserted]
+        );
+        assert_eq!(
+            imported.report.rows[1].message.as_deref(),
+            Some("row is invalid")
+        );
+        assert!(tx.find_currency("QIP").await.expect("find").is_some());
+        assert!(tx.find_currency("QIS").await.expect("find").is_some());
+
+        let csv = "code,name,decimals\nQIT,Import T,2\nQIU,Import U,2\nQIV,Import V,2\nQIZ,Import Z,2\nQIW,Import W,2\n";
+        let rows = parse(csv.as_bytes(), Format::Csv).expect("csv");
+        let imported = import_currencies(&mut tx, rows, OnConflict::Skip, 2).await;
+
+        assert!(imported.error.is_some());
+        assert!(imported.report.aborted.is_some());
+        let outcomes: Vec<_> = imported.report.rows.iter().map(|row| row.outcome).collect();
+        assert_eq!(
+            outcomes,
+            [
+                Outcome::Inserted,
+                Outcome::Inserted,
+                Outcome::Failed,
+                Outcome::Skipped,
+                Outcome::Skipped
+            ]
+        );
+        assert_eq!(imported.changed.len(), 2);
+        assert!(tx.find_currency("QIU").await.expect("find").is_some());
+        assert!(tx.find_currency("QIV").await.expect("find").is_none());
+    }
+}
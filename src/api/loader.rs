@@ -0,0 +1,232 @@
+//! Batched lookups of the objects referenced by other objects.
+//!
+//! Resolving the currency of each security of a list would otherwise cost a connection and a
+//! transaction per security. The loaders collect the keys requested while a query executes, and
+//! look them up with a single query.
+//!
+//! The server gives each request its own loaders, so a value loaded once is reused until the
+//! end of the request, and no longer. The schema holds loaders that do not reuse values, for
+//! subscriptions, whose events may follow changes to them, and for requests executed directly.
+
+use async_graphql::dataloader::{DataLoader, Loader};
+use async_graphql::{ErrorExtensions, FieldError};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use super::model::{Currency, Exchange, Security, Service};
+
+/// The loaders of a request.
+pub struct Loaders {
+    currencies: DataLoader<CurrencyLoader>,
+    exchanges: DataLoader<ExchangeLoader>,
+    securities: DataLoader<SecurityLoader>,
+    caching: Arc<AtomicBool>,
+}
+
+impl Loaders {
+    pub fn new(service: Service) -> Self {
+        let caching = Arc::new(AtomicBool::new(true));
+        Loaders {
+            currencies: DataLoader::new(CurrencyLoader(Cache::new(&service, &caching))),
+            exchanges: DataLoader::new(ExchangeLoader(Cache::new(&service, &caching))),
+            securities: DataLoader::new(SecurityLoader(Cache::new(&service, &caching))),
+            caching,
+        }
+    }
+
+    /// Look up every key again, they are still batched.
+    pub fn disable_caching(&self) {
+        self.caching.store(false, Ordering::Relaxed);
+    }
+
+    pub async fn currency(&self, code: &str) -> Result<Option<Currency>, FieldError> {
+        self.currencies.load_one(String::from(code)).await
+    }
+
+    pub async fn exchange(&self, mic: &str) -> Result<Option<Exchange>, FieldError> {
+        self.exchanges.load_one(String::from(mic)).await
+    }
+
+    pub async fn security(&self, ticker: &str) -> Result<Option<Security>, FieldError> {
+        self.securities.load_one(String::from(ticker)).await
+    }
+}
+
+/// The values loaded so far, with `None` for the keys that were not found.
+struct Cache<V> {
+    service: Service,
+    caching: Arc<AtomicBool>,
+    values: Mutex<HashMap<String, Option<V>>>,
+}
+
+impl<V: Clone> Cache<V> {
+    fn new(service: &Service, caching: &Arc<AtomicBool>) -> Self {
+        Cache {
+            service: service.clone(),
+            caching: caching.clone(),
+            values: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Splits the keys between the values already loaded, and the keys to look up.
+    fn lookup(&self, keys: &[String]) -> (HashMap<String, V>, Vec<String>) {
+        let values = self.values.lock().expect("loader cache");
+        let mut found = HashMap::new();
+        let mut missing = Vec::new();
+        for key in keys {
+            match values.get(key) {
+                Some(Some(value)) => {
+                    found.insert(key.clone(), value.clone());
+                }
+                Some(None) => {}
+                None => missing.push(key.clone()),
+            }
+        }
+        (found, missing)
+    }
+
+    /// Adds the values looked up for the missing keys to the ones found.
+    fn store(
+        &self,
+        missing: Vec<String>,
+        loaded: impl Iterator<Item = (String, V)>,
+        found: &mut HashMap<String, V>,
+    ) {
+        let loaded: HashMap<String, V> = loaded.collect();
+        if self.caching.load(Ordering::Relaxed) {
+            let mut values = self.values.lock().expect("loader cache");
+            for key in missing {
+                values.insert(key.clone(), loaded.get(&key).cloned());
+            }
+        }
+        found.extend(loaded);
+    }
+}
+
+struct CurrencyLoader(Cache<Currency>);
+
+#[async_trait]
+impl Loader<String> for CurrencyLoader {
+    type Value = Currency;
+    type Error = FieldError;
+
+    async fn load(&self, codes: &[String]) -> Result<HashMap<String, Currency>, FieldError> {
+        let (mut found, missing) = self.0.lookup(codes);
+        if !missing.is_empty() {
+            let currencies = self
+                .0
+                .service
+                .find_currencies(&missing)
+                .await
+                .map_err(|e| e.extend())?;
+            let loaded = currencies.into_iter().map(|c| (c.code.clone(), c));
+            self.0.store(missing, loaded, &mut found);
+        }
+        Ok(found)
+    }
+}
+
+struct ExchangeLoader(Cache<Exchange>);
+
+#[async_trait]
+impl Loader<String> for ExchangeLoader {
+    type Value = Exchange;
+    type Error = FieldError;
+
+    async fn load(&self, mics: &[String]) -> Result<HashMap<String, Exchange>, FieldError> {
+        let (mut found, missing) = self.0.lookup(mics);
+        if !missing.is_empty() {
+            let exchanges = self
+                .0
+                .service
+                .find_exchanges(&missing)
+                .await
+                .map_err(|e| e.extend())?;
+            let loaded = exchanges.into_iter().map(|e| (e.mic.clone(), e));
+            self.0.store(missing, loaded, &mut found);
+        }
+        Ok(found)
+    }
+}
+
+struct SecurityLoader(Cache<Security>);
+
+#[async_trait]
+impl Loader<String> for SecurityLoader {
+    type Value = Security;
+    type Error = FieldError;
+
+    async fn load(&self, tickers: &[String]) -> Result<HashMap<String, Security>, FieldError> {
+        let (mut found, missing) = self.0.lookup(tickers);
+        if !missing.is_empty() {
+            let securities = self
+                .0
+                .service
+                .find_securities_by_ticker(&missing)
+                .await
+                .map_err(|e| e.extend())?;
+            let loaded = securities.into_iter().map(|s| (s.ticker.clone(), s));
+            self.0.store(missing, loaded, &mut found);
+        }
+        Ok(found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::model::MockStockService;
+
+    fn currency(code: &str) -> Currency {
+        Currency {
+            code: String::from(code),
+            name: String::from(code),
+            decimals: 2,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_currencies_are_batched_and_cached() {
+        let mut service = MockStockService::new();
+        service
+            .expect_find_currencies()
+            .withf(|codes| {
+                let mut codes = codes.to_vec();
+                codes.sort();
+                codes == ["EUR", "USD", "XXX"]
+            })
+            .times(1)
+            .returning(|_| Ok(vec![currency("EUR"), currency("USD")]));
+        let loaders = Loaders::new(Arc::new(service));
+
+        let (eur, usd, unknown) = futures::join!(
+            loaders.currency("EUR"),
+            loaders.currency("USD"),
+            loaders.currency("XXX")
+        );
+        assert_eq!(eur.expect("eur").map(|c| c.code), Some(String::from("EUR")));
+        assert_eq!(usd.expect("usd").map(|c| c.code), Some(String::from("USD")));
+        assert!(unknown.expect("unknown").is_none());
+
+        // Served from the cache, the mock would fail on a second call.
+        let eur = loaders.currency("EUR").await.expect("eur");
+        assert_eq!(eur.map(|c| c.code), Some(String::from("EUR")));
+        assert!(loaders.currency("XXX").await.expect("unknown").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_without_caching_keys_are_looked_up_again() {
+        let mut service = MockStockService::new();
+        service
+            .expect_find_currencies()
+            .times(2)
+            .returning(|_| Ok(vec![currency("EUR")]));
+        let loaders = Loaders::new(Arc::new(service));
+        loaders.disable_caching();
+
+        loaders.currency("EUR").await.expect("eur");
+        loaders.currency("EUR").await.expect("eur");
+    }
+}
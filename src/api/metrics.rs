@@ -0,0 +1,274 @@
+//! Prometheus metrics of the service.
+//!
+//! GraphQL operations are counted and timed by the `Metrics` extension, provider errors
+//! when they are returned to the client, cache lookups as they are made, and the pool gauges
+//! are read at each scrape.
+
+use async_graphql::extensions::{Extension, ExtensionContext, ExtensionFactory};
+use async_graphql::parser::types::{ExecutableDocument, OperationType};
+use async_graphql::{Request, ServerError, ServerResult};
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge, register_int_gauge_vec,
+    Encoder, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, TextEncoder,
+};
+use sqlx::pool::PoolConnection;
+use sqlx::postgres::{PgPool, Postgres};
+use std::time::Instant;
+
+use crate::db::model::ProvideError;
+
+lazy_static! {
+    static ref OPERATIONS: IntCounterVec = register_int_counter_vec!(
+        "stocks_graphql_operations_total",
+        "GraphQL operations, by operation name and outcome",
+        &["operation", "outcome"]
+    )
+    .unwrap();
+    static ref OPERATION_DURATION: HistogramVec = register_histogram_vec!(
+        "stocks_graphql_operation_duration_seconds",
+        "Duration of GraphQL operations, by operation name and outcome",
+        &["operation", "outcome"]
+    )
+    .unwrap();
+    static ref PROVIDE_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "stocks_provide_errors_total",
+        "Errors from the store returned to clients, by kind",
+        &["kind"]
+    )
+    .unwrap();
+    static ref CACHE_LOOKUPS: IntCounterVec = register_int_counter_vec!(
+        "stocks_cache_lookups_total",
+        "Lookups of reference data in the cache, by entity and outcome (hit or miss)",
+        &["entity", "outcome"]
+    )
+    .unwrap();
+    static ref POOL_SIZE: IntGauge = register_int_gauge!(
+        "stocks_db_pool_connections",
+        "Connections in the database pool"
+    )
+    .unwrap();
+    static ref POOL_IDLE: IntGauge = register_int_gauge!(
+        "stocks_db_pool_idle_connections",
+        "Idle connections in the database pool"
+    )
+    .unwrap();
+    static ref POOL_WAITERS: IntGauge = register_int_gauge!(
+        "stocks_db_pool_waiters",
+        "Tasks waiting for a connection from the database pool"
+    )
+    .unwrap();
+    static ref BUILD_INFO: IntGaugeVec = register_int_gauge_vec!(
+        "stocks_build_info",
+        "Build information, the value is always 1",
+        &["version"]
+    )
+    .unwrap();
+}
+
+/// Count an error from the store returned to a client.
+pub fn record_provide_error(err: &ProvideError) {
+    PROVIDE_ERRORS.with_label_values(&[err.kind()]).inc();
+}
+
+/// Count a lookup of an entity in the cache.
+pub fn record_cache_lookup(entity: &str, hit: bool) {
+    let outcome = if hit { "hit" } else { "miss" };
+    CACHE_LOOKUPS.with_label_values(&[entity, outcome]).inc();
+}
+
+/// Acquire a connection from the pool, counting the tasks waiting for one.
+///
+/// sqlx does not expose its queue of waiters, hence this wrapper around `PgPool::acquire`.
+pub async fn acquire(pool: &PgPool) -> Result<PoolConnection<Postgres>, sqlx::Error> {
+    acquire_counted(pool, &POOL_WAITERS).await
+}
+
+async fn acquire_counted(
+    pool: &PgPool,
+    waiters: &IntGauge,
+) -> Result<PoolConnection<Postgres>, sqlx::Error> {
+    let _waiting = Waiting::new(waiters);
+    pool.acquire().await
+}
+
+/// Counts a waiter until dropped, including when the caller gives up waiting.
+struct Waiting<'a>(&'a IntGauge);
+
+impl<'a> Waiting<'a> {
+    fn new(waiters: &'a IntGauge) -> Self {
+        waiters.inc();
+        Waiting(waiters)
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+/// Render all the metrics in the Prometheus text format.
+pub fn render(pool: &PgPool) -> String {
+    POOL_SIZE.set(i64::from(pool.size()));
+    POOL_IDLE.set(pool.num_idle() as i64);
+    BUILD_INFO
+        .with_label_values(&[env!("CARGO_PKG_VERSION")])
+        .set(1);
+
+    let mut buffer = Vec::new();
+    // Encoding only fails on invalid metric families, which the macros prevent.
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buffer)
+        .expect("metrics encoding");
+    String::from_utf8(buffer).expect("metrics are UTF-8")
+}
+
+/// GraphQL extension counting and timing operations.
+///
+/// Operations without a name are labelled `anonymous`. Subscriptions are not timed, as they
+/// last as long as the client listens.
+pub struct Metrics;
+
+impl ExtensionFactory for Metrics {
+    fn create(&self) -> Box<dyn Extension> {
+        Box::new(MetricsExtension {
+            start: Instant::now(),
+            operation: None,
+            subscription: false,
+            failed: false,
+        })
+    }
+}
+
+struct MetricsExtension {
+    start: Instant,
+    operation: Option<String>,
+    subscription: bool,
+    failed: bool,
+}
+
+#[async_trait::async_trait]
+impl Extension for MetricsExtension {
+    async fn prepare_request(
+        &mut self,
+        _ctx: &ExtensionContext<'_>,
+        request: Request,
+    ) -> ServerResult<Request> {
+        self.start = Instant::now();
+        self.operation = request.operation_name.clone();
+        Ok(request)
+    }
+
+    fn parse_end(&mut self, _ctx: &ExtensionContext<'_>, document: &ExecutableDocument) {
+        let mut operations = document.operations.iter();
+        let operation = match &self.operation {
+            Some(operation) => operations
+                .find(|(name, _)| name.is_some_and(|name| name.as_str() == operation.as_str())),
+            None => operations.next(),
+        };
+        if let Some((name, operation)) = operation {
+            self.subscription = operation.node.ty == OperationType::Subscription;
+            if self.operation.is_none() {
+                self.operation = name.map(|name| name.to_string());
+            }
+        }
+    }
+
+    fn error(&mut self, _ctx: &ExtensionContext<'_>, _err: &ServerError) {
+        self.failed = true;
+    }
+}
+
+// Recorded when the request is done with the extension, whether it got to the execution or
+// failed before.
+impl Drop for MetricsExtension {
+    fn drop(&mut self) {
+        if self.subscription {
+            return;
+        }
+        let operation = self.operation.as_deref().unwrap_or("anonymous");
+        let outcome = if self.failed { "error" } else { "ok" };
+        OPERATIONS.with_label_values(&[operation, outcome]).inc();
+        OPERATION_DURATION
+            .with_label_values(&[operation, outcome])
+            .observe(self.start.elapsed().as_secs_f64());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::auth::{AuthMethod, Permission, Principal};
+    use crate::api::bus::Bus;
+    use crate::api::{error, gql, model};
+    use crate::utils::get_database_url;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_operations_and_errors_are_counted() {
+        let mut service = model::MockStockService::new();
+        service.expect_find_currency().returning(|code| match code {
+            "EUR" => Ok(None),
+            _ => Err(error::Error::DBProvideError {
+                msg: String::from("Could not find currency"),
+                source: ProvideError::NotFound,
+            }),
+        });
+        let schema = gql::schema(Arc::new(service), Bus::default());
+        let request = |query: &str| {
+            Request::new(query).data(Principal {
+                subject: String::from("test"),
+                method: AuthMethod::ApiKey,
+                roles: Vec::new(),
+                permissions: vec![Permission::Read],
+            })
+        };
+
+        let found = schema
+            .execute(request(
+                r#"query metricsFound { findCurrency(code: "EUR") { code } }"#,
+            ))
+            .await;
+        let failed = schema
+            .execute(request(
+                r#"query metricsFailed { findCurrency(code: "XXX") { code } }"#,
+            ))
+            .await;
+        assert!(found.errors.is_empty());
+        assert!(!failed.errors.is_empty());
+
+        let pool = PgPool::connect(&get_database_url()).await.expect("pool");
+        let metrics = render(&pool);
+
+        assert!(metrics.contains(
+            r#"stocks_graphql_operations_total{operation="metricsFound",outcome="ok"} 1"#
+        ));
+        assert!(metrics.contains(
+            r#"stocks_graphql_operations_total{operation="metricsFailed",outcome="error"} 1"#
+        ));
+        assert!(metrics.contains(r#"stocks_provide_errors_total{kind="not_found"}"#));
+        assert!(metrics.contains(&format!(
+            r#"stocks_build_info{{version="{}"}} 1"#,
+            env!("CARGO_PKG_VERSION")
+        )));
+        assert!(metrics.contains("stocks_db_pool_connections"));
+    }
+
+    #[tokio::test]
+    async fn test_waiters_are_counted_until_they_give_up() {
+        let pool = sqlx::postgres::PgPoolOptions::new()
+            .max_connections(1)
+            .connect(&get_database_url())
+            .await
+            .expect("pool");
+        let _held = pool.acquire().await.expect("connection");
+        let waiters = IntGauge::new("waiters", "Waiters").expect("gauge");
+
+        let waiting = acquire_counted(&pool, &waiters);
+        let res = tokio::time::timeout(std::time::Duration::from_millis(50), waiting).await;
+
+        assert!(res.is_err());
+        assert_eq!(waiters.get(), 0);
+    }
+}
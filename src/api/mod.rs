@@ -1,4 +1,14 @@
+pub mod auth;
+pub mod bus;
+pub mod cache;
 pub mod error;
+pub mod fx;
 pub mod gql;
+pub mod health;
 pub mod imp;
+pub mod import;
+pub mod loader;
+pub mod metrics;
 pub mod model;
+pub mod notify;
+pub mod validate;
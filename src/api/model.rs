@@ -1,16 +1,23 @@
 // use juniper::GraphQLObject;
 use async_graphql::*;
 use async_trait::async_trait;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, Utc, Weekday};
+use chrono_tz::Tz;
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use uuid::Uuid;
 // use snafu::ResultExt;
 // use sqlx::Connection;
 
 use super::error;
+use super::gql::{get_loaders_from_context, get_service_from_context};
+use super::import;
 use crate::db::model as db;
+use crate::pnl;
 // use crate::db::model::ProvideStock;
 // use crate::state::State;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Currency {
     pub code: String,
@@ -50,10 +57,795 @@ impl From<db::CurrencyEntity> for Currency {
     }
 }
 
+/// How an entity was changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Enum, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ChangeKind {
+    Added,
+    Updated,
+    Deleted,
+}
+
+/// A currency added, updated or deleted. A deleted currency is given as it was last stored.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, SimpleObject)]
+#[serde(rename_all = "camelCase")]
+pub struct CurrencyChange {
+    pub kind: ChangeKind,
+    pub currency: Currency,
+}
+
+/// The kind of instrument a security represents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Enum, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum AssetClass {
+    Equity,
+    Etf,
+    Fund,
+    Bond,
+    Commodity,
+    Cryptocurrency,
+    Other,
+}
+
+impl From<db::AssetClass> for AssetClass {
+    fn from(asset_class: db::AssetClass) -> Self {
+        match asset_class {
+            db::AssetClass::Equity => AssetClass::Equity,
+            db::AssetClass::Etf => AssetClass::Etf,
+            db::AssetClass::Fund => AssetClass::Fund,
+            db::AssetClass::Bond => AssetClass::Bond,
+            db::AssetClass::Commodity => AssetClass::Commodity,
+            db::AssetClass::Cryptocurrency => AssetClass::Cryptocurrency,
+            db::AssetClass::Other => AssetClass::Other,
+        }
+    }
+}
+
+impl From<AssetClass> for db::AssetClass {
+    fn from(asset_class: AssetClass) -> Self {
+        match asset_class {
+            AssetClass::Equity => db::AssetClass::Equity,
+            AssetClass::Etf => db::AssetClass::Etf,
+            AssetClass::Fund => db::AssetClass::Fund,
+            AssetClass::Bond => db::AssetClass::Bond,
+            AssetClass::Commodity => db::AssetClass::Commodity,
+            AssetClass::Cryptocurrency => db::AssetClass::Cryptocurrency,
+            AssetClass::Other => db::AssetClass::Other,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Security {
+    pub id: Uuid,
+    pub ticker: String,
+    pub isin: String,
+    pub name: String,
+    pub exchange: String,
+    pub currency_code: String,
+    pub asset_class: AssetClass,
+}
+
+#[Object]
+impl Security {
+    async fn id(&self) -> &Uuid {
+        &self.id
+    }
+
+    async fn ticker(&self) -> &String {
+        &self.ticker
+    }
+
+    async fn isin(&self) -> &String {
+        &self.isin
+    }
+
+    async fn name(&self) -> &String {
+        &self.name
+    }
+
+    /// MIC code of the exchange where the security is listed
+    async fn exchange(&self) -> &String {
+        &self.exchange
+    }
+
+    /// The currency the security is traded in
+    async fn currency(&self, context: &Context<'_>) -> FieldResult<Option<Currency>> {
+        let loaders = get_loaders_from_context(context)?;
+        loaders.currency(&self.currency_code).await
+    }
+
+    async fn asset_class(&self) -> &AssetClass {
+        &self.asset_class
+    }
+
+    /// The exchange where the security is listed, if it is registered
+    async fn venue(&self, context: &Context<'_>) -> FieldResult<Option<Exchange>> {
+        let loaders = get_loaders_from_context(context)?;
+        loaders.exchange(&self.exchange).await
+    }
+}
+
+impl From<db::SecurityEntity> for Security {
+    fn from(entity: db::SecurityEntity) -> Self {
+        let db::SecurityEntity {
+            id,
+            ticker,
+            isin,
+            name,
+            exchange,
+            currency,
+            asset_class,
+        } = entity;
+
+        Security {
+            id,
+            ticker,
+            isin,
+            name,
+            exchange,
+            currency_code: currency,
+            asset_class: asset_class.into(),
+        }
+    }
+}
+
+/// The daily trading session of an exchange, in the exchange's local time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, SimpleObject)]
+pub struct TradingHours {
+    pub open: NaiveTime,
+    pub close: NaiveTime,
+}
+
+impl TradingHours {
+    /// Returns true if the local time falls within the session.
+    ///
+    /// A session which closes before it opens is taken to span midnight.
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.open <= self.close {
+            self.open <= time && time < self.close
+        } else {
+            self.open <= time || time < self.close
+        }
+    }
+}
+
+impl From<db::TradingHours> for TradingHours {
+    fn from(hours: db::TradingHours) -> Self {
+        TradingHours {
+            open: hours.open,
+            close: hours.close,
+        }
+    }
+}
+
+impl From<TradingHours> for db::TradingHours {
+    fn from(hours: TradingHours) -> Self {
+        db::TradingHours {
+            open: hours.open,
+            close: hours.close,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Exchange {
+    pub mic: String,
+    pub name: String,
+    pub country: String,
+    pub timezone: String,
+    pub trading_hours: TradingHours,
+    pub currency_code: String,
+}
+
+impl Exchange {
+    /// Returns true if the exchange is in session at the given instant.
+    ///
+    /// Sessions run from Monday to Friday, public holidays are not taken into account.
+    pub fn is_open_at(&self, at: DateTime<Utc>) -> Result<bool, String> {
+        let tz = self.timezone.parse::<Tz>()?;
+        let local = at.with_timezone(&tz);
+        let weekday = !matches!(local.weekday(), Weekday::Sat | Weekday::Sun);
+        Ok(weekday && self.trading_hours.contains(local.time()))
+    }
+}
+
+#[Object]
+impl Exchange {
+    /// ISO 10383 Market Identifier Code
+    async fn mic(&self) -> &String {
+        &self.mic
+    }
+
+    async fn name(&self) -> &String {
+        &self.name
+    }
+
+    /// ISO 3166-1 alpha-2 country code
+    async fn country(&self) -> &String {
+        &self.country
+    }
+
+    /// IANA timezone name
+    async fn timezone(&self) -> &String {
+        &self.timezone
+    }
+
+    async fn trading_hours(&self) -> &TradingHours {
+        &self.trading_hours
+    }
+
+    /// The default currency for securities traded on this exchange
+    async fn currency(&self, context: &Context<'_>) -> FieldResult<Option<Currency>> {
+        let loaders = get_loaders_from_context(context)?;
+        loaders.currency(&self.currency_code).await
+    }
+
+    /// Whether the exchange is in session at the given time (defaults to now)
+    async fn is_open(&self, at: Option<DateTime<Utc>>) -> FieldResult<bool> {
+        self.is_open_at(at.unwrap_or_else(Utc::now))
+            .map_err(FieldError::from)
+    }
+}
+
+impl From<db::ExchangeEntity> for Exchange {
+    fn from(entity: db::ExchangeEntity) -> Self {
+        let db::ExchangeEntity {
+            mic,
+            name,
+            country,
+            timezone,
+            trading_hours,
+            currency,
+        } = entity;
+
+        Exchange {
+            mic,
+            name,
+            country,
+            timezone,
+            trading_hours: trading_hours.into(),
+            currency_code: currency,
+        }
+    }
+}
+
+/// The period covered by each bar of a price series.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Enum, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Interval {
+    #[default]
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+impl Interval {
+    /// The first day of the period containing the date. Weeks start on Monday.
+    pub fn period_start(&self, date: NaiveDate) -> NaiveDate {
+        match self {
+            Interval::Daily => date,
+            Interval::Weekly => {
+                date - Duration::days(i64::from(date.weekday().num_days_from_monday()))
+            }
+            Interval::Monthly => NaiveDate::from_ymd_opt(date.year(), date.month(), 1)
+                .expect("the first day of a month"),
+        }
+    }
+}
+
+/// An OHLCV bar. The date is the first day of the period covered by the bar.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, SimpleObject)]
+pub struct PriceBar {
+    pub date: NaiveDate,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: i64,
+}
+
+impl From<db::PriceBarEntity> for PriceBar {
+    fn from(entity: db::PriceBarEntity) -> Self {
+        let db::PriceBarEntity {
+            date,
+            open,
+            high,
+            low,
+            close,
+            volume,
+            ..
+        } = entity;
+
+        PriceBar {
+            date,
+            open,
+            high,
+            low,
+            close,
+            volume,
+        }
+    }
+}
+
+/// A daily bar stored for a security.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, SimpleObject)]
+#[serde(rename_all = "camelCase")]
+pub struct PriceUpdate {
+    pub ticker: String,
+    pub bar: PriceBar,
+}
+
+/// Aggregate chronologically ordered daily bars into bars of the given interval.
+pub fn aggregate_bars(bars: Vec<PriceBar>, interval: Interval) -> Vec<PriceBar> {
+    let mut aggregated: Vec<PriceBar> = Vec::new();
+    for bar in bars {
+        let start = interval.period_start(bar.date);
+        match aggregated.last_mut() {
+            Some(last) if last.date == start => {
+                last.high = last.high.max(bar.high);
+                last.low = last.low.min(bar.low);
+                last.close = bar.close;
+                last.volume += bar.volume;
+            }
+            _ => aggregated.push(PriceBar { date: start, ..bar }),
+        }
+    }
+    aggregated
+}
+
+/// The price of one unit of the base currency, expressed in the quote currency.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, SimpleObject)]
+pub struct FxRate {
+    pub base: String,
+    pub quote: String,
+    /// The date the rate was observed, which may precede the requested date
+    pub date: NaiveDate,
+    pub rate: f64,
+}
+
+impl From<db::FxRateEntity> for FxRate {
+    fn from(entity: db::FxRateEntity) -> Self {
+        let db::FxRateEntity {
+            base,
+            quote,
+            date,
+            rate,
+        } = entity;
+
+        FxRate {
+            base,
+            quote,
+            date,
+            rate,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Portfolio {
+    pub id: Uuid,
+    pub name: String,
+    pub currency_code: String,
+}
+
+#[Object]
+impl Portfolio {
+    async fn id(&self) -> &Uuid {
+        &self.id
+    }
+
+    async fn name(&self) -> &String {
+        &self.name
+    }
+
+    /// The currency the portfolio is valued in
+    async fn currency(&self, context: &Context<'_>) -> FieldResult<Option<Currency>> {
+        let loaders = get_loaders_from_context(context)?;
+        loaders.currency(&self.currency_code).await
+    }
+
+    /// The ledger of the portfolio, in execution order
+    async fn transactions(&self, context: &Context<'_>) -> FieldResult<Vec<Transaction>> {
+        let service = get_service_from_context(context)?;
+        service
+            .list_transactions(self.id)
+            .await
+            .map_err(|e| e.extend())
+    }
+
+    async fn positions(&self, context: &Context<'_>) -> FieldResult<Vec<Position>> {
+        let service = get_service_from_context(context)?;
+        service.positions(self.id).await.map_err(|e| e.extend())
+    }
+
+    /// Realized and unrealized gains, from the transactions executed on or before the date
+    /// (UTC, default today), valued at the latest price stored on or before that date.
+    async fn performance(
+        &self,
+        context: &Context<'_>,
+        #[graphql(default)] method: CostMethod,
+        as_of: Option<NaiveDate>,
+    ) -> FieldResult<Performance> {
+        let service = get_service_from_context(context)?;
+        let as_of = as_of.unwrap_or_else(|| Utc::now().date_naive());
+        service
+            .performance(self.id, method, as_of)
+            .await
+            .map_err(|e| e.extend())
+    }
+}
+
+impl From<db::PortfolioEntity> for Portfolio {
+    fn from(entity: db::PortfolioEntity) -> Self {
+        let db::PortfolioEntity { id, name, currency } = entity;
+
+        Portfolio {
+            id,
+            name,
+            currency_code: currency,
+        }
+    }
+}
+
+/// The nature of a portfolio transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Enum, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum TransactionKind {
+    Buy,
+    Sell,
+    Deposit,
+    Withdrawal,
+    Fee,
+    Dividend,
+}
+
+impl From<db::TransactionKind> for TransactionKind {
+    fn from(kind: db::TransactionKind) -> Self {
+        match kind {
+            db::TransactionKind::Buy => TransactionKind::Buy,
+            db::TransactionKind::Sell => TransactionKind::Sell,
+            db::TransactionKind::Deposit => TransactionKind::Deposit,
+            db::TransactionKind::Withdrawal => TransactionKind::Withdrawal,
+            db::TransactionKind::Fee => TransactionKind::Fee,
+            db::TransactionKind::Dividend => TransactionKind::Dividend,
+        }
+    }
+}
+
+impl From<TransactionKind> for db::TransactionKind {
+    fn from(kind: TransactionKind) -> Self {
+        match kind {
+            TransactionKind::Buy => db::TransactionKind::Buy,
+            TransactionKind::Sell => db::TransactionKind::Sell,
+            TransactionKind::Deposit => db::TransactionKind::Deposit,
+            TransactionKind::Withdrawal => db::TransactionKind::Withdrawal,
+            TransactionKind::Fee => db::TransactionKind::Fee,
+            TransactionKind::Dividend => db::TransactionKind::Dividend,
+        }
+    }
+}
+
+/// An entry in the ledger of a portfolio. Amounts are in the currency of the portfolio.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, SimpleObject)]
+#[serde(rename_all = "camelCase")]
+pub struct Transaction {
+    pub id: Uuid,
+    pub portfolio_id: Uuid,
+    pub kind: TransactionKind,
+    /// The security traded, or paying the dividend
+    pub ticker: Option<String>,
+    pub executed_at: DateTime<Utc>,
+    /// Units traded (buy and sell only)
+    pub quantity: f64,
+    /// Value of the trade, or cash amount of other transactions, excluding fees
+    pub amount: f64,
+    pub fees: f64,
+}
+
+impl From<db::TransactionEntity> for Transaction {
+    fn from(entity: db::TransactionEntity) -> Self {
+        let db::TransactionEntity {
+            id,
+            portfolio,
+            kind,
+            ticker,
+            executed_at,
+            quantity,
+            amount,
+            fees,
+        } = entity;
+
+        Transaction {
+            id,
+            portfolio_id: portfolio,
+            kind: kind.into(),
+            ticker,
+            executed_at,
+            quantity,
+            amount,
+            fees,
+        }
+    }
+}
+
+/// A transaction to record, see `Transaction`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewTransaction {
+    pub kind: TransactionKind,
+    pub ticker: Option<String>,
+    pub executed_at: DateTime<Utc>,
+    pub quantity: f64,
+    pub amount: f64,
+    pub fees: f64,
+}
+
+/// The holding of a security in a portfolio, derived from the ledger.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Position {
+    pub ticker: String,
+    pub quantity: f64,
+    /// Total cost of the units held, fees included
+    pub cost_basis: f64,
+}
+
+impl Position {
+    /// Cost of a unit held, fees included
+    pub fn unit_cost(&self) -> f64 {
+        self.cost_basis / self.quantity
+    }
+}
+
+#[Object]
+impl Position {
+    async fn ticker(&self) -> &String {
+        &self.ticker
+    }
+
+    async fn security(&self, context: &Context<'_>) -> FieldResult<Option<Security>> {
+        let loaders = get_loaders_from_context(context)?;
+        loaders.security(&self.ticker).await
+    }
+
+    async fn quantity(&self) -> f64 {
+        self.quantity
+    }
+
+    /// Total cost of the units held, fees included
+    async fn cost_basis(&self) -> f64 {
+        self.cost_basis
+    }
+
+    /// Cost of a unit held, fees included
+    async fn average_cost(&self) -> f64 {
+        self.unit_cost()
+    }
+}
+
+impl Transaction {
+    /// The ledger event of the transaction for the P&L engine, if it affects the gains.
+    ///
+    /// Trade fees are added to the cost of a purchase and taken off the proceeds of a sale.
+    pub fn event(&self) -> Option<pnl::Event> {
+        match (self.kind, &self.ticker) {
+            (TransactionKind::Buy, Some(ticker)) => Some(pnl::Event::Buy {
+                ticker: ticker.clone(),
+                quantity: self.quantity,
+                cost: self.amount + self.fees,
+            }),
+            (TransactionKind::Sell, Some(ticker)) => Some(pnl::Event::Sell {
+                ticker: ticker.clone(),
+                quantity: self.quantity,
+                proceeds: self.amount - self.fees,
+            }),
+            (TransactionKind::Dividend, Some(ticker)) => Some(pnl::Event::Dividend {
+                ticker: ticker.clone(),
+                amount: self.amount - self.fees,
+            }),
+            (TransactionKind::Fee, _) => Some(pnl::Event::Fee {
+                amount: self.amount + self.fees,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Derive the open positions from a ledger in execution order, sorted by ticker.
+///
+/// The cost of the units sold is taken at the average cost of the position.
+pub fn positions(transactions: &[Transaction]) -> Vec<Position> {
+    let events = transactions
+        .iter()
+        .filter_map(Transaction::event)
+        .collect::<Vec<_>>();
+    pnl::Ledger::replay(pnl::Method::AverageCost, &events)
+        .holdings()
+        .into_iter()
+        .map(|holding| Position {
+            ticker: holding.ticker,
+            quantity: holding.quantity,
+            cost_basis: holding.cost_basis,
+        })
+        .collect()
+}
+
+/// A sell of more units than held when it is executed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Oversell {
+    pub ticker: String,
+    pub executed_at: DateTime<Utc>,
+    pub quantity: f64,
+    pub held: f64,
+}
+
+/// The first sell of a ledger in execution order exceeding the units held at that point.
+pub fn find_oversell(transactions: &[Transaction]) -> Option<Oversell> {
+    let mut held = std::collections::HashMap::new();
+    for transaction in transactions {
+        if let Some(ticker) = &transaction.ticker {
+            let quantity = held.entry(ticker.as_str()).or_insert(0.0);
+            match transaction.kind {
+                TransactionKind::Buy => *quantity += transaction.quantity,
+                TransactionKind::Sell if transaction.quantity > *quantity + pnl::EPSILON => {
+                    return Some(Oversell {
+                        ticker: ticker.clone(),
+                        executed_at: transaction.executed_at,
+                        quantity: transaction.quantity,
+                        held: *quantity,
+                    })
+                }
+                TransactionKind::Sell => *quantity -= transaction.quantity,
+                _ => {}
+            }
+        }
+    }
+    None
+}
+
+/// How the units sold are matched against the units bought to compute realized gains.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Enum, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum CostMethod {
+    /// First in, first out
+    #[default]
+    Fifo,
+    /// Last in, first out
+    Lifo,
+    /// Units sold at the average cost of the position
+    AverageCost,
+}
+
+impl From<pnl::Method> for CostMethod {
+    fn from(method: pnl::Method) -> Self {
+        match method {
+            pnl::Method::Fifo => CostMethod::Fifo,
+            pnl::Method::Lifo => CostMethod::Lifo,
+            pnl::Method::AverageCost => CostMethod::AverageCost,
+        }
+    }
+}
+
+impl From<CostMethod> for pnl::Method {
+    fn from(method: CostMethod) -> Self {
+        match method {
+            CostMethod::Fifo => pnl::Method::Fifo,
+            CostMethod::Lifo => pnl::Method::Lifo,
+            CostMethod::AverageCost => pnl::Method::AverageCost,
+        }
+    }
+}
+
+/// The gains on a security of a portfolio, in the currency of the portfolio.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, SimpleObject)]
+#[serde(rename_all = "camelCase")]
+pub struct SecurityPerformance {
+    pub ticker: String,
+    /// Units held, zero for a closed position
+    pub quantity: f64,
+    /// Cost of the units held, fees included
+    pub cost_basis: f64,
+    pub realized: f64,
+    pub dividends: f64,
+    /// Value of the units held at the latest price, if a price is available
+    pub market_value: Option<f64>,
+    /// Market value less cost basis, if a price is available
+    pub unrealized: Option<f64>,
+}
+
+impl From<pnl::SecurityPerformance> for SecurityPerformance {
+    fn from(performance: pnl::SecurityPerformance) -> Self {
+        let pnl::SecurityPerformance {
+            ticker,
+            quantity,
+            cost_basis,
+            realized,
+            dividends,
+            market_value,
+            unrealized,
+        } = performance;
+
+        SecurityPerformance {
+            ticker,
+            quantity,
+            cost_basis,
+            realized,
+            dividends,
+            market_value,
+            unrealized,
+        }
+    }
+}
+
+/// The gains of a portfolio at a date, in the currency of the portfolio.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, SimpleObject)]
+#[serde(rename_all = "camelCase")]
+pub struct Performance {
+    pub method: CostMethod,
+    pub as_of: NaiveDate,
+    pub realized: f64,
+    /// Unrealized gains of the securities for which a price is available
+    pub unrealized: f64,
+    pub dividends: f64,
+    /// Fees not attached to a trade
+    pub fees: f64,
+    /// Realized and unrealized gains, plus dividends, less fees
+    pub total: f64,
+    pub securities: Vec<SecurityPerformance>,
+}
+
+impl Performance {
+    pub fn new(performance: pnl::Performance, as_of: NaiveDate) -> Self {
+        let total = performance.total();
+        let pnl::Performance {
+            method,
+            realized,
+            unrealized,
+            dividends,
+            fees,
+            securities,
+        } = performance;
+
+        Performance {
+            method: method.into(),
+            as_of,
+            realized,
+            unrealized,
+            dividends,
+            fees,
+            total,
+            securities: securities
+                .into_iter()
+                .map(SecurityPerformance::from)
+                .collect(),
+        }
+    }
+}
+
+/// A page of a list, and the length of the whole list.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total_count: i64,
+}
+
+/// The service, shared by the schema and the loaders of the requests.
+pub type Service = Arc<dyn StockService + Send + Sync>;
+
 #[mockall::automock]
 #[async_trait]
 pub trait StockService {
     async fn list_currencies(&self) -> Result<Vec<Currency>, error::Error>;
+    async fn search_currencies(
+        &self,
+        filter: db::CurrencyFilter,
+        sort: db::CurrencySortKey,
+        descending: bool,
+        page: db::Pagination,
+    ) -> Result<Page<Currency>, error::Error>;
     async fn add_currency(
         &self,
         code: &str,
@@ -61,4 +853,315 @@ pub trait StockService {
         decimals: i32,
     ) -> Result<Currency, error::Error>;
     async fn find_currency(&self, code: &str) -> Result<Option<Currency>, error::Error>;
+    async fn find_currencies(&self, codes: &[String]) -> Result<Vec<Currency>, error::Error>;
+    async fn update_currency(
+        &self,
+        code: &str,
+        name: Option<String>,
+        decimals: Option<i32>,
+    ) -> Result<Currency, error::Error>;
+    async fn delete_currency(&self, code: &str) -> Result<Currency, error::Error>;
+    async fn import_currencies(
+        &self,
+        data: Vec<u8>,
+        format: import::Format,
+        on_conflict: import::OnConflict,
+    ) -> Result<import::ImportReport, error::Error>;
+    async fn list_securities(&self) -> Result<Vec<Security>, error::Error>;
+    async fn add_security(
+        &self,
+        ticker: &str,
+        isin: &str,
+        name: &str,
+        exchange: &str,
+        currency: &str,
+        asset_class: AssetClass,
+    ) -> Result<Security, error::Error>;
+    async fn find_security_by_ticker(&self, ticker: &str)
+        -> Result<Option<Security>, error::Error>;
+    async fn find_security_by_isin(&self, isin: &str) -> Result<Option<Security>, error::Error>;
+    async fn find_securities_by_ticker(
+        &self,
+        tickers: &[String],
+    ) -> Result<Vec<Security>, error::Error>;
+    async fn list_exchanges(&self) -> Result<Vec<Exchange>, error::Error>;
+    async fn add_exchange(
+        &self,
+        mic: &str,
+        name: &str,
+        country: &str,
+        timezone: &str,
+        trading_hours: TradingHours,
+        currency: &str,
+    ) -> Result<Exchange, error::Error>;
+    async fn find_exchange(&self, mic: &str) -> Result<Option<Exchange>, error::Error>;
+    async fn find_exchanges(&self, mics: &[String]) -> Result<Vec<Exchange>, error::Error>;
+    async fn update_exchange(
+        &self,
+        mic: &str,
+        name: Option<String>,
+        country: Option<String>,
+        timezone: Option<String>,
+        trading_hours: Option<TradingHours>,
+        currency: Option<String>,
+    ) -> Result<Exchange, error::Error>;
+    async fn delete_exchange(&self, mic: &str) -> Result<Exchange, error::Error>;
+    async fn add_price_bars(&self, ticker: &str, bars: Vec<PriceBar>) -> Result<u64, error::Error>;
+    async fn price_history(
+        &self,
+        ticker: &str,
+        from: NaiveDate,
+        to: NaiveDate,
+        interval: Interval,
+    ) -> Result<Vec<PriceBar>, error::Error>;
+    async fn add_fx_rate(
+        &self,
+        base: &str,
+        quote: &str,
+        date: NaiveDate,
+        rate: f64,
+    ) -> Result<FxRate, error::Error>;
+    async fn fx_rate(
+        &self,
+        base: &str,
+        quote: &str,
+        date: NaiveDate,
+        pivot: &str,
+    ) -> Result<Option<FxRate>, error::Error>;
+    async fn convert(
+        &self,
+        amount: f64,
+        from: &str,
+        to: &str,
+        date: NaiveDate,
+        pivot: &str,
+    ) -> Result<Option<f64>, error::Error>;
+    async fn list_portfolios(&self) -> Result<Vec<Portfolio>, error::Error>;
+    async fn add_portfolio(&self, name: &str, currency: &str) -> Result<Portfolio, error::Error>;
+    async fn find_portfolio(&self, id: Uuid) -> Result<Option<Portfolio>, error::Error>;
+    async fn add_transaction(
+        &self,
+        portfolio: Uuid,
+        transaction: NewTransaction,
+    ) -> Result<Transaction, error::Error>;
+    async fn list_transactions(&self, portfolio: Uuid) -> Result<Vec<Transaction>, error::Error>;
+    async fn delete_transaction(&self, id: Uuid) -> Result<Transaction, error::Error>;
+    async fn positions(&self, portfolio: Uuid) -> Result<Vec<Position>, error::Error>;
+    async fn performance(
+        &self,
+        portfolio: Uuid,
+        method: CostMethod,
+        as_of: NaiveDate,
+    ) -> Result<Performance, error::Error>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn euronext() -> Exchange {
+        Exchange {
+            mic: String::from("XPAR"),
+            name: String::from("Euronext Paris"),
+            country: String::from("FR"),
+            timezone: String::from("Europe/Paris"),
+            trading_hours: TradingHours {
+                open: NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
+                close: NaiveTime::from_hms_opt(17, 30, 0).unwrap(),
+            },
+            currency_code: String::from("EUR"),
+        }
+    }
+
+    #[test]
+    fn test_exchange_is_open() {
+        let exchange = euronext();
+        // Wednesday 2021-03-17, 10:00 in Paris (UTC+1)
+        assert!(exchange
+            .is_open_at(Utc.with_ymd_and_hms(2021, 3, 17, 9, 0, 0).unwrap())
+            .unwrap());
+        // Same day, 17:30 in Paris: the session just closed
+        assert!(!exchange
+            .is_open_at(Utc.with_ymd_and_hms(2021, 3, 17, 16, 30, 0).unwrap())
+            .unwrap());
+        // Saturday
+        assert!(!exchange
+            .is_open_at(Utc.with_ymd_and_hms(2021, 3, 20, 10, 0, 0).unwrap())
+            .unwrap());
+    }
+
+    #[test]
+    fn test_overnight_trading_hours() {
+        let hours = TradingHours {
+            open: NaiveTime::from_hms_opt(18, 0, 0).unwrap(),
+            close: NaiveTime::from_hms_opt(17, 0, 0).unwrap(),
+        };
+        assert!(hours.contains(NaiveTime::from_hms_opt(2, 0, 0).unwrap()));
+        assert!(!hours.contains(NaiveTime::from_hms_opt(17, 30, 0).unwrap()));
+    }
+
+    fn day(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn bar(date: NaiveDate, open: f64, high: f64, low: f64, close: f64) -> PriceBar {
+        PriceBar {
+            date,
+            open,
+            high,
+            low,
+            close,
+            volume: 100,
+        }
+    }
+
+    #[test]
+    fn test_aggregate_weekly_bars() {
+        // Thursday 2021-03-11 to Tuesday 2021-03-16
+        let bars = vec![
+            bar(day(2021, 3, 11), 10.0, 11.0, 9.0, 10.5),
+            bar(day(2021, 3, 12), 10.5, 13.0, 10.0, 12.0),
+            bar(day(2021, 3, 15), 12.0, 12.5, 8.0, 9.0),
+            bar(day(2021, 3, 16), 9.0, 10.0, 8.5, 9.5),
+        ];
+
+        let weekly = aggregate_bars(bars, Interval::Weekly);
+
+        assert_eq!(
+            weekly,
+            vec![
+                PriceBar {
+                    volume: 200,
+                    ..bar(day(2021, 3, 8), 10.0, 13.0, 9.0, 12.0)
+                },
+                PriceBar {
+                    volume: 200,
+                    ..bar(day(2021, 3, 15), 12.0, 12.5, 8.0, 9.5)
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_aggregate_monthly_bars() {
+        let bars = vec![
+            bar(day(2021, 2, 26), 10.0, 11.0, 9.0, 10.5),
+            bar(day(2021, 3, 1), 10.5, 13.0, 10.0, 12.0),
+            bar(day(2021, 3, 31), 12.0, 12.5, 8.0, 9.0),
+        ];
+
+        let monthly = aggregate_bars(bars.clone(), Interval::Monthly);
+
+        assert_eq!(monthly.len(), 2);
+        assert_eq!(monthly[0].date, day(2021, 2, 1));
+        assert_eq!(monthly[1].date, day(2021, 3, 1));
+        assert_eq!(monthly[1].close, 9.0);
+        assert_eq!(monthly[1].low, 8.0);
+
+        assert_eq!(aggregate_bars(bars.clone(), Interval::Daily), bars);
+    }
+
+    fn transaction(kind: TransactionKind, ticker: &str, quantity: f64, amount: f64) -> Transaction {
+        Transaction {
+            id: Uuid::new_v4(),
+            portfolio_id: Uuid::nil(),
+            kind,
+            ticker: Some(String::from(ticker)),
+            executed_at: Utc.with_ymd_and_hms(2021, 3, 1, 9, 0, 0).unwrap(),
+            quantity,
+            amount,
+            fees: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_positions_average_cost() {
+        let ledger = vec![
+            transaction(TransactionKind::Buy, "AAPL", 10.0, 1000.0),
+            Transaction {
+                fees: 10.0,
+                ..transaction(TransactionKind::Buy, "AAPL", 10.0, 1190.0)
+            },
+            transaction(TransactionKind::Dividend, "AAPL", 0.0, 5.0),
+            transaction(TransactionKind::Sell, "AAPL", 5.0, 600.0),
+            transaction(TransactionKind::Buy, "MSFT", 1.0, 200.0),
+            transaction(TransactionKind::Sell, "MSFT", 1.0, 210.0),
+        ];
+
+        let positions = positions(&ledger);
+
+        assert_eq!(
+            positions,
+            vec![Position {
+                ticker: String::from("AAPL"),
+                quantity: 15.0,
+                cost_basis: 1650.0,
+            }]
+        );
+        assert_eq!(positions[0].unit_cost(), 110.0);
+    }
+
+    #[test]
+    fn test_sells_are_checked_against_the_units_held_when_executed() {
+        let ledger = vec![
+            transaction(TransactionKind::Buy, "AAPL", 10.0, 1000.0),
+            transaction(TransactionKind::Sell, "AAPL", 4.0, 500.0),
+            transaction(TransactionKind::Sell, "AAPL", 6.0, 700.0),
+        ];
+        assert_eq!(find_oversell(&ledger), None);
+
+        // A sell back-dated before the buy, then the ledger once the buy is deleted.
+        let early = Transaction {
+            executed_at: Utc.with_ymd_and_hms(2021, 2, 1, 9, 0, 0).unwrap(),
+            ..transaction(TransactionKind::Sell, "AAPL", 1.0, 100.0)
+        };
+        let backdated = [vec![early.clone()], ledger.clone()].concat();
+        assert_eq!(
+            find_oversell(&backdated),
+            Some(Oversell {
+                ticker: String::from("AAPL"),
+                executed_at: early.executed_at,
+                quantity: 1.0,
+                held: 0.0,
+            })
+        );
+        assert_eq!(find_oversell(&ledger[1..]).map(|o| o.quantity), Some(4.0));
+    }
+
+    #[test]
+    fn test_transaction_events_include_fees() {
+        let buy = Transaction {
+            fees: 5.0,
+            ..transaction(TransactionKind::Buy, "AAPL", 10.0, 1000.0)
+        };
+        let sell = Transaction {
+            fees: 5.0,
+            ..transaction(TransactionKind::Sell, "AAPL", 10.0, 1100.0)
+        };
+        let deposit = Transaction {
+            ticker: None,
+            ..transaction(TransactionKind::Deposit, "AAPL", 0.0, 5000.0)
+        };
+
+        let events = [buy, sell, deposit]
+            .iter()
+            .filter_map(Transaction::event)
+            .collect::<Vec<_>>();
+
+        let performance =
+            pnl::Ledger::replay(pnl::Method::Fifo, &events).performance(&Default::default());
+
+        assert_eq!(events.len(), 2);
+        assert_eq!(performance.realized, 90.0);
+    }
+
+    #[test]
+    fn test_exchange_invalid_timezone() {
+        let mut exchange = euronext();
+        exchange.timezone = String::from("Mars/Olympus");
+        assert!(exchange
+            .is_open_at(Utc.with_ymd_and_hms(2021, 3, 17, 9, 0, 0).unwrap())
+            .is_err());
+    }
 }
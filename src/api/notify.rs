@@ -0,0 +1,249 @@
+//! Relay of the changes announced by Postgres.
+//!
+//! Triggers on the main tables call `pg_notify` with a JSON payload, so changes made by any
+//! client of the `api` functions reach the service. The payload carries the
+//! `application_name` of the writer: the changes made by the service itself are already
+//! published on the bus when committed, and are not relayed twice.
+
+use serde::Deserialize;
+use sqlx::postgres::{PgListener, PgPool};
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+
+use super::bus::{Bus, Event};
+use super::model;
+use crate::db::model::{CurrencyEntity, PriceBarEntity, TransactionEntity};
+
+/// Delay before the first attempt to reconnect, doubled after each failure.
+const MIN_RETRY_DELAY: Duration = Duration::from_millis(500);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// A change announced by the database.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "event", content = "data", rename_all = "snake_case")]
+pub enum Change {
+    CurrencyAdded(CurrencyEntity),
+    CurrencyUpdated(CurrencyEntity),
+    CurrencyDeleted(CurrencyEntity),
+    PriceInserted(PriceBarEntity),
+    PriceUpdated(PriceBarEntity),
+    TransactionRecorded(TransactionEntity),
+}
+
+/// The payload of a notification.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notification {
+    /// The `application_name` of the connection which made the change
+    pub origin: String,
+    pub change: Change,
+}
+
+impl Notification {
+    /// Decode `{ "origin": ..., "event": ..., "data": ... }`.
+    pub fn decode(payload: &str) -> Result<Self, serde_json::Error> {
+        // The change is extracted from a value rather than with `#[serde(flatten)]`, which
+        // does not deserialize numbers when serde_json has arbitrary precision enabled.
+        let mut value: serde_json::Value = serde_json::from_str(payload)?;
+        let origin = value
+            .as_object_mut()
+            .and_then(|object| object.remove("origin"))
+            .unwrap_or_default();
+        Ok(Notification {
+            origin: String::deserialize(origin)?,
+            change: Change::deserialize(value)?,
+        })
+    }
+}
+
+impl From<Change> for Event {
+    fn from(change: Change) -> Self {
+        let currency_change = |kind, entity| {
+            Event::CurrencyChanged(model::CurrencyChange {
+                kind,
+                currency: model::Currency::from(entity),
+            })
+        };
+        match change {
+            Change::CurrencyAdded(entity) => currency_change(model::ChangeKind::Added, entity),
+            Change::CurrencyUpdated(entity) => currency_change(model::ChangeKind::Updated, entity),
+            Change::CurrencyDeleted(entity) => currency_change(model::ChangeKind::Deleted, entity),
+            Change::PriceInserted(entity) | Change::PriceUpdated(entity) => {
+                Event::PriceUpdated(model::PriceUpdate {
+                    ticker: entity.ticker.clone(),
+                    bar: model::PriceBar::from(entity),
+                })
+            }
+            Change::TransactionRecorded(entity) => {
+                Event::TransactionRecorded(model::Transaction::from(entity))
+            }
+        }
+    }
+}
+
+/// The channel the triggers notify on, from the `stocks.notify_channel` setting of the
+/// database, as seen by a connection of the pool.
+async fn trigger_channel(pool: &PgPool) -> Result<String, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT COALESCE(NULLIF(current_setting('stocks.notify_channel', true), ''), 'stocks_changes')",
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Warn when the triggers notify on another channel than the one listened to: the changes
+/// made outside the service would then never be relayed.
+async fn check_channel(pool: &PgPool, channel: &str) {
+    match trigger_channel(pool).await {
+        Ok(trigger) if trigger == channel => {}
+        Ok(trigger) => warn!(
+            "Listening on {} but changes are notified on {}, set database.notify_channel or stocks.notify_channel so that they match",
+            channel, trigger
+        ),
+        Err(err) => warn!("Could not read stocks.notify_channel: {}", err),
+    }
+}
+
+async fn listen(pool: &PgPool, channel: &str) -> Result<PgListener, sqlx::Error> {
+    let mut listener = PgListener::connect_with(pool).await?;
+    listener.listen(channel).await?;
+    Ok(listener)
+}
+
+/// Publish on the bus the changes notified on the channel by connections other than
+/// `origin`.
+///
+/// This runs until the task is dropped. The connection is re-established whenever it is
+/// lost; the changes notified in the meantime are missed, and the gap is logged.
+pub async fn relay(pool: PgPool, channel: String, origin: String, bus: Bus) {
+    let mut listener: Option<PgListener> = None;
+    let mut lost_at: Option<Instant> = None;
+    let mut delay = MIN_RETRY_DELAY;
+
+    loop {
+        let connection = match listener.as_mut() {
+            Some(connection) => connection,
+            None => match listen(&pool, &channel).await {
+                Ok(connection) => {
+                    match lost_at.take() {
+                        Some(lost_at) => warn!(
+                            "Listening on {} again, changes notified in the last {:?} were missed",
+                            channel,
+                            lost_at.elapsed()
+                        ),
+                        None => info!("Listening on {}", channel),
+                    }
+                    check_channel(&pool, &channel).await;
+                    delay = MIN_RETRY_DELAY;
+                    listener.insert(connection)
+                }
+                Err(err) => {
+                    error!("Could not listen on {}: {}", channel, err);
+                    lost_at.get_or_insert_with(Instant::now);
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(MAX_RETRY_DELAY);
+                    continue;
+                }
+            },
+        };
+
+        match connection.try_recv().await {
+            Ok(Some(notification)) => match Notification::decode(notification.payload()) {
+                Ok(notification) if notification.origin == origin => {}
+                Ok(notification) => bus.publish(Event::from(notification.change)),
+                Err(err) => warn!(
+                    "Could not decode notification '{}': {}",
+                    notification.payload(),
+                    err
+                ),
+            },
+            Ok(None) => {
+                warn!("Lost connection listening on {}", channel);
+                lost_at = Some(Instant::now());
+                listener = None;
+            }
+            Err(err) => {
+                error!("Could not receive notification on {}: {}", channel, err);
+                lost_at = Some(Instant::now());
+                listener = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{NaiveDate, TimeZone, Utc};
+    use uuid::Uuid;
+
+    // The payloads below are the ones produced by the triggers.
+
+    #[test]
+    fn test_decode_currency_change() {
+        let payload = r#"{"origin" : "psql", "event" : "currency_deleted", "data" : {"code" : "EUR", "name" : "Euro", "decimals" : 2}}"#;
+
+        let notification = Notification::decode(payload).expect("notification");
+
+        assert_eq!(notification.origin, "psql");
+        assert_eq!(
+            Event::from(notification.change),
+            Event::CurrencyChanged(model::CurrencyChange {
+                kind: model::ChangeKind::Deleted,
+                currency: model::Currency {
+                    code: String::from("EUR"),
+                    name: String::from("Euro"),
+                    decimals: 2,
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn test_decode_price_inserted() {
+        let payload = r#"{"origin" : "psql", "event" : "price_inserted", "data" : {"ticker" : "AAPL", "date" : "2021-03-01", "open" : 1, "high" : 2, "low" : 0.5, "close" : 1.5, "volume" : 100}}"#;
+
+        let notification = Notification::decode(payload).expect("notification");
+
+        assert_eq!(
+            notification.change,
+            Change::PriceInserted(PriceBarEntity {
+                ticker: String::from("AAPL"),
+                date: NaiveDate::from_ymd_opt(2021, 3, 1).unwrap(),
+                open: 1.0,
+                high: 2.0,
+                low: 0.5,
+                close: 1.5,
+                volume: 100,
+            })
+        );
+    }
+
+    #[test]
+    fn test_decode_transaction_recorded() {
+        let payload = r#"{"origin" : "psql", "event" : "transaction_recorded", "data" : {"id" : "8715155a-897a-445e-9988-b98cd1795aed", "portfolio" : "6d4cc117-54a0-4060-a638-1a8436f177bd", "kind" : "buy", "ticker" : "AAPL", "executed_at" : "2021-03-01T09:00:00+00:00", "quantity" : 1, "amount" : 1.5, "fees" : 0.1}}"#;
+
+        let notification = Notification::decode(payload).expect("notification");
+
+        match Event::from(notification.change) {
+            Event::TransactionRecorded(transaction) => {
+                assert_eq!(
+                    transaction.portfolio_id,
+                    Uuid::parse_str("6d4cc117-54a0-4060-a638-1a8436f177bd").unwrap()
+                );
+                assert_eq!(transaction.kind, model::TransactionKind::Buy);
+                assert_eq!(
+                    transaction.executed_at,
+                    Utc.with_ymd_and_hms(2021, 3, 1, 9, 0, 0).unwrap()
+                );
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+    }
+
+    #[test]
+    fn test_decode_unknown_event() {
+        let payload = r#"{"origin" : "psql", "event" : "security_added", "data" : {}}"#;
+
+        assert!(Notification::decode(payload).is_err());
+    }
+}
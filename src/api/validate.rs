@@ -0,0 +1,561 @@
+//! Validation of the inputs of the mutations, and of the cursors of the queries, before they
+//! reach the store.
+//!
+//! Each function checks all the fields of an operation, and reports every violation at once,
+//! by field. Field names are those of the GraphQL inputs.
+
+use chrono_tz::Tz;
+
+use super::error::Error;
+use super::model::{NewTransaction, PriceBar, TradingHours, TransactionKind};
+use crate::db::model::{CurrencySortKey, Keyset};
+
+/// Longest name of an entity, as stored.
+const MAX_NAME_LENGTH: usize = 255;
+/// Longest ticker, as stored.
+const MAX_TICKER_LENGTH: usize = 32;
+const MAX_DECIMALS: i32 = 8;
+
+/// A field with an invalid value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub field: String,
+    pub message: String,
+}
+
+/// The violations found so far.
+#[derive(Debug, Default)]
+struct Violations(Vec<Violation>);
+
+impl Violations {
+    fn check(&mut self, field: &str, res: Result<(), String>) -> &mut Self {
+        if let Err(message) = res {
+            self.0.push(Violation {
+                field: String::from(field),
+                message,
+            });
+        }
+        self
+    }
+
+    fn check_opt<T>(
+        &mut self,
+        field: &str,
+        value: Option<T>,
+        check: impl FnOnce(T) -> Result<(), String>,
+    ) -> &mut Self {
+        match value {
+            Some(value) => self.check(field, check(value)),
+            None => self,
+        }
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::ValidationError {
+                violations: std::mem::take(&mut self.0),
+            })
+        }
+    }
+}
+
+/// Three uppercase letters, as ISO 4217 currency codes.
+pub fn currency_code(code: &str) -> Result<(), String> {
+    if code.len() == 3 && code.chars().all(|c| c.is_ascii_uppercase()) {
+        Ok(())
+    } else {
+        Err(format!("'{}' is not 3 uppercase letters", code))
+    }
+}
+
+pub fn name(name: &str) -> Result<(), String> {
+    let length = name.trim().chars().count();
+    if length == 0 {
+        Err(String::from("must not be blank"))
+    } else if name.chars().count() > MAX_NAME_LENGTH {
+        Err(format!("must be at most {} characters", MAX_NAME_LENGTH))
+    } else {
+        Ok(())
+    }
+}
+
+pub fn decimals(decimals: i32) -> Result<(), String> {
+    if (0..=MAX_DECIMALS).contains(&decimals) {
+        Ok(())
+    } else {
+        Err(format!("must be between 0 and {}", MAX_DECIMALS))
+    }
+}
+
+/// Uppercase letters and digits, possibly with a `.` or `-` suffix, eg `MC.PA` or `BRK-B`.
+pub fn ticker(ticker: &str) -> Result<(), String> {
+    let valid = !ticker.is_empty()
+        && ticker.len() <= MAX_TICKER_LENGTH
+        && ticker
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '.' || c == '-')
+        && ticker.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && ticker.ends_with(|c: char| c.is_ascii_alphanumeric());
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "'{}' is not up to {} uppercase letters, digits, '.' or '-'",
+            ticker, MAX_TICKER_LENGTH
+        ))
+    }
+}
+
+/// ISO 6166: a country code, 9 alphanumeric characters, and a Luhn check digit computed
+/// on the digits of the other characters (A = 10, ..., Z = 35).
+pub fn isin(isin: &str) -> Result<(), String> {
+    let bytes = isin.as_bytes();
+    let well_formed = bytes.len() == 12
+        && bytes[..2].iter().all(u8::is_ascii_uppercase)
+        && bytes[2..11]
+            .iter()
+            .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit())
+        && bytes[11].is_ascii_digit();
+    if !well_formed {
+        return Err(format!(
+            "'{}' is not a country code, 9 letters or digits, and a check digit",
+            isin
+        ));
+    }
+
+    let digits = isin[..11]
+        .chars()
+        .flat_map(|c| {
+            let value = c.to_digit(36).unwrap_or_default();
+            if value < 10 {
+                vec![value]
+            } else {
+                vec![value / 10, value % 10]
+            }
+        })
+        .collect::<Vec<_>>();
+    // Luhn, doubling from the rightmost digit, the check digit being left out.
+    let sum = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(index, &digit)| {
+            if index % 2 == 0 {
+                let doubled = digit * 2;
+                doubled / 10 + doubled % 10
+            } else {
+                digit
+            }
+        })
+        .sum::<u32>();
+    let check = (10 - sum % 10) % 10;
+    if bytes[11] - b'0' == check as u8 {
+        Ok(())
+    } else {
+        Err(format!("'{}' has an invalid check digit", isin))
+    }
+}
+
+/// Four uppercase letters or digits, as ISO 10383 market identifier codes.
+pub fn mic(mic: &str) -> Result<(), String> {
+    if mic.len() == 4
+        && mic
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+    {
+        Ok(())
+    } else {
+        Err(format!("'{}' is not 4 uppercase letters or digits", mic))
+    }
+}
+
+/// Two uppercase letters, as ISO 3166-1 alpha-2 country codes.
+pub fn country(country: &str) -> Result<(), String> {
+    if country.len() == 2 && country.chars().all(|c| c.is_ascii_uppercase()) {
+        Ok(())
+    } else {
+        Err(format!("'{}' is not 2 uppercase letters", country))
+    }
+}
+
+pub fn timezone(timezone: &str) -> Result<(), String> {
+    timezone
+        .parse::<Tz>()
+        .map(|_| ())
+        .map_err(|_| format!("'{}' is not an IANA timezone", timezone))
+}
+
+/// A session may close before it opens, it then spans midnight.
+pub fn trading_hours(hours: &TradingHours) -> Result<(), String> {
+    if hours.open != hours.close {
+        Ok(())
+    } else {
+        Err(String::from("must not open and close at the same time"))
+    }
+}
+
+fn positive(value: f64) -> Result<(), String> {
+    if value.is_finite() && value > 0.0 {
+        Ok(())
+    } else {
+        Err(String::from("must be positive"))
+    }
+}
+
+fn not_negative(value: f64) -> Result<(), String> {
+    if value.is_finite() && value >= 0.0 {
+        Ok(())
+    } else {
+        Err(String::from("must not be negative"))
+    }
+}
+
+pub fn add_currency(code: &str, name: &str, decimals: i32) -> Result<(), Error> {
+    Violations::default()
+        .check("code", currency_code(code))
+        .check("name", self::name(name))
+        .check("decimals", self::decimals(decimals))
+        .finish()
+}
+
+pub fn update_currency(code: &str, name: Option<&str>, decimals: Option<i32>) -> Result<(), Error> {
+    Violations::default()
+        .check("code", currency_code(code))
+        .check_opt("name", name, self::name)
+        .check_opt("decimals", decimals, self::decimals)
+        .finish()
+}
+
+pub fn add_security(
+    ticker: &str,
+    isin: &str,
+    name: &str,
+    exchange: &str,
+    currency: &str,
+) -> Result<(), Error> {
+    Violations::default()
+        .check("ticker", self::ticker(ticker))
+        .check("isin", self::isin(isin))
+        .check("name", self::name(name))
+        .check("exchange", mic(exchange))
+        .check("currency", currency_code(currency))
+        .finish()
+}
+
+pub fn add_exchange(
+    mic: &str,
+    name: &str,
+    country: &str,
+    timezone: &str,
+    trading_hours: &TradingHours,
+    currency: &str,
+) -> Result<(), Error> {
+    Violations::default()
+        .check("mic", self::mic(mic))
+        .check("name", self::name(name))
+        .check("country", self::country(country))
+        .check("timezone", self::timezone(timezone))
+        .check("tradingHours", self::trading_hours(trading_hours))
+        .check("currency", currency_code(currency))
+        .finish()
+}
+
+pub fn update_exchange(
+    mic: &str,
+    name: Option<&str>,
+    country: Option<&str>,
+    timezone: Option<&str>,
+    trading_hours: Option<&TradingHours>,
+    currency: Option<&str>,
+) -> Result<(), Error> {
+    Violations::default()
+        .check("mic", self::mic(mic))
+        .check_opt("name", name, self::name)
+        .check_opt("country", country, self::country)
+        .check_opt("timezone", timezone, self::timezone)
+        .check_opt("tradingHours", trading_hours, self::trading_hours)
+        .check_opt("currency", currency, currency_code)
+        .finish()
+}
+
+pub fn add_price_bars(ticker: &str, bars: &[PriceBar]) -> Result<(), Error> {
+    let mut violations = Violations::default();
+    violations.check("ticker", self::ticker(ticker));
+    for (index, bar) in bars.iter().enumerate() {
+        check_price_bar(&mut violations, bar, |name| {
+            format!("bars[{}].{}", index, name)
+        });
+    }
+    violations.finish()
+}
+
+/// A single bar, as a row of an import.
+pub fn price_bar(ticker: &str, bar: &PriceBar) -> Result<(), Error> {
+    let mut violations = Violations::default();
+    violations.check("ticker", self::ticker(ticker));
+    check_price_bar(&mut violations, bar, |name| String::from(name));
+    violations.finish()
+}
+
+fn check_price_bar(violations: &mut Violations, bar: &PriceBar, field: impl Fn(&str) -> String) {
+    violations
+        .check(&field("open"), positive(bar.open))
+        .check(&field("high"), positive(bar.high))
+        .check(&field("low"), positive(bar.low))
+        .check(&field("close"), positive(bar.close))
+        .check(
+            &field("volume"),
+            if bar.volume >= 0 {
+                Ok(())
+            } else {
+                Err(String::from("must not be negative"))
+            },
+        );
+    let within = |price: f64| bar.low <= price && price <= bar.high;
+    if bar.low > bar.high {
+        violations.check(&field("low"), Err(String::from("must not exceed high")));
+    } else if !within(bar.open) || !within(bar.close) {
+        violations.check(
+            &field("high"),
+            Err(String::from("open and close must be between low and high")),
+        );
+    }
+}
+
+pub fn add_fx_rate(base: &str, quote: &str, rate: f64) -> Result<(), Error> {
+    let mut violations = Violations::default();
+    violations
+        .check("base", currency_code(base))
+        .check("quote", currency_code(quote))
+        .check("rate", positive(rate));
+    if base == quote {
+        violations.check("quote", Err(String::from("must differ from base")));
+    }
+    violations.finish()
+}
+
+pub fn add_portfolio(name: &str, currency: &str) -> Result<(), Error> {
+    Violations::default()
+        .check("name", self::name(name))
+        .check("currency", currency_code(currency))
+        .finish()
+}
+
+pub fn add_transaction(transaction: &NewTransaction) -> Result<(), Error> {
+    let mut violations = Violations::default();
+    violations
+        .check_opt("ticker", transaction.ticker.as_deref(), ticker)
+        .check("quantity", not_negative(transaction.quantity))
+        .check("amount", not_negative(transaction.amount))
+        .check("fees", not_negative(transaction.fees));
+    let trade = matches!(
+        transaction.kind,
+        TransactionKind::Buy | TransactionKind::Sell
+    );
+    let on_security = trade || transaction.kind == TransactionKind::Dividend;
+    if on_security && transaction.ticker.is_none() {
+        violations.check("ticker", Err(String::from("is required")));
+    }
+    if trade && transaction.quantity <= 0.0 {
+        violations.check(
+            "quantity",
+            Err(String::from("must be positive for a trade")),
+        );
+    }
+    if !trade && transaction.quantity != 0.0 {
+        violations.check("quantity", Err(String::from("must be 0 unless trading")));
+    }
+    violations.finish()
+}
+
+/// The cursors of a page of currencies, as decoded, must be positions in the list sorted on
+/// `sort`, in the same direction. Returns them once checked.
+pub fn currency_cursors(
+    after: Option<Result<Keyset, String>>,
+    before: Option<Result<Keyset, String>>,
+    sort: CurrencySortKey,
+    descending: bool,
+) -> Result<(Option<Keyset>, Option<Keyset>), Error> {
+    let check = |cursor: Result<Keyset, String>| {
+        cursor.and_then(|keyset| currency_keyset(&keyset, sort, descending).map(|_| keyset))
+    };
+    let after = after.map(check).transpose();
+    let before = before.map(check).transpose();
+    Violations::default()
+        .check("after", after.as_ref().map(|_| ()).map_err(Clone::clone))
+        .check("before", before.as_ref().map(|_| ()).map_err(Clone::clone))
+        .finish()?;
+    Ok((after.ok().flatten(), before.ok().flatten()))
+}
+
+fn currency_keyset(keyset: &Keyset, sort: CurrencySortKey, descending: bool) -> Result<(), String> {
+    let valid = keyset.sort == sort
+        && keyset.descending == descending
+        && currency_code(&keyset.id).is_ok()
+        && match sort {
+            CurrencySortKey::Code => currency_code(&keyset.key).is_ok(),
+            CurrencySortKey::Name => name(&keyset.key).is_ok(),
+            CurrencySortKey::Decimals => keyset.key.parse::<i32>().is_ok(),
+        };
+    if valid {
+        Ok(())
+    } else {
+        let direction = if descending {
+            "descending"
+        } else {
+            "ascending"
+        };
+        Err(format!(
+            "Invalid cursor for currencies sorted on {}, {}",
+            sort.as_str(),
+            direction
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveTime;
+
+    fn fields(res: Result<(), Error>) -> Vec<String> {
+        match res {
+            Err(Error::ValidationError { violations }) => violations
+                .into_iter()
+                .map(|violation| violation.field)
+                .collect(),
+            res => panic!("expected violations, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_isin_check_digit() {
+        assert!(isin("FR0000121014").is_ok());
+        assert!(isin("US0378331005").is_ok());
+        assert!(isin("US0378331006").is_err());
+        assert!(isin("us0378331005").is_err());
+        assert!(isin("US037833100").is_err());
+    }
+
+    #[test]
+    fn test_every_violation_is_reported() {
+        assert!(add_currency("EUR", "Euro", 2).is_ok());
+        assert_eq!(
+            fields(add_currency("euro", " ", 9)),
+            vec!["code", "name", "decimals"]
+        );
+        assert_eq!(
+            fields(add_security("MC PA", "FR0000121015", "LVMH", "XPAR", "EUR")),
+            vec!["ticker", "isin"]
+        );
+        assert_eq!(
+            fields(add_exchange(
+                "XPAR",
+                "Euronext Paris",
+                "FRA",
+                "Europe/Lutetia",
+                &TradingHours {
+                    open: NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
+                    close: NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
+                },
+                "EUR"
+            )),
+            vec!["country", "timezone", "tradingHours"]
+        );
+        // Overnight sessions span midnight.
+        assert!(add_exchange(
+            "XPAR",
+            "Euronext Paris",
+            "FR",
+            "Europe/Paris",
+            &TradingHours {
+                open: NaiveTime::from_hms_opt(17, 30, 0).unwrap(),
+                close: NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
+            },
+            "EUR"
+        )
+        .is_ok());
+        assert_eq!(
+            fields(add_fx_rate("EUR", "EUR", 0.0)),
+            vec!["rate", "quote"]
+        );
+    }
+
+    #[test]
+    fn test_price_bars_are_reported_by_index() {
+        let bar = PriceBar {
+            date: chrono::NaiveDate::from_ymd_opt(2021, 4, 1).unwrap(),
+            open: 10.0,
+            high: 12.0,
+            low: 9.0,
+            close: 11.0,
+            volume: 100,
+        };
+        let inverted = PriceBar {
+            low: 13.0,
+            volume: -1,
+            ..bar.clone()
+        };
+
+        assert!(add_price_bars("MC.PA", std::slice::from_ref(&bar)).is_ok());
+        assert_eq!(
+            fields(add_price_bars("MC.PA", &[bar, inverted])),
+            vec!["bars[1].volume", "bars[1].low"]
+        );
+    }
+
+    #[test]
+    fn test_cursors_must_match_the_sort() {
+        let keyset = |sort, descending, key: &str, id: &str| {
+            Ok(Keyset {
+                sort,
+                descending,
+                key: String::from(key),
+                id: String::from(id),
+            })
+        };
+        let check = |after, before, sort, descending| {
+            currency_cursors(after, before, sort, descending).map(|_| ())
+        };
+
+        let (after, before) = currency_cursors(
+            Some(keyset(CurrencySortKey::Decimals, false, "2", "EUR")),
+            None,
+            CurrencySortKey::Decimals,
+            false,
+        )
+        .expect("cursors");
+        assert_eq!(after.expect("after").id, "EUR");
+        assert!(before.is_none());
+        // A cursor of the list sorted on codes, or in the other direction, is refused.
+        assert_eq!(
+            fields(check(
+                Some(keyset(CurrencySortKey::Code, false, "EUR", "EUR")),
+                Some(keyset(CurrencySortKey::Name, false, "Euro", "EUR")),
+                CurrencySortKey::Name,
+                true
+            )),
+            vec!["after", "before"]
+        );
+        assert_eq!(
+            fields(check(
+                Some(keyset(CurrencySortKey::Decimals, false, "two", "EUR")),
+                Some(keyset(CurrencySortKey::Decimals, false, "2", "EURO")),
+                CurrencySortKey::Decimals,
+                false
+            )),
+            vec!["after", "before"]
+        );
+        assert_eq!(
+            fields(check(
+                None,
+                Some(Err(String::from("Invalid cursor"))),
+                CurrencySortKey::Name,
+                false
+            )),
+            vec!["before"]
+        );
+    }
+}
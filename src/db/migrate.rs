@@ -0,0 +1,149 @@
+//! Schema migrations, embedded from the `migrations` directory.
+//!
+//! The migrations are reversible, and applied in order of version. `Migrator::run` is not
+//! used, as it does not tell apart the up and down scripts of reversible migrations.
+
+use sqlx::migrate::{Migrate, MigrateError, Migration, Migrator};
+use sqlx::postgres::PgConnection;
+use std::collections::HashSet;
+use std::time::Duration;
+
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// The state of a migration in a database.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub applied: bool,
+}
+
+/// The up migrations, in order of version.
+fn up_migrations() -> impl Iterator<Item = &'static Migration> {
+    MIGRATOR
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+}
+
+/// List the migrations, and whether they are applied. This does not modify the database.
+pub async fn status(conn: &mut PgConnection) -> Result<Vec<MigrationStatus>, MigrateError> {
+    let exists: Option<String> = sqlx::query_scalar("SELECT to_regclass('_sqlx_migrations')::TEXT")
+        .fetch_one(&mut *conn)
+        .await?;
+
+    let applied: HashSet<i64> = match exists {
+        Some(_) => sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success")
+            .fetch_all(&mut *conn)
+            .await?
+            .into_iter()
+            .collect(),
+        None => HashSet::new(),
+    };
+
+    Ok(up_migrations()
+        .map(|migration| MigrationStatus {
+            version: migration.version,
+            description: migration.description.to_string(),
+            applied: applied.contains(&migration.version),
+        })
+        .collect())
+}
+
+/// Apply the pending migrations, after checking the applied ones were not modified.
+///
+/// Returns the migrations applied, with the time each one took.
+pub async fn up(
+    conn: &mut PgConnection,
+) -> Result<Vec<(&'static Migration, Duration)>, MigrateError> {
+    conn.lock().await?;
+    let res = apply_pending(conn).await;
+    conn.unlock().await?;
+    res
+}
+
+async fn apply_pending(
+    conn: &mut PgConnection,
+) -> Result<Vec<(&'static Migration, Duration)>, MigrateError> {
+    conn.ensure_migrations_table().await?;
+
+    let (version, dirty) = conn.version().await?.unwrap_or((0, false));
+    if dirty {
+        return Err(MigrateError::Dirty(version));
+    }
+
+    let mut applied = Vec::new();
+    for migration in up_migrations() {
+        if migration.version > version {
+            let elapsed = conn.apply(migration).await?;
+            applied.push((migration, elapsed));
+        } else {
+            conn.validate(migration).await?;
+        }
+    }
+    Ok(applied)
+}
+
+/// Revert the latest applied migration, if any.
+pub async fn down(
+    conn: &mut PgConnection,
+) -> Result<Option<(&'static Migration, Duration)>, MigrateError> {
+    conn.lock().await?;
+    let res = revert_latest(conn).await;
+    conn.unlock().await?;
+    res
+}
+
+async fn revert_latest(
+    conn: &mut PgConnection,
+) -> Result<Option<(&'static Migration, Duration)>, MigrateError> {
+    conn.ensure_migrations_table().await?;
+
+    let version = match conn.version().await? {
+        Some((version, false)) => version,
+        Some((version, true)) => return Err(MigrateError::Dirty(version)),
+        None => return Ok(None),
+    };
+
+    let migration = MIGRATOR
+        .iter()
+        .find(|migration| {
+            migration.version == version && migration.migration_type.is_down_migration()
+        })
+        .ok_or(MigrateError::VersionMissing(version))?;
+
+    let elapsed = conn.revert(migration).await?;
+    Ok(Some((migration, elapsed)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::get_database_url;
+    use sqlx::Connection;
+
+    #[test]
+    fn test_migrations_are_reversible() {
+        let ups = up_migrations().map(|m| m.version).collect::<Vec<_>>();
+        let downs = MIGRATOR
+            .iter()
+            .filter(|m| m.migration_type.is_down_migration())
+            .map(|m| m.version)
+            .collect::<Vec<_>>();
+
+        assert!(!ups.is_empty());
+        assert_eq!(ups, downs);
+    }
+
+    #[tokio::test]
+    async fn test_database_is_migrated() {
+        let url = get_database_url();
+        let mut conn = PgConnection::connect(&url).await.expect("connection");
+
+        // Applying the migrations to a migrated database only validates them.
+        let applied = up(&mut conn).await.expect("migrations");
+        let status = status(&mut conn).await.expect("status");
+
+        assert!(applied.is_empty());
+        assert!(status.iter().all(|migration| migration.applied));
+    }
+}
@@ -1,5 +1,6 @@
 use async_trait::async_trait;
 
+pub mod migrate;
 pub mod model;
 pub mod pg;
 
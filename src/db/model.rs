@@ -1,19 +1,290 @@
 use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use serde::{Deserialize, Serialize};
 use snafu::Snafu;
 use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use uuid::Uuid;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct CurrencyEntity {
     pub code: String,
     pub name: String,
     pub decimals: i32,
 }
 
+/// The kind of instrument a security represents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AssetClass {
+    Equity,
+    Etf,
+    Fund,
+    Bond,
+    Commodity,
+    Cryptocurrency,
+    Other,
+}
+
+impl AssetClass {
+    /// The representation stored in the database.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AssetClass::Equity => "equity",
+            AssetClass::Etf => "etf",
+            AssetClass::Fund => "fund",
+            AssetClass::Bond => "bond",
+            AssetClass::Commodity => "commodity",
+            AssetClass::Cryptocurrency => "cryptocurrency",
+            AssetClass::Other => "other",
+        }
+    }
+}
+
+impl fmt::Display for AssetClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for AssetClass {
+    type Err = ProvideError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "equity" => Ok(AssetClass::Equity),
+            "etf" => Ok(AssetClass::Etf),
+            "fund" => Ok(AssetClass::Fund),
+            "bond" => Ok(AssetClass::Bond),
+            "commodity" => Ok(AssetClass::Commodity),
+            "cryptocurrency" => Ok(AssetClass::Cryptocurrency),
+            "other" => Ok(AssetClass::Other),
+            _ => Err(ProvideError::ModelViolation {
+                details: format!("Unknown asset class '{}'", s),
+                field: Some(String::from("asset_class")),
+            }),
+        }
+    }
+}
+
+/// A listed instrument.
+///
+/// The ticker is unique across exchanges, so secondary listings should use a suffixed
+/// symbol (eg `MC.PA`).
+#[derive(Debug)]
+pub struct SecurityEntity {
+    pub id: Uuid,
+    pub ticker: String,
+    pub isin: String,
+    pub name: String,
+    /// MIC code of the exchange where the security is listed.
+    pub exchange: String,
+    /// Code of the currency the security is traded in.
+    pub currency: String,
+    pub asset_class: AssetClass,
+}
+
+/// The daily trading session of an exchange, in the exchange's local time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TradingHours {
+    pub open: NaiveTime,
+    pub close: NaiveTime,
+}
+
+/// A trading venue, identified by its ISO 10383 Market Identifier Code.
+#[derive(Debug)]
+pub struct ExchangeEntity {
+    pub mic: String,
+    pub name: String,
+    /// ISO 3166-1 alpha-2 country code
+    pub country: String,
+    /// IANA timezone name (eg `Europe/Paris`)
+    pub timezone: String,
+    pub trading_hours: TradingHours,
+    /// Code of the currency securities are traded in by default.
+    pub currency: String,
+}
+
+/// A daily (end-of-day) OHLCV bar for a security.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct PriceBarEntity {
+    pub ticker: String,
+    pub date: NaiveDate,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: i64,
+}
+
+/// The price of one unit of the base currency, expressed in the quote currency.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FxRateEntity {
+    pub base: String,
+    pub quote: String,
+    pub date: NaiveDate,
+    pub rate: f64,
+}
+
+/// A set of holdings, valued in a base currency.
+#[derive(Debug)]
+pub struct PortfolioEntity {
+    pub id: Uuid,
+    pub name: String,
+    /// Code of the currency the portfolio is valued in.
+    pub currency: String,
+}
+
+/// Filters of a currency search. Missing filters match every currency.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CurrencyFilter {
+    pub code_prefix: Option<String>,
+    /// Case insensitive
+    pub name_contains: Option<String>,
+    pub decimals: Option<i32>,
+}
+
+/// The column currencies are sorted on, then by code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CurrencySortKey {
+    Code,
+    Name,
+    Decimals,
+}
+
+impl CurrencySortKey {
+    /// The name of the column.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CurrencySortKey::Code => "code",
+            CurrencySortKey::Name => "name",
+            CurrencySortKey::Decimals => "decimals",
+        }
+    }
+}
+
+/// A position in a sorted list: the value of the sort column, and the identifier of the row
+/// to break ties. The sort is kept with them, a position being meaningless in another order.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Keyset {
+    pub sort: CurrencySortKey,
+    pub descending: bool,
+    pub key: String,
+    pub id: String,
+}
+
+/// Which rows of a sorted list to return.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pagination {
+    /// Only the rows strictly after this position
+    pub after: Option<Keyset>,
+    /// Only the rows strictly before this position
+    pub before: Option<Keyset>,
+    /// Return the rows from the end, in reverse order
+    pub backward: bool,
+    pub limit: i64,
+    /// Rows skipped, past the positions
+    pub offset: i64,
+}
+
+/// A key granting access to the API. Only a digest of the key is stored.
+#[derive(Debug, Clone)]
+pub struct ApiKeyEntity {
+    pub id: Uuid,
+    pub name: String,
+    pub roles: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+/// The nature of a portfolio transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransactionKind {
+    Buy,
+    Sell,
+    Deposit,
+    Withdrawal,
+    Fee,
+    Dividend,
+}
+
+impl TransactionKind {
+    /// The representation stored in the database.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransactionKind::Buy => "buy",
+            TransactionKind::Sell => "sell",
+            TransactionKind::Deposit => "deposit",
+            TransactionKind::Withdrawal => "withdrawal",
+            TransactionKind::Fee => "fee",
+            TransactionKind::Dividend => "dividend",
+        }
+    }
+}
+
+impl fmt::Display for TransactionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for TransactionKind {
+    type Err = ProvideError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "buy" => Ok(TransactionKind::Buy),
+            "sell" => Ok(TransactionKind::Sell),
+            "deposit" => Ok(TransactionKind::Deposit),
+            "withdrawal" => Ok(TransactionKind::Withdrawal),
+            "fee" => Ok(TransactionKind::Fee),
+            "dividend" => Ok(TransactionKind::Dividend),
+            _ => Err(ProvideError::ModelViolation {
+                details: format!("Unknown transaction kind '{}'", s),
+                field: Some(String::from("kind")),
+            }),
+        }
+    }
+}
+
+/// An entry in the ledger of a portfolio.
+///
+/// Amounts are expressed in the currency of the portfolio:
+/// * buy / sell: `quantity` units of the security traded for `amount`, plus `fees`.
+/// * deposit / withdrawal / fee: `amount` of cash, `quantity` is zero.
+/// * dividend: `amount` paid by the security, `quantity` is zero.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct TransactionEntity {
+    pub id: Uuid,
+    pub portfolio: Uuid,
+    pub kind: TransactionKind,
+    /// Ticker of the security, for buy, sell and dividend (optionally for fee).
+    pub ticker: Option<String>,
+    pub executed_at: DateTime<Utc>,
+    pub quantity: f64,
+    pub amount: f64,
+    pub fees: f64,
+}
+
 #[mockall::automock]
 #[async_trait]
 pub trait ProvideStock {
     async fn list_currencies(&mut self) -> ProvideResult<Vec<CurrencyEntity>>;
 
+    /// A page of the currencies matching the filter, sorted on the key then by code.
+    async fn search_currencies(
+        &mut self,
+        filter: &CurrencyFilter,
+        sort: CurrencySortKey,
+        descending: bool,
+        page: &Pagination,
+    ) -> ProvideResult<Vec<CurrencyEntity>>;
+
+    /// The number of currencies matching the filter.
+    async fn count_currencies(&mut self, filter: &CurrencyFilter) -> ProvideResult<i64>;
+
     async fn add_currency(
         &mut self,
         code: &str,
@@ -22,6 +293,161 @@ pub trait ProvideStock {
     ) -> ProvideResult<CurrencyEntity>;
 
     async fn find_currency(&mut self, code: &str) -> ProvideResult<Option<CurrencyEntity>>;
+
+    /// The currencies with one of these codes, in no particular order. Unknown codes are left out.
+    async fn find_currencies(&mut self, codes: &[String]) -> ProvideResult<Vec<CurrencyEntity>>;
+
+    /// Update the name and / or the decimals of an existing currency.
+    ///
+    /// Fields left to `None` are unchanged.
+    async fn update_currency(
+        &mut self,
+        code: &str,
+        name: Option<String>,
+        decimals: Option<i32>,
+    ) -> ProvideResult<CurrencyEntity>;
+
+    /// Delete a currency, returning the deleted entity.
+    ///
+    /// The deletion is refused (ReferenceViolation) while other records reference the currency.
+    async fn delete_currency(&mut self, code: &str) -> ProvideResult<CurrencyEntity>;
+
+    async fn list_securities(&mut self) -> ProvideResult<Vec<SecurityEntity>>;
+
+    async fn add_security(
+        &mut self,
+        ticker: &str,
+        isin: &str,
+        name: &str,
+        exchange: &str,
+        currency: &str,
+        asset_class: AssetClass,
+    ) -> ProvideResult<SecurityEntity>;
+
+    async fn find_security_by_ticker(
+        &mut self,
+        ticker: &str,
+    ) -> ProvideResult<Option<SecurityEntity>>;
+
+    /// The securities with one of these tickers, in no particular order.
+    async fn find_securities_by_ticker(
+        &mut self,
+        tickers: &[String],
+    ) -> ProvideResult<Vec<SecurityEntity>>;
+
+    async fn find_security_by_isin(&mut self, isin: &str) -> ProvideResult<Option<SecurityEntity>>;
+
+    async fn list_exchanges(&mut self) -> ProvideResult<Vec<ExchangeEntity>>;
+
+    async fn add_exchange(
+        &mut self,
+        mic: &str,
+        name: &str,
+        country: &str,
+        timezone: &str,
+        trading_hours: TradingHours,
+        currency: &str,
+    ) -> ProvideResult<ExchangeEntity>;
+
+    async fn find_exchange(&mut self, mic: &str) -> ProvideResult<Option<ExchangeEntity>>;
+
+    /// The exchanges with one of these MIC codes, in no particular order.
+    async fn find_exchanges(&mut self, mics: &[String]) -> ProvideResult<Vec<ExchangeEntity>>;
+
+    /// Update an existing exchange. Fields left to `None` are unchanged.
+    async fn update_exchange(
+        &mut self,
+        mic: &str,
+        name: Option<String>,
+        country: Option<String>,
+        timezone: Option<String>,
+        trading_hours: Option<TradingHours>,
+        currency: Option<String>,
+    ) -> ProvideResult<ExchangeEntity>;
+
+    async fn delete_exchange(&mut self, mic: &str) -> ProvideResult<ExchangeEntity>;
+
+    /// Store daily bars, returning the number of bars stored.
+    ///
+    /// Bars are keyed by (security, date): storing a bar for an existing key replaces it,
+    /// so importing the same series twice leaves the store unchanged.
+    async fn add_price_bars(&mut self, bars: &[PriceBarEntity]) -> ProvideResult<u64>;
+
+    /// Retrieve the daily bars of a security between two dates (inclusive), in
+    /// chronological order.
+    async fn find_price_bars(
+        &mut self,
+        ticker: &str,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> ProvideResult<Vec<PriceBarEntity>>;
+
+    /// Find the latest bar of a security on or before the date.
+    async fn find_latest_price_bar(
+        &mut self,
+        ticker: &str,
+        date: NaiveDate,
+    ) -> ProvideResult<Option<PriceBarEntity>>;
+
+    /// Store the rate of a currency pair for a date, replacing any existing rate.
+    async fn add_fx_rate(
+        &mut self,
+        base: &str,
+        quote: &str,
+        date: NaiveDate,
+        rate: f64,
+    ) -> ProvideResult<FxRateEntity>;
+
+    /// Find the latest stored rate of the pair on or before the date.
+    ///
+    /// Only the pair as given is searched, inverse and cross rates are not derived.
+    async fn find_fx_rate(
+        &mut self,
+        base: &str,
+        quote: &str,
+        date: NaiveDate,
+    ) -> ProvideResult<Option<FxRateEntity>>;
+
+    async fn list_portfolios(&mut self) -> ProvideResult<Vec<PortfolioEntity>>;
+
+    async fn add_portfolio(&mut self, name: &str, currency: &str)
+        -> ProvideResult<PortfolioEntity>;
+
+    async fn find_portfolio(&mut self, id: Uuid) -> ProvideResult<Option<PortfolioEntity>>;
+
+    /// Find the portfolio, and lock it until the end of the transaction, so that its ledger
+    /// is changed by one caller at a time.
+    async fn lock_portfolio(&mut self, id: Uuid) -> ProvideResult<Option<PortfolioEntity>>;
+
+    /// Record a transaction in the ledger of its portfolio.
+    async fn add_transaction(
+        &mut self,
+        transaction: &TransactionEntity,
+    ) -> ProvideResult<TransactionEntity>;
+
+    /// Retrieve the ledger of a portfolio, in execution order.
+    async fn list_transactions(&mut self, portfolio: Uuid)
+        -> ProvideResult<Vec<TransactionEntity>>;
+
+    async fn delete_transaction(&mut self, id: Uuid) -> ProvideResult<TransactionEntity>;
+
+    /// Store the digest of a new key.
+    async fn add_api_key(
+        &mut self,
+        name: &str,
+        hash: &str,
+        roles: &[String],
+    ) -> ProvideResult<ApiKeyEntity>;
+
+    /// Find the key with this digest, unless it is revoked.
+    async fn find_api_key_by_hash(&mut self, hash: &str) -> ProvideResult<Option<ApiKeyEntity>>;
+
+    async fn list_api_keys(&mut self) -> ProvideResult<Vec<ApiKeyEntity>>;
+
+    async fn revoke_api_key(&mut self, name: &str) -> ProvideResult<ApiKeyEntity>;
+
+    /// The number of rows stored for each entity, as (entity, count) pairs.
+    async fn count_entities(&mut self) -> ProvideResult<Vec<(String, i64)>>;
 }
 
 pub type ProvideResult<T> = Result<T, ProvideError>;
@@ -37,12 +463,29 @@ pub enum ProvideError {
     /// The operation violates a uniqueness constraint
     #[snafu(display("Operation violates uniqueness constraint: {}", details))]
     #[snafu(visibility(pub))]
-    UniqueViolation { details: String },
+    UniqueViolation {
+        details: String,
+        /// The offending column, when known
+        field: Option<String>,
+    },
 
     /// The requested operation violates the data model
     #[snafu(display("Operation violates model: {}", details))]
     #[snafu(visibility(pub))]
-    ModelViolation { details: String },
+    ModelViolation {
+        details: String,
+        /// The offending column, when known
+        field: Option<String>,
+    },
+
+    /// The entity is still referenced by other records
+    #[snafu(display("Entity is still referenced: {}", details))]
+    #[snafu(visibility(pub))]
+    ReferenceViolation {
+        details: String,
+        /// The referenced column, when known
+        field: Option<String>,
+    },
 
     /// The requested operation violates the data model
     #[snafu(display("UnHandled Error: {}", source))]
@@ -50,6 +493,19 @@ pub enum ProvideError {
     UnHandledError { source: sqlx::Error },
 }
 
+impl ProvideError {
+    /// The kind of error, without its details.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ProvideError::NotFound => "not_found",
+            ProvideError::UniqueViolation { .. } => "unique_violation",
+            ProvideError::ModelViolation { .. } => "model_violation",
+            ProvideError::ReferenceViolation { .. } => "reference_violation",
+            ProvideError::UnHandledError { .. } => "unhandled",
+        }
+    }
+}
+
 impl From<sqlx::Error> for ProvideError {
     /// Convert a SQLx error into a provider error
     ///
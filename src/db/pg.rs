@@ -1,9 +1,12 @@
 use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, Utc};
+use futures::stream::{BoxStream, StreamExt, TryStreamExt};
 use sqlx::pool::PoolConnection;
 use sqlx::postgres::{PgDatabaseError, PgRow, Postgres};
 use sqlx::{FromRow, Row};
 use sqlx::{PgConnection, PgPool};
 use std::convert::TryFrom;
+use uuid::Uuid;
 
 use super::model;
 use super::Db;
@@ -19,12 +22,158 @@ impl<'c> FromRow<'c, PgRow> for model::CurrencyEntity {
     }
 }
 
+// This should match the information in api.security_type
+impl<'c> FromRow<'c, PgRow> for model::SecurityEntity {
+    fn from_row(row: &'c PgRow) -> Result<Self, sqlx::Error> {
+        let asset_class: String = row.try_get(6)?;
+        let asset_class = asset_class
+            .parse::<model::AssetClass>()
+            .map_err(|err| sqlx::Error::Decode(Box::new(err)))?;
+        Ok(model::SecurityEntity {
+            id: row.try_get(0)?,
+            ticker: row.try_get(1)?,
+            isin: row.try_get(2)?,
+            name: row.try_get(3)?,
+            exchange: row.try_get(4)?,
+            currency: row.try_get(5)?,
+            asset_class,
+        })
+    }
+}
+
+// This should match the information in api.exchange_type
+impl<'c> FromRow<'c, PgRow> for model::ExchangeEntity {
+    fn from_row(row: &'c PgRow) -> Result<Self, sqlx::Error> {
+        Ok(model::ExchangeEntity {
+            mic: row.try_get(0)?,
+            name: row.try_get(1)?,
+            country: row.try_get(2)?,
+            timezone: row.try_get(3)?,
+            trading_hours: model::TradingHours {
+                open: row.try_get(4)?,
+                close: row.try_get(5)?,
+            },
+            currency: row.try_get(6)?,
+        })
+    }
+}
+
+// This should match the information in api.price_bar_type
+impl<'c> FromRow<'c, PgRow> for model::PriceBarEntity {
+    fn from_row(row: &'c PgRow) -> Result<Self, sqlx::Error> {
+        Ok(model::PriceBarEntity {
+            ticker: row.try_get(0)?,
+            date: row.try_get(1)?,
+            open: row.try_get(2)?,
+            high: row.try_get(3)?,
+            low: row.try_get(4)?,
+            close: row.try_get(5)?,
+            volume: row.try_get(6)?,
+        })
+    }
+}
+
+// This should match the information in api.fx_rate_type
+impl<'c> FromRow<'c, PgRow> for model::FxRateEntity {
+    fn from_row(row: &'c PgRow) -> Result<Self, sqlx::Error> {
+        Ok(model::FxRateEntity {
+            base: row.try_get(0)?,
+            quote: row.try_get(1)?,
+            date: row.try_get(2)?,
+            rate: row.try_get(3)?,
+        })
+    }
+}
+
+// This should match the information in api.portfolio_type
+impl<'c> FromRow<'c, PgRow> for model::PortfolioEntity {
+    fn from_row(row: &'c PgRow) -> Result<Self, sqlx::Error> {
+        Ok(model::PortfolioEntity {
+            id: row.try_get(0)?,
+            name: row.try_get(1)?,
+            currency: row.try_get(2)?,
+        })
+    }
+}
+
+// This should match the information in api.api_key_type
+impl<'c> FromRow<'c, PgRow> for model::ApiKeyEntity {
+    fn from_row(row: &'c PgRow) -> Result<Self, sqlx::Error> {
+        Ok(model::ApiKeyEntity {
+            id: row.try_get(0)?,
+            name: row.try_get(1)?,
+            roles: row.try_get(2)?,
+            created_at: row.try_get(3)?,
+            revoked_at: row.try_get(4)?,
+        })
+    }
+}
+
+// This should match the information in api.transaction_type
+impl<'c> FromRow<'c, PgRow> for model::TransactionEntity {
+    fn from_row(row: &'c PgRow) -> Result<Self, sqlx::Error> {
+        let kind: String = row.try_get(2)?;
+        let kind = kind
+            .parse::<model::TransactionKind>()
+            .map_err(|err| sqlx::Error::Decode(Box::new(err)))?;
+        Ok(model::TransactionEntity {
+            id: row.try_get(0)?,
+            portfolio: row.try_get(1)?,
+            kind,
+            ticker: row.try_get(3)?,
+            executed_at: row.try_get(4)?,
+            quantity: row.try_get(5)?,
+            amount: row.try_get(6)?,
+            fees: row.try_get(7)?,
+        })
+    }
+}
+
 /// Open a connection to a database
 pub async fn connect(db_url: &str) -> sqlx::Result<PgPool> {
     let pool = PgPool::connect(db_url).await?;
     Ok(pool)
 }
 
+// The exports stream their rows as they are read, instead of collecting them like the
+// methods of ProvideStock, so that a table does not have to fit in memory.
+
+/// The currencies created or updated since the given time, or all of them, by code.
+pub fn export_currencies(
+    conn: &mut PgConnection,
+    since: Option<DateTime<Utc>>,
+) -> BoxStream<'_, model::ProvideResult<model::CurrencyEntity>> {
+    sqlx::query_as(r#"SELECT * FROM api.export_currencies($1::TIMESTAMPTZ)"#)
+        .bind(since)
+        .fetch(conn)
+        .map_err(model::ProvideError::from)
+        .boxed()
+}
+
+/// The securities created or updated since the given time, or all of them, by ticker.
+pub fn export_securities(
+    conn: &mut PgConnection,
+    since: Option<DateTime<Utc>>,
+) -> BoxStream<'_, model::ProvideResult<model::SecurityEntity>> {
+    sqlx::query_as(r#"SELECT * FROM api.export_securities($1::TIMESTAMPTZ)"#)
+        .bind(since)
+        .fetch(conn)
+        .map_err(model::ProvideError::from)
+        .boxed()
+}
+
+/// The price bars created or updated since the given time, or all of them, by ticker and date.
+pub fn export_price_bars(
+    conn: &mut PgConnection,
+    since: Option<DateTime<Utc>>,
+) -> BoxStream<'_, model::ProvideResult<model::PriceBarEntity>> {
+    sqlx::query_as(r#"SELECT * FROM api.export_price_bars($1::TIMESTAMPTZ)"#)
+        .bind(since)
+        .fetch(conn)
+        .map_err(model::ProvideError::from)
+        .boxed()
+}
+
 impl TryFrom<&PgDatabaseError> for model::ProvideError {
     type Error = ();
 
@@ -34,12 +183,32 @@ impl TryFrom<&PgDatabaseError> for model::ProvideError {
     ///
     /// * [Postgres Error Codes](https://www.postgresql.org/docs/current/errcodes-appendix.html)
     fn try_from(pg_err: &PgDatabaseError) -> Result<Self, Self::Error> {
+        let field = pg_err
+            .detail()
+            .and_then(key_columns)
+            .or_else(|| pg_err.column())
+            .map(String::from);
         let provider_err = match pg_err.code() {
             "23505" => model::ProvideError::UniqueViolation {
                 details: pg_err.detail().unwrap().to_owned(),
+                field,
             },
+            // foreign key violation: the detail tells which table still references the key, or
+            // that the key referenced is missing.
+            "23503" => {
+                let details = pg_err
+                    .detail()
+                    .unwrap_or_else(|| pg_err.message())
+                    .to_owned();
+                if details.contains("is still referenced") {
+                    model::ProvideError::ReferenceViolation { details, field }
+                } else {
+                    model::ProvideError::ModelViolation { details, field }
+                }
+            }
             code if code.starts_with("23") => model::ProvideError::ModelViolation {
                 details: pg_err.message().to_owned(),
+                field,
             },
             _ => return Err(()),
         };
@@ -48,6 +217,14 @@ impl TryFrom<&PgDatabaseError> for model::ProvideError {
     }
 }
 
+/// The columns of the key in the detail of a constraint violation, eg `code` in
+/// `Key (code)=(EUR) already exists.`
+fn key_columns(detail: &str) -> Option<&str> {
+    let start = detail.find("Key (")? + "Key (".len();
+    let end = start + detail[start..].find(")=")?;
+    Some(&detail[start..end])
+}
+
 #[async_trait]
 impl Db for PgPool {
     type Conn = PoolConnection<Postgres>;
@@ -68,6 +245,49 @@ impl model::ProvideStock for PgConnection {
         Ok(currencies)
     }
 
+    async fn search_currencies(
+        &mut self,
+        filter: &model::CurrencyFilter,
+        sort: model::CurrencySortKey,
+        descending: bool,
+        page: &model::Pagination,
+    ) -> model::ProvideResult<Vec<model::CurrencyEntity>> {
+        let currencies: Vec<model::CurrencyEntity> = sqlx::query_as(
+            r#"SELECT * FROM api.search_currencies(
+                $1::VARCHAR, $2::VARCHAR, $3::INTEGER, $4::VARCHAR(16), $5,
+                $6::TEXT, $7::CHAR(3), $8::TEXT, $9::CHAR(3), $10, $11, $12)"#,
+        )
+        .bind(&filter.code_prefix)
+        .bind(&filter.name_contains)
+        .bind(filter.decimals)
+        .bind(sort.as_str())
+        .bind(descending)
+        .bind(page.after.as_ref().map(|after| &after.key))
+        .bind(page.after.as_ref().map(|after| &after.id))
+        .bind(page.before.as_ref().map(|before| &before.key))
+        .bind(page.before.as_ref().map(|before| &before.id))
+        .bind(page.backward)
+        .bind(page.limit)
+        .bind(page.offset)
+        .fetch_all(self)
+        .await?;
+        Ok(currencies)
+    }
+
+    async fn count_currencies(
+        &mut self,
+        filter: &model::CurrencyFilter,
+    ) -> model::ProvideResult<i64> {
+        let (count,): (i64,) =
+            sqlx::query_as(r#"SELECT api.count_currencies($1::VARCHAR, $2::VARCHAR, $3::INTEGER)"#)
+                .bind(&filter.code_prefix)
+                .bind(&filter.name_contains)
+                .bind(filter.decimals)
+                .fetch_one(self)
+                .await?;
+        Ok(count)
+    }
+
     async fn add_currency(
         &mut self,
         code: &str,
@@ -77,9 +297,9 @@ impl model::ProvideStock for PgConnection {
         let currency: model::CurrencyEntity = sqlx::query_as(
             r#"SELECT * FROM api.add_currency($1::CHAR(3), $2::VARCHAR(255), $3::INTEGER)"#,
         )
-        .bind(&code)
-        .bind(&name)
-        .bind(&decimals)
+        .bind(code)
+        .bind(name)
+        .bind(decimals)
         .fetch_one(self)
         .await?;
         Ok(currency)
@@ -91,19 +311,462 @@ impl model::ProvideStock for PgConnection {
     ) -> model::ProvideResult<Option<model::CurrencyEntity>> {
         let currency: Option<model::CurrencyEntity> =
             sqlx::query_as(r#"SELECT * FROM api.find_currency_by_code($1::CHAR(3))"#)
-                .bind(&code)
+                .bind(code)
                 .fetch_optional(self)
                 .await?;
         Ok(currency)
     }
+
+    async fn find_currencies(
+        &mut self,
+        codes: &[String],
+    ) -> model::ProvideResult<Vec<model::CurrencyEntity>> {
+        let currencies: Vec<model::CurrencyEntity> =
+            sqlx::query_as(r#"SELECT * FROM api.find_currencies_by_code($1::CHAR(3)[])"#)
+                .bind(codes)
+                .fetch_all(self)
+                .await?;
+        Ok(currencies)
+    }
+
+    async fn update_currency(
+        &mut self,
+        code: &str,
+        name: Option<String>,
+        decimals: Option<i32>,
+    ) -> model::ProvideResult<model::CurrencyEntity> {
+        let currency: model::CurrencyEntity = sqlx::query_as(
+            r#"SELECT * FROM api.update_currency($1::CHAR(3), $2::VARCHAR(255), $3::INTEGER)"#,
+        )
+        .bind(code)
+        .bind(name)
+        .bind(decimals)
+        .fetch_one(self)
+        .await?;
+        Ok(currency)
+    }
+
+    async fn delete_currency(&mut self, code: &str) -> model::ProvideResult<model::CurrencyEntity> {
+        let currency: model::CurrencyEntity =
+            sqlx::query_as(r#"SELECT * FROM api.delete_currency($1::CHAR(3))"#)
+                .bind(code)
+                .fetch_one(self)
+                .await?;
+        Ok(currency)
+    }
+
+    async fn list_securities(&mut self) -> model::ProvideResult<Vec<model::SecurityEntity>> {
+        let securities: Vec<model::SecurityEntity> =
+            sqlx::query_as(r#"SELECT * FROM api.list_securities()"#)
+                .fetch_all(self)
+                .await?;
+
+        Ok(securities)
+    }
+
+    async fn add_security(
+        &mut self,
+        ticker: &str,
+        isin: &str,
+        name: &str,
+        exchange: &str,
+        currency: &str,
+        asset_class: model::AssetClass,
+    ) -> model::ProvideResult<model::SecurityEntity> {
+        let security: model::SecurityEntity = sqlx::query_as(
+            r#"SELECT * FROM api.add_security($1::VARCHAR(32), $2::CHAR(12), $3::VARCHAR(255), $4::CHAR(4), $5::CHAR(3), $6::VARCHAR(32))"#,
+        )
+        .bind(ticker)
+        .bind(isin)
+        .bind(name)
+        .bind(exchange)
+        .bind(currency)
+        .bind(asset_class.as_str())
+        .fetch_one(self)
+        .await?;
+        Ok(security)
+    }
+
+    async fn find_security_by_ticker(
+        &mut self,
+        ticker: &str,
+    ) -> model::ProvideResult<Option<model::SecurityEntity>> {
+        let security: Option<model::SecurityEntity> =
+            sqlx::query_as(r#"SELECT * FROM api.find_security_by_ticker($1::VARCHAR(32))"#)
+                .bind(ticker)
+                .fetch_optional(self)
+                .await?;
+        Ok(security)
+    }
+
+    async fn find_securities_by_ticker(
+        &mut self,
+        tickers: &[String],
+    ) -> model::ProvideResult<Vec<model::SecurityEntity>> {
+        let securities: Vec<model::SecurityEntity> =
+            sqlx::query_as(r#"SELECT * FROM api.find_securities_by_ticker($1::VARCHAR(32)[])"#)
+                .bind(tickers)
+                .fetch_all(self)
+                .await?;
+        Ok(securities)
+    }
+
+    async fn find_security_by_isin(
+        &mut self,
+        isin: &str,
+    ) -> model::ProvideResult<Option<model::SecurityEntity>> {
+        let security: Option<model::SecurityEntity> =
+            sqlx::query_as(r#"SELECT * FROM api.find_security_by_isin($1::CHAR(12))"#)
+                .bind(isin)
+                .fetch_optional(self)
+                .await?;
+        Ok(security)
+    }
+
+    async fn list_exchanges(&mut self) -> model::ProvideResult<Vec<model::ExchangeEntity>> {
+        let exchanges: Vec<model::ExchangeEntity> =
+            sqlx::query_as(r#"SELECT * FROM api.list_exchanges()"#)
+                .fetch_all(self)
+                .await?;
+
+        Ok(exchanges)
+    }
+
+    async fn add_exchange(
+        &mut self,
+        mic: &str,
+        name: &str,
+        country: &str,
+        timezone: &str,
+        trading_hours: model::TradingHours,
+        currency: &str,
+    ) -> model::ProvideResult<model::ExchangeEntity> {
+        let exchange: model::ExchangeEntity = sqlx::query_as(
+            r#"SELECT * FROM api.add_exchange($1::CHAR(4), $2::VARCHAR(255), $3::CHAR(2), $4::VARCHAR(64), $5::TIME, $6::TIME, $7::CHAR(3))"#,
+        )
+        .bind(mic)
+        .bind(name)
+        .bind(country)
+        .bind(timezone)
+        .bind(trading_hours.open)
+        .bind(trading_hours.close)
+        .bind(currency)
+        .fetch_one(self)
+        .await?;
+        Ok(exchange)
+    }
+
+    async fn find_exchange(
+        &mut self,
+        mic: &str,
+    ) -> model::ProvideResult<Option<model::ExchangeEntity>> {
+        let exchange: Option<model::ExchangeEntity> =
+            sqlx::query_as(r#"SELECT * FROM api.find_exchange_by_mic($1::CHAR(4))"#)
+                .bind(mic)
+                .fetch_optional(self)
+                .await?;
+        Ok(exchange)
+    }
+
+    async fn find_exchanges(
+        &mut self,
+        mics: &[String],
+    ) -> model::ProvideResult<Vec<model::ExchangeEntity>> {
+        let exchanges: Vec<model::ExchangeEntity> =
+            sqlx::query_as(r#"SELECT * FROM api.find_exchanges_by_mic($1::CHAR(4)[])"#)
+                .bind(mics)
+                .fetch_all(self)
+                .await?;
+        Ok(exchanges)
+    }
+
+    async fn update_exchange(
+        &mut self,
+        mic: &str,
+        name: Option<String>,
+        country: Option<String>,
+        timezone: Option<String>,
+        trading_hours: Option<model::TradingHours>,
+        currency: Option<String>,
+    ) -> model::ProvideResult<model::ExchangeEntity> {
+        let exchange: model::ExchangeEntity = sqlx::query_as(
+            r#"SELECT * FROM api.update_exchange($1::CHAR(4), $2::VARCHAR(255), $3::CHAR(2), $4::VARCHAR(64), $5::TIME, $6::TIME, $7::CHAR(3))"#,
+        )
+        .bind(mic)
+        .bind(name)
+        .bind(country)
+        .bind(timezone)
+        .bind(trading_hours.map(|hours| hours.open))
+        .bind(trading_hours.map(|hours| hours.close))
+        .bind(currency)
+        .fetch_one(self)
+        .await?;
+        Ok(exchange)
+    }
+
+    async fn delete_exchange(&mut self, mic: &str) -> model::ProvideResult<model::ExchangeEntity> {
+        let exchange: model::ExchangeEntity =
+            sqlx::query_as(r#"SELECT * FROM api.delete_exchange($1::CHAR(4))"#)
+                .bind(mic)
+                .fetch_one(self)
+                .await?;
+        Ok(exchange)
+    }
+
+    async fn add_price_bars(
+        &mut self,
+        bars: &[model::PriceBarEntity],
+    ) -> model::ProvideResult<u64> {
+        // The bars are sent column by column, and zipped back together with UNNEST.
+        let tickers = bars
+            .iter()
+            .map(|bar| bar.ticker.clone())
+            .collect::<Vec<_>>();
+        let dates = bars.iter().map(|bar| bar.date).collect::<Vec<_>>();
+        let opens = bars.iter().map(|bar| bar.open).collect::<Vec<_>>();
+        let highs = bars.iter().map(|bar| bar.high).collect::<Vec<_>>();
+        let lows = bars.iter().map(|bar| bar.low).collect::<Vec<_>>();
+        let closes = bars.iter().map(|bar| bar.close).collect::<Vec<_>>();
+        let volumes = bars.iter().map(|bar| bar.volume).collect::<Vec<_>>();
+
+        let count: (i64,) = sqlx::query_as(
+            r#"SELECT * FROM api.add_price_bars($1::VARCHAR(32)[], $2::DATE[], $3::DOUBLE PRECISION[], $4::DOUBLE PRECISION[], $5::DOUBLE PRECISION[], $6::DOUBLE PRECISION[], $7::BIGINT[])"#,
+        )
+        .bind(tickers)
+        .bind(dates)
+        .bind(opens)
+        .bind(highs)
+        .bind(lows)
+        .bind(closes)
+        .bind(volumes)
+        .fetch_one(self)
+        .await?;
+        Ok(count.0 as u64)
+    }
+
+    async fn find_price_bars(
+        &mut self,
+        ticker: &str,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> model::ProvideResult<Vec<model::PriceBarEntity>> {
+        let bars: Vec<model::PriceBarEntity> = sqlx::query_as(
+            r#"SELECT * FROM api.find_price_bars($1::VARCHAR(32), $2::DATE, $3::DATE)"#,
+        )
+        .bind(ticker)
+        .bind(from)
+        .bind(to)
+        .fetch_all(self)
+        .await?;
+        Ok(bars)
+    }
+
+    async fn find_latest_price_bar(
+        &mut self,
+        ticker: &str,
+        date: NaiveDate,
+    ) -> model::ProvideResult<Option<model::PriceBarEntity>> {
+        let bar: Option<model::PriceBarEntity> =
+            sqlx::query_as(r#"SELECT * FROM api.find_latest_price_bar($1::VARCHAR(32), $2::DATE)"#)
+                .bind(ticker)
+                .bind(date)
+                .fetch_optional(self)
+                .await?;
+        Ok(bar)
+    }
+
+    async fn add_fx_rate(
+        &mut self,
+        base: &str,
+        quote: &str,
+        date: NaiveDate,
+        rate: f64,
+    ) -> model::ProvideResult<model::FxRateEntity> {
+        let rate: model::FxRateEntity = sqlx::query_as(
+            r#"SELECT * FROM api.add_fx_rate($1::CHAR(3), $2::CHAR(3), $3::DATE, $4::DOUBLE PRECISION)"#,
+        )
+        .bind(base)
+        .bind(quote)
+        .bind(date)
+        .bind(rate)
+        .fetch_one(self)
+        .await?;
+        Ok(rate)
+    }
+
+    async fn find_fx_rate(
+        &mut self,
+        base: &str,
+        quote: &str,
+        date: NaiveDate,
+    ) -> model::ProvideResult<Option<model::FxRateEntity>> {
+        let rate: Option<model::FxRateEntity> =
+            sqlx::query_as(r#"SELECT * FROM api.find_fx_rate($1::CHAR(3), $2::CHAR(3), $3::DATE)"#)
+                .bind(base)
+                .bind(quote)
+                .bind(date)
+                .fetch_optional(self)
+                .await?;
+        Ok(rate)
+    }
+
+    async fn list_portfolios(&mut self) -> model::ProvideResult<Vec<model::PortfolioEntity>> {
+        let portfolios: Vec<model::PortfolioEntity> =
+            sqlx::query_as(r#"SELECT * FROM api.list_portfolios()"#)
+                .fetch_all(self)
+                .await?;
+
+        Ok(portfolios)
+    }
+
+    async fn add_portfolio(
+        &mut self,
+        name: &str,
+        currency: &str,
+    ) -> model::ProvideResult<model::PortfolioEntity> {
+        let portfolio: model::PortfolioEntity =
+            sqlx::query_as(r#"SELECT * FROM api.add_portfolio($1::VARCHAR(255), $2::CHAR(3))"#)
+                .bind(name)
+                .bind(currency)
+                .fetch_one(self)
+                .await?;
+        Ok(portfolio)
+    }
+
+    async fn find_portfolio(
+        &mut self,
+        id: Uuid,
+    ) -> model::ProvideResult<Option<model::PortfolioEntity>> {
+        let portfolio: Option<model::PortfolioEntity> =
+            sqlx::query_as(r#"SELECT * FROM api.find_portfolio_by_id($1::UUID)"#)
+                .bind(id)
+                .fetch_optional(self)
+                .await?;
+        Ok(portfolio)
+    }
+
+    async fn lock_portfolio(
+        &mut self,
+        id: Uuid,
+    ) -> model::ProvideResult<Option<model::PortfolioEntity>> {
+        let portfolio: Option<model::PortfolioEntity> =
+            sqlx::query_as(r#"SELECT * FROM api.lock_portfolio($1::UUID)"#)
+                .bind(id)
+                .fetch_optional(self)
+                .await?;
+        Ok(portfolio)
+    }
+
+    async fn add_transaction(
+        &mut self,
+        transaction: &model::TransactionEntity,
+    ) -> model::ProvideResult<model::TransactionEntity> {
+        let transaction: model::TransactionEntity = sqlx::query_as(
+            r#"SELECT * FROM api.add_transaction($1::UUID, $2::UUID, $3::VARCHAR(16), $4::VARCHAR(32), $5::TIMESTAMPTZ, $6::DOUBLE PRECISION, $7::DOUBLE PRECISION, $8::DOUBLE PRECISION)"#,
+        )
+        .bind(transaction.id)
+        .bind(transaction.portfolio)
+        .bind(transaction.kind.as_str())
+        .bind(&transaction.ticker)
+        .bind(transaction.executed_at)
+        .bind(transaction.quantity)
+        .bind(transaction.amount)
+        .bind(transaction.fees)
+        .fetch_one(self)
+        .await?;
+        Ok(transaction)
+    }
+
+    async fn list_transactions(
+        &mut self,
+        portfolio: Uuid,
+    ) -> model::ProvideResult<Vec<model::TransactionEntity>> {
+        let transactions: Vec<model::TransactionEntity> =
+            sqlx::query_as(r#"SELECT * FROM api.list_transactions($1::UUID)"#)
+                .bind(portfolio)
+                .fetch_all(self)
+                .await?;
+        Ok(transactions)
+    }
+
+    async fn delete_transaction(
+        &mut self,
+        id: Uuid,
+    ) -> model::ProvideResult<model::TransactionEntity> {
+        let transaction: model::TransactionEntity =
+            sqlx::query_as(r#"SELECT * FROM api.delete_transaction($1::UUID)"#)
+                .bind(id)
+                .fetch_one(self)
+                .await?;
+        Ok(transaction)
+    }
+
+    async fn add_api_key(
+        &mut self,
+        name: &str,
+        hash: &str,
+        roles: &[String],
+    ) -> model::ProvideResult<model::ApiKeyEntity> {
+        let key: model::ApiKeyEntity = sqlx::query_as(
+            r#"SELECT * FROM api.add_api_key($1::VARCHAR(64), $2::CHAR(64), $3::VARCHAR(32)[])"#,
+        )
+        .bind(name)
+        .bind(hash)
+        .bind(roles)
+        .fetch_one(self)
+        .await?;
+        Ok(key)
+    }
+
+    async fn find_api_key_by_hash(
+        &mut self,
+        hash: &str,
+    ) -> model::ProvideResult<Option<model::ApiKeyEntity>> {
+        let key: Option<model::ApiKeyEntity> =
+            sqlx::query_as(r#"SELECT * FROM api.find_api_key_by_hash($1::CHAR(64))"#)
+                .bind(hash)
+                .fetch_optional(self)
+                .await?;
+        Ok(key)
+    }
+
+    async fn list_api_keys(&mut self) -> model::ProvideResult<Vec<model::ApiKeyEntity>> {
+        let keys: Vec<model::ApiKeyEntity> = sqlx::query_as(r#"SELECT * FROM api.list_api_keys()"#)
+            .fetch_all(self)
+            .await?;
+        Ok(keys)
+    }
+
+    async fn revoke_api_key(&mut self, name: &str) -> model::ProvideResult<model::ApiKeyEntity> {
+        let key: model::ApiKeyEntity =
+            sqlx::query_as(r#"SELECT * FROM api.revoke_api_key($1::VARCHAR(64))"#)
+                .bind(name)
+                .fetch_one(self)
+                .await?;
+        Ok(key)
+    }
+
+    async fn count_entities(&mut self) -> model::ProvideResult<Vec<(String, i64)>> {
+        let counts: Vec<(String, i64)> = sqlx::query_as(r#"SELECT * FROM api.entity_counts()"#)
+            .fetch_all(self)
+            .await?;
+        Ok(counts)
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::model::ProvideStock;
+    use super::model::{
+        AssetClass, CurrencyFilter, CurrencySortKey, Keyset, Pagination, PriceBarEntity,
+        ProvideError, ProvideStock, TradingHours, TransactionEntity, TransactionKind,
+    };
     use crate::utils::get_database_url;
+    use chrono::{NaiveDate, NaiveTime, TimeZone, Utc};
+    use futures::stream::TryStreamExt;
     use sqlx::postgres::PgPoolOptions;
+    use sqlx::Acquire;
     use std::time::Duration;
+    use uuid::Uuid;
 
     #[tokio::test]
     async fn test_add_and_find_currency() {
@@ -124,4 +787,571 @@ mod tests {
 
         assert_eq!(currency.unwrap().name, "Euro");
     }
+
+    #[tokio::test]
+    async fn test_update_and_delete_currency() {
+        let url = get_database_url();
+        let pool = PgPoolOptions::new()
+            .connect_timeout(Duration::new(2, 0))
+            .connect(&url)
+            .await
+            .expect("Database connection");
+        let mut conn = pool.acquire().await.expect("connection");
+
+        let _currency = conn
+            .add_currency("XTS", "Test Code", 2)
+            .await
+            .expect("add currency");
+
+        let currency = conn
+            .update_currency("XTS", Some(String::from("Testing Code")), None)
+            .await
+            .expect("update currency");
+
+        assert_eq!(currency.name, "Testing Code");
+        assert_eq!(currency.decimals, 2);
+
+        let currency = conn.delete_currency("XTS").await.expect("delete currency");
+
+        assert_eq!(currency.name, "Testing Code");
+
+        let res = conn.delete_currency("XTS").await;
+
+        assert!(matches!(res, Err(ProvideError::NotFound)));
+    }
+
+    #[tokio::test]
+    async fn test_find_currencies_by_codes() {
+        let url = get_database_url();
+        let pool = PgPoolOptions::new()
+            .connect_timeout(Duration::new(2, 0))
+            .connect(&url)
+            .await
+            .expect("Database connection");
+        let mut conn = pool.acquire().await.expect("connection");
+        let mut tx = conn.begin().await.expect("transaction");
+
+        for code in &["QBA", "QBB", "QBC"] {
+            tx.add_currency(code, "Batch", 2)
+                .await
+                .expect("add currency");
+        }
+
+        let codes = vec![
+            String::from("QBA"),
+            String::from("QBC"),
+            String::from("QBZ"),
+        ];
+        let mut currencies = tx.find_currencies(&codes).await.expect("find currencies");
+        currencies.sort_by(|a, b| a.code.cmp(&b.code));
+
+        let codes: Vec<&str> = currencies.iter().map(|c| c.code.as_str()).collect();
+        assert_eq!(codes, ["QBA", "QBC"]);
+    }
+
+    #[tokio::test]
+    async fn test_search_currencies_by_page() {
+        let url = get_database_url();
+        let pool = PgPoolOptions::new()
+            .connect_timeout(Duration::new(2, 0))
+            .connect(&url)
+            .await
+            .expect("Database connection");
+        let mut conn = pool.acquire().await.expect("connection");
+        let mut tx = conn.begin().await.expect("transaction");
+
+        for (code, name, decimals) in &[
+            ("QQA", "Quux Delta", 2),
+            ("QQB", "Quux Alpha", 0),
+            ("QQC", "Quux Charlie", 2),
+            ("QQD", "Quux Bravo", 3),
+            ("QRA", "Quux Echo", 2),
+        ] {
+            tx.add_currency(code, name, *decimals)
+                .await
+                .expect("add currency");
+        }
+        let filter = CurrencyFilter {
+            code_prefix: Some(String::from("QQ")),
+            name_contains: Some(String::from("quux")),
+            decimals: None,
+        };
+        let page = |after: Option<Keyset>, before: Option<Keyset>, backward, limit| Pagination {
+            after,
+            before,
+            backward,
+            limit,
+            offset: 0,
+        };
+        let codes = |currencies: Vec<super::model::CurrencyEntity>| {
+            currencies
+                .into_iter()
+                .map(|currency| currency.code)
+                .collect::<Vec<_>>()
+        };
+        let keyset = |sort, descending, key: &str, id: &str| Keyset {
+            sort,
+            descending,
+            key: String::from(key),
+            id: String::from(id),
+        };
+
+        let first = tx
+            .search_currencies(
+                &filter,
+                CurrencySortKey::Name,
+                false,
+                &page(None, None, false, 2),
+            )
+            .await
+            .expect("search");
+        let next = tx
+            .search_currencies(
+                &filter,
+                CurrencySortKey::Name,
+                false,
+                &page(
+                    Some(keyset(CurrencySortKey::Name, false, "Quux Bravo", "QQD")),
+                    None,
+                    false,
+                    2,
+                ),
+            )
+            .await
+            .expect("search");
+        let last = tx
+            .search_currencies(
+                &filter,
+                CurrencySortKey::Decimals,
+                true,
+                &page(
+                    None,
+                    Some(keyset(CurrencySortKey::Decimals, true, "0", "QQB")),
+                    true,
+                    2,
+                ),
+            )
+            .await
+            .expect("search");
+        let count = tx.count_currencies(&filter).await.expect("count");
+        // Filters are not truncated to the length of the column.
+        let longer = CurrencyFilter {
+            code_prefix: Some(String::from("QQAX")),
+            ..filter.clone()
+        };
+        let none = tx
+            .search_currencies(
+                &longer,
+                CurrencySortKey::Code,
+                false,
+                &page(None, None, false, 2),
+            )
+            .await
+            .expect("search");
+
+        assert_eq!(codes(first), vec!["QQB", "QQD"]);
+        assert_eq!(codes(next), vec!["QQC", "QQA"]);
+        // Sorted on decimals descending, then by code: QQD, QQC, QQA, QQB.
+        assert_eq!(codes(last), vec!["QQA", "QQC"]);
+        assert_eq!(count, 4);
+        assert!(none.is_empty());
+        assert_eq!(tx.count_currencies(&longer).await.expect("count"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_add_and_find_security() {
+        let url = get_database_url();
+        let pool = PgPoolOptions::new()
+            .connect_timeout(Duration::new(2, 0))
+            .connect(&url)
+            .await
+            .expect("Database connection");
+        let mut conn = pool.acquire().await.expect("connection");
+        // The transaction is never committed, so the test leaves the database untouched.
+        let mut tx = conn.begin().await.expect("transaction");
+
+        let _currency = tx
+            .add_currency("XXX", "No Currency", 2)
+            .await
+            .expect("add currency");
+
+        let _security = tx
+            .add_security(
+                "TEST",
+                "XX0000000001",
+                "Test Security",
+                "XXXX",
+                "XXX",
+                AssetClass::Equity,
+            )
+            .await
+            .expect("add security");
+
+        let security = tx
+            .find_security_by_isin("XX0000000001")
+            .await
+            .expect("find security")
+            .expect("security");
+
+        assert_eq!(security.ticker, "TEST");
+        assert_eq!(security.currency, "XXX");
+        assert_eq!(security.asset_class, AssetClass::Equity);
+
+        let res = tx.delete_currency("XXX").await;
+
+        assert!(matches!(res, Err(ProvideError::ReferenceViolation { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_add_and_update_exchange() {
+        let url = get_database_url();
+        let pool = PgPoolOptions::new()
+            .connect_timeout(Duration::new(2, 0))
+            .connect(&url)
+            .await
+            .expect("Database connection");
+        let mut conn = pool.acquire().await.expect("connection");
+        // The transaction is never committed, so the test leaves the database untouched.
+        let mut tx = conn.begin().await.expect("transaction");
+
+        let _currency = tx
+            .add_currency("XXX", "No Currency", 2)
+            .await
+            .expect("add currency");
+
+        let _exchange = tx
+            .add_exchange(
+                "XXXX",
+                "Test Exchange",
+                "FR",
+                "Europe/Paris",
+                TradingHours {
+                    open: NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
+                    close: NaiveTime::from_hms_opt(17, 30, 0).unwrap(),
+                },
+                "XXX",
+            )
+            .await
+            .expect("add exchange");
+
+        let exchange = tx
+            .update_exchange(
+                "XXXX",
+                None,
+                None,
+                None,
+                Some(TradingHours {
+                    open: NaiveTime::from_hms_opt(8, 0, 0).unwrap(),
+                    close: NaiveTime::from_hms_opt(16, 30, 0).unwrap(),
+                }),
+                None,
+            )
+            .await
+            .expect("update exchange");
+
+        assert_eq!(exchange.name, "Test Exchange");
+        assert_eq!(
+            exchange.trading_hours.open,
+            NaiveTime::from_hms_opt(8, 0, 0).unwrap()
+        );
+
+        let exchange = tx
+            .find_exchange("XXXX")
+            .await
+            .expect("find exchange")
+            .expect("exchange");
+
+        assert_eq!(
+            exchange.trading_hours.close,
+            NaiveTime::from_hms_opt(16, 30, 0).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_add_price_bars_is_idempotent() {
+        let url = get_database_url();
+        let pool = PgPoolOptions::new()
+            .connect_timeout(Duration::new(2, 0))
+            .connect(&url)
+            .await
+            .expect("Database connection");
+        let mut conn = pool.acquire().await.expect("connection");
+        // The transaction is never committed, so the test leaves the database untouched.
+        let mut tx = conn.begin().await.expect("transaction");
+
+        let _currency = tx
+            .add_currency("XXX", "No Currency", 2)
+            .await
+            .expect("add currency");
+
+        let _security = tx
+            .add_security(
+                "TEST",
+                "XX0000000001",
+                "Test Security",
+                "XXXX",
+                "XXX",
+                AssetClass::Equity,
+            )
+            .await
+            .expect("add security");
+
+        let bars = (1..=5)
+            .map(|day| PriceBarEntity {
+                ticker: String::from("TEST"),
+                date: NaiveDate::from_ymd_opt(2021, 3, day).unwrap(),
+                open: 10.0,
+                high: 12.0,
+                low: 9.0,
+                close: 11.0,
+                volume: 1000,
+            })
+            .collect::<Vec<_>>();
+
+        let count = tx.add_price_bars(&bars).await.expect("add price bars");
+        assert_eq!(count, 5);
+
+        let count = tx.add_price_bars(&bars).await.expect("add price bars");
+        assert_eq!(count, 5);
+
+        let stored = tx
+            .find_price_bars(
+                "TEST",
+                NaiveDate::from_ymd_opt(2021, 3, 2).unwrap(),
+                NaiveDate::from_ymd_opt(2021, 3, 4).unwrap(),
+            )
+            .await
+            .expect("find price bars");
+
+        assert_eq!(stored, bars[1..4].to_vec());
+
+        let latest = tx
+            .find_latest_price_bar("TEST", NaiveDate::from_ymd_opt(2021, 3, 31).unwrap())
+            .await
+            .expect("find latest price bar");
+
+        assert_eq!(latest, Some(bars[4].clone()));
+
+        let unknown = vec![PriceBarEntity {
+            ticker: String::from("UNKNOWN"),
+            ..bars[0].clone()
+        }];
+        let res = tx.add_price_bars(&unknown).await;
+
+        assert!(matches!(res, Err(ProvideError::ModelViolation { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_find_fx_rate_on_or_before_date() {
+        let url = get_database_url();
+        let pool = PgPoolOptions::new()
+            .connect_timeout(Duration::new(2, 0))
+            .connect(&url)
+            .await
+            .expect("Database connection");
+        let mut conn = pool.acquire().await.expect("connection");
+        // The transaction is never committed, so the test leaves the database untouched.
+        let mut tx = conn.begin().await.expect("transaction");
+
+        let _currency = tx
+            .add_currency("XXX", "No Currency", 2)
+            .await
+            .expect("add currency");
+
+        let _currency = tx
+            .add_currency("XTS", "Test Code", 2)
+            .await
+            .expect("add currency");
+
+        let _rate = tx
+            .add_fx_rate(
+                "XXX",
+                "XTS",
+                NaiveDate::from_ymd_opt(2021, 3, 1).unwrap(),
+                1.5,
+            )
+            .await
+            .expect("add fx rate");
+
+        let _rate = tx
+            .add_fx_rate(
+                "XXX",
+                "XTS",
+                NaiveDate::from_ymd_opt(2021, 3, 5).unwrap(),
+                1.6,
+            )
+            .await
+            .expect("add fx rate");
+
+        let rate = tx
+            .find_fx_rate("XXX", "XTS", NaiveDate::from_ymd_opt(2021, 3, 4).unwrap())
+            .await
+            .expect("find fx rate")
+            .expect("fx rate");
+
+        assert_eq!(rate.date, NaiveDate::from_ymd_opt(2021, 3, 1).unwrap());
+        assert_eq!(rate.rate, 1.5);
+
+        let rate = tx
+            .find_fx_rate("XXX", "XTS", NaiveDate::from_ymd_opt(2021, 2, 28).unwrap())
+            .await
+            .expect("find fx rate");
+
+        assert!(rate.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_portfolio_ledger() {
+        let url = get_database_url();
+        let pool = PgPoolOptions::new()
+            .connect_timeout(Duration::new(2, 0))
+            .connect(&url)
+            .await
+            .expect("Database connection");
+        let mut conn = pool.acquire().await.expect("connection");
+        // The transaction is never committed, so the test leaves the database untouched.
+        let mut tx = conn.begin().await.expect("transaction");
+
+        let _currency = tx
+            .add_currency("XXX", "No Currency", 2)
+            .await
+            .expect("add currency");
+
+        let _security = tx
+            .add_security(
+                "TEST",
+                "XX0000000001",
+                "Test Security",
+                "XXXX",
+                "XXX",
+                AssetClass::Equity,
+            )
+            .await
+            .expect("add security");
+
+        let portfolio = tx
+            .add_portfolio("Test Portfolio", "XXX")
+            .await
+            .expect("add portfolio");
+
+        let deposit = TransactionEntity {
+            id: Uuid::new_v4(),
+            portfolio: portfolio.id,
+            kind: TransactionKind::Deposit,
+            ticker: None,
+            executed_at: Utc.with_ymd_and_hms(2021, 3, 1, 9, 0, 0).unwrap(),
+            quantity: 0.0,
+            amount: 1000.0,
+            fees: 0.0,
+        };
+
+        let buy = TransactionEntity {
+            id: Uuid::new_v4(),
+            kind: TransactionKind::Buy,
+            ticker: Some(String::from("TEST")),
+            executed_at: Utc.with_ymd_and_hms(2021, 3, 2, 9, 0, 0).unwrap(),
+            quantity: 10.0,
+            amount: 500.0,
+            fees: 1.0,
+            ..deposit.clone()
+        };
+
+        // Recorded out of order, listed in execution order.
+        let _buy = tx.add_transaction(&buy).await.expect("add transaction");
+        let _deposit = tx.add_transaction(&deposit).await.expect("add transaction");
+
+        let ledger = tx
+            .list_transactions(portfolio.id)
+            .await
+            .expect("list transactions");
+
+        assert_eq!(ledger, vec![deposit, buy.clone()]);
+
+        let unknown = TransactionEntity {
+            id: Uuid::new_v4(),
+            ticker: Some(String::from("UNKNOWN")),
+            ..buy
+        };
+        let res = tx.add_transaction(&unknown).await;
+
+        assert!(matches!(res, Err(ProvideError::ModelViolation { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_export_currencies_since() {
+        let url = get_database_url();
+        let pool = PgPoolOptions::new()
+            .connect_timeout(Duration::new(2, 0))
+            .connect(&url)
+            .await
+            .expect("Database connection");
+        let mut conn = pool.acquire().await.expect("connection");
+        let mut tx = conn.begin().await.expect("transaction");
+
+        for code in &["QEB", "QEA"] {
+            tx.add_currency(code, "Export", 2)
+                .await
+                .expect("add currency");
+        }
+
+        let since = Utc::now() - chrono::Duration::hours(1);
+        let currencies: Vec<_> = super::export_currencies(&mut tx, Some(since))
+            .try_collect()
+            .await
+            .expect("export currencies");
+        let codes: Vec<&str> = currencies
+            .iter()
+            .map(|c| c.code.as_str())
+            .filter(|code| code.starts_with("QE"))
+            .collect();
+        assert_eq!(codes, ["QEA", "QEB"]);
+
+        let since = Utc::now() + chrono::Duration::hours(1);
+        let currencies: Vec<_> = super::export_currencies(&mut tx, Some(since))
+            .try_collect()
+            .await
+            .expect("export currencies");
+        assert!(currencies.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_revoked_api_key_is_not_found() {
+        let url = get_database_url();
+        let pool = PgPoolOptions::new()
+            .connect_timeout(Duration::new(2, 0))
+            .connect(&url)
+            .await
+            .expect("Database connection");
+        let mut conn = pool.acquire().await.expect("connection");
+        // The transaction is never committed, so the test leaves the database untouched.
+        let mut tx = conn.begin().await.expect("transaction");
+        let hash = "0".repeat(64);
+
+        let key = tx
+            .add_api_key("test", &hash, &[String::from("reader")])
+            .await
+            .expect("api key");
+        let found = tx.find_api_key_by_hash(&hash).await.expect("api key");
+        let duplicate = tx.add_api_key("test", &"1".repeat(64), &[]).await;
+
+        assert_eq!(key.roles, vec![String::from("reader")]);
+        assert_eq!(found.map(|found| found.id), Some(key.id));
+        assert!(matches!(
+            duplicate,
+            Err(ProvideError::UniqueViolation { field: Some(ref field), .. }) if field == "name"
+        ));
+
+        // The failed insertion aborted the transaction.
+        drop(tx);
+        let mut tx = conn.begin().await.expect("transaction");
+        tx.add_api_key("test", &hash, &[]).await.expect("api key");
+        let revoked = tx.revoke_api_key("test").await.expect("revoked");
+
+        assert!(revoked.revoked_at.is_some());
+        assert!(tx
+            .find_api_key_by_hash(&hash)
+            .await
+            .expect("api key")
+            .is_none());
+    }
 }
@@ -0,0 +1,591 @@
+use chrono::{DateTime, Datelike, NaiveDate, TimeZone, Utc};
+use clap::ArgMatches;
+use futures::stream::{BoxStream, StreamExt};
+use parquet::data_type::{ByteArray, ByteArrayType, DoubleType, Int32Type, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+use serde::Serialize;
+use snafu::{ResultExt, Snafu};
+use sqlx::postgres::PgConnection;
+use sqlx::Connection;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use stocks::db::model::{CurrencyEntity, PriceBarEntity, ProvideError, SecurityEntity};
+use stocks::db::pg;
+use stocks::settings::Settings;
+
+/// Rows held in memory before they are written to a Parquet file, as one row group.
+const ROW_GROUP_SIZE: usize = 10_000;
+
+/// Days from 0001-01-01 to 1970-01-01, Parquet dates count days since the latter.
+const UNIX_EPOCH_DAYS_FROM_CE: i32 = 719_163;
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Could not get database connection: {}", source))]
+    DBConnectionError { source: sqlx::Error },
+    #[snafu(display("Could not generate settings: {}", source))]
+    SettingsError {
+        #[snafu(backtrace)]
+        source: stocks::settings::Error,
+    },
+    #[snafu(display("Could not read rows: {}", source))]
+    DBProvideError { source: ProvideError },
+    #[snafu(display("Could not write {}: {}", path, source))]
+    IOError {
+        path: String,
+        source: std::io::Error,
+    },
+    #[snafu(display("Could not write CSV: {}", source))]
+    CsvError { source: csv::Error },
+    #[snafu(display("Could not write NDJSON: {}", source))]
+    JsonError { source: serde_json::Error },
+    #[snafu(display("Could not write Parquet: {}", source))]
+    ParquetError {
+        source: parquet::errors::ParquetError,
+    },
+    #[snafu(display("Command Line Interface Error: {}", msg))]
+    CLIError { msg: String },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Entity {
+    Currencies,
+    Securities,
+    Prices,
+}
+
+impl FromStr for Entity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "currencies" => Ok(Entity::Currencies),
+            "securities" => Ok(Entity::Securities),
+            "prices" => Ok(Entity::Prices),
+            _ => Err(format!(
+                "Unknown entity '{}', expected currencies, securities or prices",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Csv,
+    Ndjson,
+    Parquet,
+}
+
+impl Format {
+    /// Parquet for `.parquet` files, NDJSON for `.ndjson`, `.jsonl` and `.json` files, CSV
+    /// otherwise.
+    fn from_file_name(name: &str) -> Format {
+        let name = name.to_lowercase();
+        if name.ends_with(".parquet") {
+            Format::Parquet
+        } else if [".ndjson", ".jsonl", ".json"]
+            .iter()
+            .any(|extension| name.ends_with(extension))
+        {
+            Format::Ndjson
+        } else {
+            Format::Csv
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "ndjson" => Ok(Format::Ndjson),
+            "parquet" => Ok(Format::Parquet),
+            _ => Err(format!(
+                "Unknown format '{}', expected csv, ndjson or parquet",
+                s
+            )),
+        }
+    }
+}
+
+/// The values of a column of a row group.
+enum Column {
+    Text(Vec<ByteArray>),
+    Int32(Vec<i32>),
+    Int64(Vec<i64>),
+    Double(Vec<f64>),
+}
+
+/// A row of an export. Its fields are named like the ones `service import` reads.
+trait Record: Serialize + Sized {
+    /// The Parquet schema, with a column per field, in the same order.
+    const SCHEMA: &'static str;
+
+    /// The values of each column of the schema.
+    fn columns(records: &[Self]) -> Vec<Column>;
+}
+
+fn text<T>(records: &[T], field: impl Fn(&T) -> &str) -> Column {
+    Column::Text(records.iter().map(|r| ByteArray::from(field(r))).collect())
+}
+
+fn date(date: NaiveDate) -> i32 {
+    date.num_days_from_ce() - UNIX_EPOCH_DAYS_FROM_CE
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct CurrencyRecord {
+    code: String,
+    name: String,
+    decimals: i32,
+}
+
+impl From<CurrencyEntity> for CurrencyRecord {
+    fn from(entity: CurrencyEntity) -> Self {
+        let CurrencyEntity {
+            code,
+            name,
+            decimals,
+        } = entity;
+        CurrencyRecord {
+            code,
+            name,
+            decimals,
+        }
+    }
+}
+
+impl Record for CurrencyRecord {
+    const SCHEMA: &'static str = "
+        message currency {
+            REQUIRED BYTE_ARRAY code (UTF8);
+            REQUIRED BYTE_ARRAY name (UTF8);
+            REQUIRED INT32 decimals;
+        }";
+
+    fn columns(records: &[Self]) -> Vec<Column> {
+        vec![
+            text(records, |r| &r.code),
+            text(records, |r| &r.name),
+            Column::Int32(records.iter().map(|r| r.decimals).collect()),
+        ]
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct SecurityRecord {
+    ticker: String,
+    isin: String,
+    name: String,
+    exchange: String,
+    currency: String,
+    asset_class: String,
+}
+
+impl From<SecurityEntity> for SecurityRecord {
+    fn from(entity: SecurityEntity) -> Self {
+        SecurityRecord {
+            ticker: entity.ticker,
+            isin: entity.isin,
+            name: entity.name,
+            exchange: entity.exchange,
+            currency: entity.currency,
+            asset_class: String::from(entity.asset_class.as_str()),
+        }
+    }
+}
+
+impl Record for SecurityRecord {
+    const SCHEMA: &'static str = "
+        message security {
+            REQUIRED BYTE_ARRAY ticker (UTF8);
+            REQUIRED BYTE_ARRAY isin (UTF8);
+            REQUIRED BYTE_ARRAY name (UTF8);
+            REQUIRED BYTE_ARRAY exchange (UTF8);
+            REQUIRED BYTE_ARRAY currency (UTF8);
+            REQUIRED BYTE_ARRAY asset_class (UTF8);
+        }";
+
+    fn columns(records: &[Self]) -> Vec<Column> {
+        vec![
+            text(records, |r| &r.ticker),
+            text(records, |r| &r.isin),
+            text(records, |r| &r.name),
+            text(records, |r| &r.exchange),
+            text(records, |r| &r.currency),
+            text(records, |r| &r.asset_class),
+        ]
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct PriceRecord {
+    ticker: String,
+    date: NaiveDate,
+    open: f64,
+    high: f64,
+    low: f64,
+    close: f64,
+    volume: i64,
+}
+
+impl From<PriceBarEntity> for PriceRecord {
+    fn from(entity: PriceBarEntity) -> Self {
+        PriceRecord {
+            ticker: entity.ticker,
+            date: entity.date,
+            open: entity.open,
+            high: entity.high,
+            low: entity.low,
+            close: entity.close,
+            volume: entity.volume,
+        }
+    }
+}
+
+impl Record for PriceRecord {
+    const SCHEMA: &'static str = "
+        message price {
+            REQUIRED BYTE_ARRAY ticker (UTF8);
+            REQUIRED INT32 date (DATE);
+            REQUIRED DOUBLE open;
+            REQUIRED DOUBLE high;
+            REQUIRED DOUBLE low;
+            REQUIRED DOUBLE close;
+            REQUIRED INT64 volume;
+        }";
+
+    fn columns(records: &[Self]) -> Vec<Column> {
+        vec![
+            text(records, |r| &r.ticker),
+            Column::Int32(records.iter().map(|r| date(r.date)).collect()),
+            Column::Double(records.iter().map(|r| r.open).collect()),
+            Column::Double(records.iter().map(|r| r.high).collect()),
+            Column::Double(records.iter().map(|r| r.low).collect()),
+            Column::Double(records.iter().map(|r| r.close).collect()),
+            Column::Int64(records.iter().map(|r| r.volume).collect()),
+        ]
+    }
+}
+
+/// Where the records go, one at a time. Parquet files are written by row groups, so records
+/// are held until a row group is full.
+enum Sink<W: Write + Send, T> {
+    Csv(csv::Writer<W>),
+    Ndjson(W),
+    Parquet {
+        writer: SerializedFileWriter<W>,
+        records: Vec<T>,
+    },
+}
+
+impl<W: Write + Send, T: Record> Sink<W, T> {
+    fn new(out: W, format: Format) -> Result<Self, Error> {
+        match format {
+            Format::Csv => Ok(Sink::Csv(csv::Writer::from_writer(out))),
+            Format::Ndjson => Ok(Sink::Ndjson(out)),
+            Format::Parquet => {
+                let schema = parse_message_type(T::SCHEMA).context(ParquetError)?;
+                let properties = WriterProperties::builder().build();
+                let writer = SerializedFileWriter::new(out, Arc::new(schema), Arc::new(properties))
+                    .context(ParquetError)?;
+                Ok(Sink::Parquet {
+                    writer,
+                    records: Vec::with_capacity(ROW_GROUP_SIZE),
+                })
+            }
+        }
+    }
+
+    fn write(&mut self, record: T) -> Result<(), Error> {
+        match self {
+            Sink::Csv(writer) => writer.serialize(record).context(CsvError),
+            Sink::Ndjson(out) => {
+                serde_json::to_writer(&mut *out, &record).context(JsonError)?;
+                out.write_all(b"\n")
+                    .map_err(serde_json::Error::io)
+                    .context(JsonError)
+            }
+            Sink::Parquet { writer, records } => {
+                records.push(record);
+                if records.len() == ROW_GROUP_SIZE {
+                    write_row_group(writer, records)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Write what is left, and return the output.
+    fn finish(self) -> Result<W, Error> {
+        match self {
+            Sink::Csv(writer) => writer
+                .into_inner()
+                .map_err(|err| csv::Error::from(err.into_error()))
+                .context(CsvError),
+            Sink::Ndjson(out) => Ok(out),
+            Sink::Parquet {
+                mut writer,
+                mut records,
+            } => {
+                if !records.is_empty() {
+                    write_row_group(&mut writer, &mut records)?;
+                }
+                writer.into_inner().context(ParquetError)
+            }
+        }
+    }
+}
+
+fn write_row_group<W: Write + Send, T: Record>(
+    writer: &mut SerializedFileWriter<W>,
+    records: &mut Vec<T>,
+) -> Result<(), Error> {
+    let mut row_group = writer.next_row_group().context(ParquetError)?;
+    for column in T::columns(records) {
+        let mut writer = row_group
+            .next_column()
+            .context(ParquetError)?
+            .ok_or_else(|| Error::CLIError {
+                msg: String::from("The Parquet schema has fewer columns than the records"),
+            })?;
+        match column {
+            Column::Text(values) => writer
+                .typed::<ByteArrayType>()
+                .write_batch(&values, None, None),
+            Column::Int32(values) => writer.typed::<Int32Type>().write_batch(&values, None, None),
+            Column::Int64(values) => writer.typed::<Int64Type>().write_batch(&values, None, None),
+            Column::Double(values) => writer
+                .typed::<DoubleType>()
+                .write_batch(&values, None, None),
+        }
+        .context(ParquetError)?;
+        writer.close().context(ParquetError)?;
+    }
+    row_group.close().context(ParquetError)?;
+    records.clear();
+    Ok(())
+}
+
+/// Write the rows as they are read, and return how many there were.
+async fn export<E, T: Record + From<E>, W: Write + Send>(
+    mut rows: BoxStream<'_, Result<E, ProvideError>>,
+    format: Format,
+    out: W,
+) -> Result<(usize, W), Error> {
+    let mut sink = Sink::<W, T>::new(out, format)?;
+    let mut count = 0;
+    while let Some(row) = rows.next().await {
+        sink.write(T::from(row.context(DBProvideError)?))?;
+        count += 1;
+    }
+    let out = sink.finish()?;
+    Ok((count, out))
+}
+
+/// A date is taken as midnight UTC.
+fn parse_since(since: &str) -> Result<DateTime<Utc>, String> {
+    DateTime::parse_from_rfc3339(since)
+        .map(|since| since.with_timezone(&Utc))
+        .or_else(|_| {
+            NaiveDate::parse_from_str(since, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+                .map(|since| Utc.from_utc_datetime(&since))
+                .ok_or(())
+        })
+        .map_err(|_| {
+            format!(
+                "Invalid time '{}', expected a date (2021-04-01) or an RFC 3339 time (2021-04-01T12:00:00Z)",
+                since
+            )
+        })
+}
+
+/// Export currencies, securities or daily prices to a file.
+///
+/// Rows are written as they are read from the database. They go to a temporary file next to
+/// the output, which replaces it once complete, so a failed export leaves no partial file.
+#[allow(clippy::needless_lifetimes)]
+pub async fn run<'a>(matches: &ArgMatches<'a>) -> Result<(), Error> {
+    let settings = Settings::new(matches).context(SettingsError)?;
+    let matches = matches
+        .subcommand_matches("export")
+        .ok_or_else(|| Error::CLIError {
+            msg: String::from("Expected the export subcommand"),
+        })?;
+
+    let path = matches.value_of("out").unwrap_or_default();
+    let entity = matches
+        .value_of("entity")
+        .unwrap_or_default()
+        .parse::<Entity>()
+        .map_err(|msg| Error::CLIError { msg })?;
+    let format = match matches.value_of("format") {
+        Some(format) => format
+            .parse::<Format>()
+            .map_err(|msg| Error::CLIError { msg })?,
+        None => Format::from_file_name(path),
+    };
+    let since = matches
+        .value_of("since")
+        .map(parse_since)
+        .transpose()
+        .map_err(|msg| Error::CLIError { msg })?;
+
+    let mut conn = PgConnection::connect(&settings.database.url)
+        .await
+        .context(DBConnectionError)?;
+
+    let partial = format!("{}.part", path);
+    let out = File::create(&partial).context(IOError { path: &partial })?;
+    let out = BufWriter::new(out);
+
+    let exported = match entity {
+        Entity::Currencies => {
+            let rows = pg::export_currencies(&mut conn, since);
+            export::<_, CurrencyRecord, _>(rows, format, out).await
+        }
+        Entity::Securities => {
+            let rows = pg::export_securities(&mut conn, since);
+            export::<_, SecurityRecord, _>(rows, format, out).await
+        }
+        Entity::Prices => {
+            let rows = pg::export_price_bars(&mut conn, since);
+            export::<_, PriceRecord, _>(rows, format, out).await
+        }
+    };
+
+    let count = exported
+        .and_then(|(count, mut out)| {
+            out.flush().context(IOError { path: &partial })?;
+            Ok(count)
+        })
+        .inspect_err(|_| {
+            let _ = std::fs::remove_file(&partial);
+        })?;
+    std::fs::rename(&partial, path).context(IOError { path })?;
+
+    eprintln!(
+        "Exported {} {}{} to {}",
+        count,
+        matches.value_of("entity").unwrap_or_default(),
+        since
+            .map(|since| format!(" changed since {}", since.to_rfc3339()))
+            .unwrap_or_default(),
+        path
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::record::{Field, RowAccessor};
+
+    fn bars() -> Vec<Result<PriceBarEntity, ProvideError>> {
+        vec![
+            Ok(PriceBarEntity {
+                ticker: String::from("ACME"),
+                date: NaiveDate::from_ymd_opt(2021, 1, 4).expect("date"),
+                open: 10.0,
+                high: 12.5,
+                low: 9.75,
+                close: 12.0,
+                volume: 1200,
+            }),
+            Ok(PriceBarEntity {
+                ticker: String::from("ACME"),
+                date: NaiveDate::from_ymd_opt(2021, 1, 5).expect("date"),
+                open: 12.0,
+                high: 13.0,
+                low: 11.5,
+                close: 11.75,
+                volume: 800,
+            }),
+        ]
+    }
+
+    #[tokio::test]
+    async fn test_export_prices_as_csv_and_ndjson() {
+        let rows = futures::stream::iter(bars()).boxed();
+        let (count, csv) = export::<_, PriceRecord, _>(rows, Format::Csv, Vec::new())
+            .await
+            .expect("csv export");
+        assert_eq!(count, 2);
+        assert_eq!(
+            String::from_utf8(csv).expect("utf8"),
+            "ticker,date,open,high,low,close,volume\n\
+             ACME,2021-01-04,10.0,12.5,9.75,12.0,1200\n\
+             ACME,2021-01-05,12.0,13.0,11.5,11.75,800\n"
+        );
+
+        let rows = futures::stream::iter(bars()).boxed();
+        let (_, ndjson) = export::<_, PriceRecord, _>(rows, Format::Ndjson, Vec::new())
+            .await
+            .expect("ndjson export");
+        let lines: Vec<serde_json::Value> = String::from_utf8(ndjson)
+            .expect("utf8")
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("json line"))
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1]["date"], "2021-01-05");
+        assert_eq!(lines[1]["volume"], 800);
+    }
+
+    #[tokio::test]
+    async fn test_export_prices_as_parquet() {
+        let path = std::env::temp_dir().join(format!("export-{}.parquet", std::process::id()));
+        let out = File::create(&path).expect("parquet file");
+        let rows = futures::stream::iter(bars()).boxed();
+        let (count, out) = export::<_, PriceRecord, _>(rows, Format::Parquet, out)
+            .await
+            .expect("parquet export");
+        drop(out);
+        assert_eq!(count, 2);
+
+        let reader = SerializedFileReader::new(File::open(&path).expect("open")).expect("reader");
+        let rows: Vec<_> = reader
+            .get_row_iter(None)
+            .expect("rows")
+            .map(|row| row.expect("row"))
+            .collect();
+        std::fs::remove_file(&path).expect("remove");
+
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].get_string(0).expect("ticker"), "ACME");
+        assert_eq!(rows[0].get_double(3).expect("high"), 12.5);
+        assert_eq!(rows[1].get_long(6).expect("volume"), 800);
+        let (_, day) = rows[1].get_column_iter().nth(1).expect("date column");
+        assert_eq!(
+            day,
+            &Field::Date(date(NaiveDate::from_ymd_opt(2021, 1, 5).expect("date")))
+        );
+        assert_eq!(date(NaiveDate::from_ymd_opt(1970, 1, 2).expect("date")), 1);
+    }
+
+    #[test]
+    fn test_since_is_a_date_or_a_time() {
+        assert_eq!(
+            parse_since("2021-04-01").expect("date"),
+            Utc.with_ymd_and_hms(2021, 4, 1, 0, 0, 0).unwrap()
+        );
+        assert_eq!(
+            parse_since("2021-04-01T14:30:00+02:00").expect("time"),
+            Utc.with_ymd_and_hms(2021, 4, 1, 12, 30, 0).unwrap()
+        );
+        assert!(parse_since("yesterday").is_err());
+    }
+}
@@ -0,0 +1,117 @@
+use clap::ArgMatches;
+use snafu::{ResultExt, Snafu};
+use sqlx::postgres::PgConnection;
+use sqlx::Connection;
+
+use stocks::api::import::{self, Format, ImportReport, OnConflict};
+use stocks::settings::Settings;
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Could not get database connection: {}", source))]
+    DBConnectionError { source: sqlx::Error },
+    #[snafu(display("Could not generate settings: {}", source))]
+    SettingsError {
+        #[snafu(backtrace)]
+        source: stocks::settings::Error,
+    },
+    #[snafu(display("Could not read {}: {}", path, source))]
+    IOError {
+        path: String,
+        source: std::io::Error,
+    },
+    #[snafu(display("Could not import {}: {}", path, source))]
+    ImportError {
+        path: String,
+        source: stocks::api::error::Error,
+    },
+    #[snafu(display("Command Line Interface Error: {}", msg))]
+    CLIError { msg: String },
+}
+
+/// Import currencies or daily prices, and print what became of each row.
+///
+/// Failed rows do not stop the import, their lines are listed with the reason.
+#[allow(clippy::needless_lifetimes)]
+pub async fn run<'a>(matches: &ArgMatches<'a>) -> Result<(), Error> {
+    let settings = Settings::new(matches).context(SettingsError)?;
+    let matches = matches
+        .subcommand_matches("import")
+        .ok_or_else(|| Error::CLIError {
+            msg: String::from("Expected the import subcommand"),
+        })?;
+
+    let path = matches.value_of("file").unwrap_or_default();
+    let format = match matches.value_of("format") {
+        Some(format) => format
+            .parse::<Format>()
+            .map_err(|msg| Error::CLIError { msg })?,
+        None => Format::from_file_name(path),
+    };
+    let on_conflict = matches
+        .value_of("on conflict")
+        .unwrap_or("skip")
+        .parse::<OnConflict>()
+        .map_err(|msg| Error::CLIError { msg })?;
+    let batch_size = match matches.value_of("batch size") {
+        Some(size) => size.parse::<usize>().map_err(|_| Error::CLIError {
+            msg: format!("Invalid batch size '{}'", size),
+        })?,
+        None => import::BATCH_SIZE,
+    };
+
+    let data = std::fs::read(path).context(IOError { path })?;
+    // The report goes to whoever holds the database credentials, not to clients of the service.
+    stocks::api::error::set_debug(true);
+
+    let mut conn = PgConnection::connect(&settings.database.url)
+        .await
+        .context(DBConnectionError)?;
+
+    let (report, error) = match matches.value_of("entity") {
+        Some("currencies") => {
+            let rows = import::parse(&data, format).context(ImportError { path })?;
+            let imported =
+                import::import_currencies(&mut conn, rows, on_conflict, batch_size).await;
+            (imported.report, imported.error)
+        }
+        Some("prices") => {
+            let rows = import::parse(&data, format).context(ImportError { path })?;
+            let imported = import::import_prices(&mut conn, rows, on_conflict, batch_size).await;
+            (imported.report, imported.error)
+        }
+        _ => {
+            return Err(Error::CLIError {
+                msg: String::from("Expected currencies or prices"),
+            })
+        }
+    };
+
+    // The rows imported before the store failed are reported too.
+    print_report(path, &report);
+
+    match error {
+        Some(source) => Err(Error::ImportError {
+            path: String::from(path),
+            source,
+        }),
+        None => Ok(()),
+    }
+}
+
+fn print_report(path: &str, report: &ImportReport) {
+    for row in &report.rows {
+        println!(
+            "{:>6} {:<24} {:<8} {}",
+            row.line,
+            row.key.as_deref().unwrap_or("-"),
+            format!("{:?}", row.outcome).to_lowercase(),
+            row.message.as_deref().unwrap_or_default()
+        );
+    }
+    eprintln!(
+        "Imported {}: {} inserted, {} updated, {} skipped, {} failed",
+        path, report.inserted, report.updated, report.skipped, report.failed
+    );
+}
@@ -1,8 +1,175 @@
 use clap::ArgMatches;
+use snafu::{ResultExt, Snafu};
+use sqlx::migrate::MigrateError;
+use sqlx::postgres::PgConnection;
+use sqlx::Connection;
 
-use stocks::error;
+use stocks::db::migrate;
+use stocks::db::model::{ProvideError, ProvideStock};
+use stocks::settings::Settings;
 
+/// ISO 4217 currencies. The codes which have no minor units (precious metals, SDR, testing
+/// and 'no currency' codes) are seeded with 0 decimals.
+const CURRENCIES: &str = include_str!("../data/iso4217.csv");
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Could not get database connection: {}", source))]
+    DBConnectionError { source: sqlx::Error },
+    #[snafu(display("Could not generate settings: {}", source))]
+    SettingsError {
+        #[snafu(backtrace)]
+        source: stocks::settings::Error,
+    },
+    #[snafu(display("Migration Error: {}", source))]
+    MigrationError { source: MigrateError },
+    #[snafu(display("{}: {}", msg, source))]
+    DBProvideError { msg: String, source: ProvideError },
+    #[snafu(display("Invalid currency data, line {}: {}", line, msg))]
+    DataError { line: usize, msg: String },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Currency {
+    code: String,
+    name: String,
+    decimals: i32,
+}
+
+/// Parse the bundled currencies, a CSV file with a header and no quoted fields.
+fn currencies() -> Result<Vec<Currency>, Error> {
+    CURRENCIES
+        .lines()
+        .enumerate()
+        .skip(1)
+        .filter(|(_, row)| !row.trim().is_empty())
+        .map(|(index, row)| {
+            let line = index + 1;
+            let fields = row.split(',').collect::<Vec<_>>();
+            match fields.as_slice() {
+                [code, name, decimals] => Ok(Currency {
+                    code: String::from(*code),
+                    name: String::from(*name),
+                    decimals: decimals.parse().map_err(|_| Error::DataError {
+                        line,
+                        msg: format!("invalid minor units '{}'", decimals),
+                    })?,
+                }),
+                _ => Err(Error::DataError {
+                    line,
+                    msg: format!("expected 3 fields, got {}", fields.len()),
+                }),
+            }
+        })
+        .collect()
+}
+
+/// Create the schema if needed, and add the ISO 4217 currencies missing from the store.
+///
+/// Currencies already in the store are left as they are, so running it again changes
+/// nothing. With `--dry-run`, the changes are printed but not made.
 #[allow(clippy::needless_lifetimes)]
-pub async fn init<'a>(_matches: &ArgMatches<'a>) -> Result<(), error::Error> {
+pub async fn init<'a>(matches: &ArgMatches<'a>) -> Result<(), Error> {
+    let settings = Settings::new(matches).context(SettingsError)?;
+    let dry_run = matches
+        .subcommand_matches("init")
+        .is_some_and(|matches| matches.is_present("dry run"));
+
+    let currencies = currencies()?;
+
+    let mut conn = PgConnection::connect(&settings.database.url)
+        .await
+        .context(DBConnectionError)?;
+
+    let status = migrate::status(&mut conn).await.context(MigrationError)?;
+    // The currencies are in the first migration: without it, none is stored.
+    let has_schema = status.iter().any(|migration| migration.applied);
+
+    if dry_run {
+        for migration in status.iter().filter(|migration| !migration.applied) {
+            println!(
+                "Would apply migration {}/{}",
+                migration.version, migration.description
+            );
+        }
+    } else {
+        for (migration, _) in migrate::up(&mut conn).await.context(MigrationError)? {
+            println!(
+                "Applied migration {}/{}",
+                migration.version, migration.description
+            );
+        }
+    }
+
+    let mut tx = conn.begin().await.context(DBConnectionError)?;
+
+    let mut added = 0;
+    for currency in &currencies {
+        let existing = if has_schema || !dry_run {
+            tx.find_currency(&currency.code)
+                .await
+                .context(DBProvideError {
+                    msg: format!("Could not find currency {}", currency.code),
+                })?
+        } else {
+            None
+        };
+        if existing.is_some() {
+            continue;
+        }
+        added += 1;
+        if dry_run {
+            println!(
+                "Would add currency {} ({}, {} decimals)",
+                currency.code, currency.name, currency.decimals
+            );
+        } else {
+            tx.add_currency(&currency.code, &currency.name, currency.decimals)
+                .await
+                .context(DBProvideError {
+                    msg: format!("Could not add currency {}", currency.code),
+                })?;
+        }
+    }
+
+    tx.commit().await.context(DBConnectionError)?;
+
+    println!(
+        "{} {} currencies, {} already present",
+        if dry_run { "Would add" } else { "Added" },
+        added,
+        currencies.len() - added
+    );
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_bundled_currencies() {
+        let currencies = currencies().expect("currencies");
+        let codes = currencies
+            .iter()
+            .map(|currency| currency.code.as_str())
+            .collect::<HashSet<_>>();
+
+        assert_eq!(codes.len(), currencies.len());
+        assert!(codes.contains("EUR"));
+        assert!(currencies.contains(&Currency {
+            code: String::from("XAU"),
+            name: String::from("Gold"),
+            decimals: 0,
+        }));
+        assert!(currencies.iter().all(|currency| {
+            currency.code.len() == 3
+                && currency.code.chars().all(|c| c.is_ascii_uppercase())
+                && !currency.name.is_empty()
+                && (0..=4).contains(&currency.decimals)
+        }));
+    }
+}
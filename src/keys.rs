@@ -0,0 +1,91 @@
+use clap::ArgMatches;
+use snafu::{ResultExt, Snafu};
+use sqlx::postgres::PgConnection;
+use sqlx::Connection;
+
+use stocks::api::auth;
+use stocks::db::model::{ProvideError, ProvideStock};
+use stocks::settings::Settings;
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Could not get database connection: {}", source))]
+    DBConnectionError { source: sqlx::Error },
+    #[snafu(display("Could not generate settings: {}", source))]
+    SettingsError {
+        #[snafu(backtrace)]
+        source: stocks::settings::Error,
+    },
+    #[snafu(display("{}: {}", msg, source))]
+    DBProvideError { msg: String, source: ProvideError },
+    #[snafu(display("Command Line Interface Error: {}", msg))]
+    CLIError { msg: String },
+}
+
+/// Manage the API keys: create one, list them, or revoke one.
+///
+/// A new key is printed once, only its digest is stored.
+#[allow(clippy::needless_lifetimes)]
+pub async fn run<'a>(matches: &ArgMatches<'a>) -> Result<(), Error> {
+    let settings = Settings::new(matches).context(SettingsError)?;
+
+    let mut conn = PgConnection::connect(&settings.database.url)
+        .await
+        .context(DBConnectionError)?;
+
+    let command = matches
+        .subcommand_matches("keys")
+        .map(|matches| matches.subcommand());
+
+    match command {
+        Some(("create", Some(matches))) => {
+            let name = matches.value_of("name").unwrap_or_default();
+            let roles = matches
+                .values_of("role")
+                .map(|roles| roles.map(String::from).collect::<Vec<_>>())
+                .unwrap_or_default();
+            let key = auth::generate_api_key();
+            conn.add_api_key(name, &auth::hash_api_key(&key), &roles)
+                .await
+                .context(DBProvideError {
+                    msg: format!("Could not add API key {}", name),
+                })?;
+            println!("{}", key);
+            eprintln!("Created API key {}, it will not be shown again", name);
+        }
+        Some(("list", Some(_))) => {
+            let keys = conn.list_api_keys().await.context(DBProvideError {
+                msg: String::from("Could not list API keys"),
+            })?;
+            for key in keys {
+                println!(
+                    "{:<24} {:<24} created {} {}",
+                    key.name,
+                    key.roles.join(","),
+                    key.created_at.format("%Y-%m-%d %H:%M:%S"),
+                    key.revoked_at
+                        .map(|revoked_at| format!(
+                            "revoked {}",
+                            revoked_at.format("%Y-%m-%d %H:%M:%S")
+                        ))
+                        .unwrap_or_default()
+                );
+            }
+        }
+        Some(("revoke", Some(matches))) => {
+            let name = matches.value_of("name").unwrap_or_default();
+            conn.revoke_api_key(name).await.context(DBProvideError {
+                msg: format!("Could not revoke API key {}", name),
+            })?;
+            println!("Revoked API key {}", name);
+        }
+        _ => {
+            return Err(Error::CLIError {
+                msg: String::from("Expected one of create, list, revoke"),
+            })
+        }
+    }
+
+    Ok(())
+}
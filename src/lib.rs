@@ -1,5 +1,8 @@
 pub mod api;
 pub mod db;
+pub mod logging;
+pub mod pnl;
 pub mod settings;
 pub mod state;
+pub mod telemetry;
 pub mod utils;
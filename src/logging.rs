@@ -0,0 +1,302 @@
+//! Setup of the logs, from the `[logging]` settings.
+//!
+//! Events are filtered by `RUST_LOG` if set, and by the level and directives of the settings
+//! otherwise. The filter can be changed while running, through a `FilterHandle`.
+
+use opentelemetry::sdk::trace::Tracer;
+use snafu::{ResultExt, Snafu};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling;
+use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
+use tracing_log::LogTracer;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::{fmt, reload, EnvFilter, Registry};
+
+use crate::settings::{Format, Logging, Rotation, Sink};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Invalid log filter '{}': {}", filter, msg))]
+    FilterError { filter: String, msg: String },
+    #[snafu(display("Could not open log file in {}: {}", path, source))]
+    FileError { path: String, source: io::Error },
+    #[snafu(display("Could not install logger: {}", msg))]
+    InstallError { msg: String },
+    #[snafu(display("Could not access log filter: {}", source))]
+    ReloadError { source: reload::Error },
+}
+
+/// Handle on the filter of the installed logger.
+#[derive(Clone)]
+pub struct FilterHandle(reload::Handle<EnvFilter, Registry>);
+
+impl FilterHandle {
+    /// The current directives.
+    pub fn current(&self) -> Result<String, Error> {
+        self.0
+            .with_current(|filter| filter.to_string())
+            .context(ReloadError)
+    }
+
+    /// Replace the filter, until the next change or restart.
+    pub fn set(&self, directives: &str) -> Result<(), Error> {
+        let filter = parse_filter(directives)?;
+        self.0.reload(filter).context(ReloadError)
+    }
+}
+
+fn parse_filter(directives: &str) -> Result<EnvFilter, Error> {
+    EnvFilter::try_new(directives).map_err(|err| Error::FilterError {
+        filter: String::from(directives),
+        msg: err.to_string(),
+    })
+}
+
+/// The directives of the settings: the level, then its refinements.
+fn directives(settings: &Logging) -> String {
+    std::iter::once(settings.level.as_str())
+        .chain(settings.directives.iter().map(String::as_str))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn filter(settings: &Logging) -> Result<EnvFilter, Error> {
+    match std::env::var(EnvFilter::DEFAULT_ENV) {
+        Ok(directives) => parse_filter(&directives),
+        Err(_) => parse_filter(&directives(settings)),
+    }
+}
+
+/// A log file rotated when it would exceed a size.
+///
+/// The rotated files are suffixed with `.1` for the most recent, up to `.{max_files}`; older
+/// ones are removed.
+pub struct SizeRollingFile {
+    path: PathBuf,
+    max_size: u64,
+    max_files: usize,
+    file: File,
+    size: u64,
+}
+
+impl SizeRollingFile {
+    pub fn new(
+        directory: &str,
+        file_name: &str,
+        max_size: u64,
+        max_files: usize,
+    ) -> io::Result<Self> {
+        fs::create_dir_all(directory)?;
+        let path = PathBuf::from(directory).join(file_name);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(SizeRollingFile {
+            path,
+            max_size,
+            max_files,
+            file,
+            size,
+        })
+    }
+
+    fn rotated(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        PathBuf::from(path)
+    }
+
+    fn roll(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.max_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for index in (1..self.max_files).rev() {
+                let from = self.rotated(index);
+                if from.exists() {
+                    fs::rename(from, self.rotated(index + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for SizeRollingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // A record larger than the limit still goes to a file of its own.
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.roll()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Writes each record to stdout and/or a file.
+struct Sinks {
+    stdout: bool,
+    file: Option<Box<dyn Write + Send + Sync>>,
+}
+
+impl Write for Sinks {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.stdout {
+            io::stdout().write_all(buf)?;
+        }
+        if let Some(file) = self.file.as_mut() {
+            file.write_all(buf)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.stdout {
+            io::stdout().flush()?;
+        }
+        if let Some(file) = self.file.as_mut() {
+            file.flush()?;
+        }
+        Ok(())
+    }
+}
+
+fn sinks(settings: &Logging) -> Result<Sinks, Error> {
+    let file: Option<Box<dyn Write + Send + Sync>> = match settings.sink {
+        Sink::Stdout => None,
+        Sink::File | Sink::Both => Some(match settings.rotation {
+            Rotation::Hourly => Box::new(rolling::hourly(&settings.path, &settings.file_name)),
+            Rotation::Daily => Box::new(rolling::daily(&settings.path, &settings.file_name)),
+            Rotation::Never => Box::new(rolling::never(&settings.path, &settings.file_name)),
+            Rotation::Size => Box::new(
+                SizeRollingFile::new(
+                    &settings.path,
+                    &settings.file_name,
+                    settings.max_size,
+                    settings.max_files,
+                )
+                .context(FileError {
+                    path: settings.path.clone(),
+                })?,
+            ),
+        }),
+    };
+    Ok(Sinks {
+        stdout: settings.sink != Sink::File,
+        file,
+    })
+}
+
+/// Install the global logger, also exporting spans to `tracer` if given.
+///
+/// Records are written from a separate thread, until the returned guard is dropped.
+pub fn init(
+    settings: &Logging,
+    app_name: String,
+    tracer: Option<Tracer>,
+) -> Result<(WorkerGuard, FilterHandle), Error> {
+    LogTracer::init().map_err(|err| Error::InstallError {
+        msg: err.to_string(),
+    })?;
+
+    let (filter, handle) = reload::Layer::new(filter(settings)?);
+    let (writer, guard) = tracing_appender::non_blocking(sinks(settings)?);
+    // Colors only make sense on a terminal.
+    let ansi = settings.sink == Sink::Stdout;
+
+    let bunyan = settings.format == Format::Bunyan;
+    let subscriber = Registry::default()
+        .with(filter)
+        .with(bunyan.then_some(JsonStorageLayer))
+        .with(bunyan.then(|| BunyanFormattingLayer::new(app_name, writer.clone())))
+        .with((settings.format == Format::Pretty).then(|| {
+            fmt::layer()
+                .pretty()
+                .with_ansi(ansi)
+                .with_writer(writer.clone())
+        }))
+        .with((settings.format == Format::Compact).then(|| {
+            fmt::layer()
+                .compact()
+                .with_ansi(ansi)
+                .with_writer(writer.clone())
+        }))
+        .with(tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer)));
+
+    tracing::subscriber::set_global_default(subscriber).map_err(|err| Error::InstallError {
+        msg: err.to_string(),
+    })?;
+
+    Ok((guard, FilterHandle(handle)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_directives_refine_the_level() {
+        let settings = Logging {
+            path: String::from("./logs"),
+            file_name: String::from("stocks.log"),
+            level: String::from("warn"),
+            directives: vec![String::from("stocks::api=debug")],
+            sink: Sink::Stdout,
+            rotation: Rotation::Never,
+            max_size: 0,
+            max_files: 0,
+            format: Format::Compact,
+        };
+
+        assert_eq!(directives(&settings), "warn,stocks::api=debug");
+        assert!(parse_filter(&directives(&settings)).is_ok());
+        assert!(parse_filter("stocks=loud").is_err());
+    }
+
+    #[test]
+    fn test_filter_can_be_changed() {
+        let (_layer, handle) = reload::Layer::<_, Registry>::new(EnvFilter::new("info"));
+        let handle = FilterHandle(handle);
+
+        handle.set("stocks=debug").expect("filter");
+
+        assert_eq!(handle.current().expect("filter"), "stocks=debug");
+        assert!(handle.set("stocks=loud").is_err());
+        assert_eq!(handle.current().expect("filter"), "stocks=debug");
+    }
+
+    #[test]
+    fn test_size_rolling_file_keeps_max_files() {
+        let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let directory = directory.to_str().unwrap();
+        let mut file = SizeRollingFile::new(directory, "test.log", 10, 2).expect("file");
+
+        for line in &["line 1\n", "line 2\n", "line 3\n", "line 4\n"] {
+            file.write_all(line.as_bytes()).expect("write");
+        }
+        file.flush().expect("flush");
+
+        let read = |name: &str| fs::read_to_string(PathBuf::from(directory).join(name)).ok();
+        assert_eq!(read("test.log").as_deref(), Some("line 4\n"));
+        assert_eq!(read("test.log.1").as_deref(), Some("line 3\n"));
+        assert_eq!(read("test.log.2").as_deref(), Some("line 2\n"));
+        assert_eq!(read("test.log.3"), None);
+
+        fs::remove_dir_all(directory).expect("cleanup");
+    }
+}
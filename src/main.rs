@@ -1,6 +1,12 @@
 use clap::{App, Arg, SubCommand};
 use snafu::{ResultExt, Snafu};
+mod export;
+mod import;
+mod init;
+mod keys;
+mod migrate;
 mod server;
+mod status;
 
 #[derive(Debug, Snafu)]
 pub enum Error {
@@ -11,6 +17,36 @@ pub enum Error {
         #[snafu(backtrace)]
         source: server::Error,
     },
+    #[snafu(display("Migration Error: {}", source))]
+    MigrationError {
+        #[snafu(backtrace)]
+        source: migrate::Error,
+    },
+    #[snafu(display("Init Error: {}", source))]
+    InitError {
+        #[snafu(backtrace)]
+        source: init::Error,
+    },
+    #[snafu(display("Export Error: {}", source))]
+    ExportError {
+        #[snafu(backtrace)]
+        source: export::Error,
+    },
+    #[snafu(display("Import Error: {}", source))]
+    ImportError {
+        #[snafu(backtrace)]
+        source: import::Error,
+    },
+    #[snafu(display("Keys Error: {}", source))]
+    KeysError {
+        #[snafu(backtrace)]
+        source: keys::Error,
+    },
+    #[snafu(display("Status Error: {}", source))]
+    StatusError {
+        #[snafu(backtrace)]
+        source: status::Error,
+    },
 }
 
 #[tokio::main]
@@ -52,10 +88,174 @@ async fn main() -> Result<(), Error> {
                         .help("Port"),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("migrate")
+                .about("manage the database schema")
+                .version("0.1")
+                .author("Matthieu Paindavoine <matt@area403.org>")
+                .subcommand(SubCommand::with_name("up").about("apply pending migrations"))
+                .subcommand(SubCommand::with_name("down").about("revert the latest migration"))
+                .subcommand(SubCommand::with_name("status").about("list migrations")),
+        )
+        .subcommand(
+            SubCommand::with_name("init")
+                .about("create the schema and seed ISO 4217 currencies")
+                .version("0.1")
+                .author("Matthieu Paindavoine <matt@area403.org>")
+                .arg(
+                    Arg::with_name("dry run")
+                        .short("n")
+                        .long("dry-run")
+                        .help("Print what would change, without changing anything"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("export currencies, securities or daily prices to a CSV, NDJSON or Parquet file")
+                .version("0.1")
+                .author("Matthieu Paindavoine <matt@area403.org>")
+                .arg(
+                    Arg::with_name("entity")
+                        .value_name("ENTITY")
+                        .short("e")
+                        .long("entity")
+                        .required(true)
+                        .possible_values(&["currencies", "securities", "prices"])
+                        .help("What to export"),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .value_name("FORMAT")
+                        .short("f")
+                        .long("format")
+                        .possible_values(&["csv", "ndjson", "parquet"])
+                        .help("Format of the file, guessed from its extension by default"),
+                )
+                .arg(
+                    Arg::with_name("out")
+                        .value_name("PATH")
+                        .short("o")
+                        .long("out")
+                        .required(true)
+                        .help("File to write"),
+                )
+                .arg(
+                    Arg::with_name("since")
+                        .value_name("TIME")
+                        .long("since")
+                        .help("Only export rows created or updated since this date or RFC 3339 time"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("import")
+                .about("import currencies or daily prices from a CSV or NDJSON file")
+                .version("0.1")
+                .author("Matthieu Paindavoine <matt@area403.org>")
+                .after_help(
+                    "CSV files start with a header: code,name,decimals for currencies, \
+                     ticker,date,open,high,low,close,volume for prices. NDJSON files hold an \
+                     object with these fields per line.",
+                )
+                .arg(
+                    Arg::with_name("entity")
+                        .value_name("ENTITY")
+                        .short("e")
+                        .long("entity")
+                        .required(true)
+                        .possible_values(&["currencies", "prices"])
+                        .help("What the file holds"),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .value_name("FORMAT")
+                        .short("f")
+                        .long("format")
+                        .possible_values(&["csv", "ndjson"])
+                        .help("Format of the file, guessed from its extension by default"),
+                )
+                .arg(
+                    Arg::with_name("on conflict")
+                        .value_name("MODE")
+                        .long("on-conflict")
+                        .possible_values(&["skip", "update", "fail"])
+                        .default_value("skip")
+                        .help("What to do with rows already stored with other values"),
+                )
+                .arg(
+                    Arg::with_name("batch size")
+                        .value_name("ROWS")
+                        .long("batch-size")
+                        .help("Rows written in each transaction"),
+                )
+                .arg(
+                    Arg::with_name("file")
+                        .value_name("FILE")
+                        .required(true)
+                        .help("File to import"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("keys")
+                .about("manage the API keys")
+                .version("0.1")
+                .author("Matthieu Paindavoine <matt@area403.org>")
+                .subcommand(
+                    SubCommand::with_name("create")
+                        .about("create a key, printed once")
+                        .arg(
+                            Arg::with_name("name")
+                                .value_name("NAME")
+                                .required(true)
+                                .help("Name of the key"),
+                        )
+                        .arg(
+                            Arg::with_name("role")
+                                .value_name("ROLE")
+                                .short("r")
+                                .long("role")
+                                .multiple(true)
+                                .number_of_values(1)
+                                .help("Role granted to the key"),
+                        ),
+                )
+                .subcommand(SubCommand::with_name("list").about("list the keys"))
+                .subcommand(
+                    SubCommand::with_name("revoke").about("revoke a key").arg(
+                        Arg::with_name("name")
+                            .value_name("NAME")
+                            .required(true)
+                            .help("Name of the key"),
+                    ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("status")
+                .about("check the database, the schema, and the running service")
+                .version("0.1")
+                .author("Matthieu Paindavoine <matt@area403.org>")
+                .after_help(
+                    "Exits with 0 when everything is fine, 2 when the database is unreachable, \
+                     3 when migrations are pending, and 4 when the service does not answer.",
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Print the report as JSON"),
+                ),
+        )
         .get_matches();
 
     match matches.subcommand() {
         ("run", Some(_)) => server::run(&matches).await.context(ServerError),
+        ("migrate", Some(_)) => migrate::run(&matches).await.context(MigrationError),
+        ("init", Some(_)) => init::init(&matches).await.context(InitError),
+        ("export", Some(_)) => export::run(&matches).await.context(ExportError),
+        ("import", Some(_)) => import::run(&matches).await.context(ImportError),
+        ("keys", Some(_)) => keys::run(&matches).await.context(KeysError),
+        ("status", Some(_)) => {
+            let code = status::status(&matches).await.context(StatusError)?;
+            std::process::exit(code)
+        }
         _ => Err(Error::CLIError {
             msg: String::from("Unrecognized subcommand"),
         }),
@@ -0,0 +1,88 @@
+use clap::ArgMatches;
+use snafu::{ResultExt, Snafu};
+use sqlx::migrate::MigrateError;
+use sqlx::postgres::PgConnection;
+use sqlx::Connection;
+
+use stocks::db::migrate;
+use stocks::settings::Settings;
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Could not get database connection: {}", source))]
+    DBConnectionError { source: sqlx::Error },
+    #[snafu(display("Could not generate settings: {}", source))]
+    SettingsError {
+        #[snafu(backtrace)]
+        source: stocks::settings::Error,
+    },
+    #[snafu(display("Migration Error: {}", source))]
+    MigrationError { source: MigrateError },
+    #[snafu(display("Command Line Interface Error: {}", msg))]
+    CLIError { msg: String },
+}
+
+#[allow(clippy::needless_lifetimes)]
+pub async fn run<'a>(matches: &ArgMatches<'a>) -> Result<(), Error> {
+    let settings = Settings::new(matches).context(SettingsError)?;
+
+    // A dedicated connection, so the migration lock is released when it is closed.
+    let mut conn = PgConnection::connect(&settings.database.url)
+        .await
+        .context(DBConnectionError)?;
+
+    let command = matches
+        .subcommand_matches("migrate")
+        .and_then(|matches| matches.subcommand_name());
+
+    match command {
+        Some("up") => {
+            let applied = migrate::up(&mut conn).await.context(MigrationError)?;
+            if applied.is_empty() {
+                println!("No pending migration");
+            }
+            for (migration, elapsed) in applied {
+                println!(
+                    "Applied {}/{} ({:?})",
+                    migration.version, migration.description, elapsed
+                );
+            }
+        }
+        Some("down") => match migrate::down(&mut conn).await.context(MigrationError)? {
+            Some((migration, elapsed)) => println!(
+                "Reverted {}/{} ({:?})",
+                migration.version, migration.description, elapsed
+            ),
+            None => println!("No migration to revert"),
+        },
+        Some("status") => {
+            let migrations = migrate::status(&mut conn).await.context(MigrationError)?;
+            let width = migrations
+                .iter()
+                .map(|migration| migration.description.len())
+                .max()
+                .unwrap_or(0);
+            for migration in migrations {
+                println!(
+                    "{} {:<width$} {}",
+                    migration.version,
+                    migration.description,
+                    if migration.applied {
+                        "applied"
+                    } else {
+                        "pending"
+                    },
+                    width = width
+                );
+            }
+        }
+        _ => {
+            return Err(Error::CLIError {
+                msg: String::from("Expected one of up, down, status"),
+            })
+        }
+    }
+
+    Ok(())
+}
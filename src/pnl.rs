@@ -0,0 +1,369 @@
+//! Profit and loss computation over a portfolio ledger.
+//!
+//! This module has no dependency on the database or on GraphQL: the ledger is replayed as a
+//! sequence of `Event`s, and the valuation uses the prices given by the caller.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+/// Quantities below this threshold are considered to be zero.
+pub(crate) const EPSILON: f64 = 1e-9;
+
+/// How the units sold are matched against the units bought.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    /// The oldest units are sold first.
+    Fifo,
+    /// The most recent units are sold first.
+    Lifo,
+    /// All units held share the same, average, cost.
+    AverageCost,
+}
+
+/// An entry of the ledger, in the currency of the portfolio.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// Units bought, `cost` includes fees.
+    Buy {
+        ticker: String,
+        quantity: f64,
+        cost: f64,
+    },
+    /// Units sold, `proceeds` are net of fees.
+    Sell {
+        ticker: String,
+        quantity: f64,
+        proceeds: f64,
+    },
+    /// Income paid by a security, net of fees.
+    Dividend { ticker: String, amount: f64 },
+    /// Fees which are not attached to a trade.
+    Fee { amount: f64 },
+}
+
+/// Units bought together, at the same unit cost.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Lot {
+    quantity: f64,
+    unit_cost: f64,
+}
+
+#[derive(Debug, Default)]
+struct Book {
+    lots: VecDeque<Lot>,
+    realized: f64,
+    dividends: f64,
+}
+
+impl Book {
+    fn quantity(&self) -> f64 {
+        self.lots.iter().map(|lot| lot.quantity).sum()
+    }
+
+    fn cost_basis(&self) -> f64 {
+        self.lots
+            .iter()
+            .map(|lot| lot.quantity * lot.unit_cost)
+            .sum()
+    }
+
+    fn buy(&mut self, method: Method, quantity: f64, cost: f64) {
+        let lot = Lot {
+            quantity,
+            unit_cost: cost / quantity,
+        };
+        match (method, self.lots.pop_front()) {
+            (Method::AverageCost, Some(held)) => {
+                let quantity = held.quantity + lot.quantity;
+                self.lots.push_back(Lot {
+                    quantity,
+                    unit_cost: (held.quantity * held.unit_cost + cost) / quantity,
+                });
+            }
+            (_, held) => {
+                if let Some(held) = held {
+                    self.lots.push_front(held);
+                }
+                self.lots.push_back(lot);
+            }
+        }
+    }
+
+    /// Units sold in excess of the units held are matched at no cost.
+    fn sell(&mut self, method: Method, quantity: f64, proceeds: f64) {
+        let mut remaining = quantity;
+        let mut cost = 0.0;
+        while remaining > EPSILON {
+            let lot = match method {
+                Method::Lifo => self.lots.back_mut(),
+                Method::Fifo | Method::AverageCost => self.lots.front_mut(),
+            };
+            let lot = match lot {
+                Some(lot) => lot,
+                None => break,
+            };
+            let matched = remaining.min(lot.quantity);
+            cost += matched * lot.unit_cost;
+            lot.quantity -= matched;
+            remaining -= matched;
+            if lot.quantity < EPSILON {
+                match method {
+                    Method::Lifo => self.lots.pop_back(),
+                    Method::Fifo | Method::AverageCost => self.lots.pop_front(),
+                };
+            }
+        }
+        self.realized += proceeds - cost;
+    }
+}
+
+/// The units of a security held, and their cost.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Holding {
+    pub ticker: String,
+    pub quantity: f64,
+    pub cost_basis: f64,
+}
+
+/// The gains of a security, including closed positions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SecurityPerformance {
+    pub ticker: String,
+    pub quantity: f64,
+    pub cost_basis: f64,
+    pub realized: f64,
+    pub dividends: f64,
+    /// Value of the units held, if a price is available.
+    pub market_value: Option<f64>,
+    /// Market value less cost basis, if a price is available.
+    pub unrealized: Option<f64>,
+}
+
+/// The gains of a portfolio.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Performance {
+    pub method: Method,
+    pub realized: f64,
+    /// Unrealized gains of the securities for which a price is available.
+    pub unrealized: f64,
+    pub dividends: f64,
+    pub fees: f64,
+    pub securities: Vec<SecurityPerformance>,
+}
+
+impl Performance {
+    /// Realized and unrealized gains, plus dividends, less fees.
+    pub fn total(&self) -> f64 {
+        self.realized + self.unrealized + self.dividends - self.fees
+    }
+}
+
+/// Replays ledger events, matching sales against lots with the given method.
+#[derive(Debug)]
+pub struct Ledger {
+    method: Method,
+    books: BTreeMap<String, Book>,
+    fees: f64,
+}
+
+impl Ledger {
+    pub fn new(method: Method) -> Self {
+        Ledger {
+            method,
+            books: BTreeMap::new(),
+            fees: 0.0,
+        }
+    }
+
+    /// Replay events in execution order.
+    pub fn replay<'a, I>(method: Method, events: I) -> Self
+    where
+        I: IntoIterator<Item = &'a Event>,
+    {
+        let mut ledger = Ledger::new(method);
+        for event in events {
+            ledger.record(event);
+        }
+        ledger
+    }
+
+    pub fn record(&mut self, event: &Event) {
+        match event {
+            Event::Buy {
+                ticker,
+                quantity,
+                cost,
+            } => self
+                .books
+                .entry(ticker.clone())
+                .or_default()
+                .buy(self.method, *quantity, *cost),
+            Event::Sell {
+                ticker,
+                quantity,
+                proceeds,
+            } => self.books.entry(ticker.clone()).or_default().sell(
+                self.method,
+                *quantity,
+                *proceeds,
+            ),
+            Event::Dividend { ticker, amount } => {
+                self.books.entry(ticker.clone()).or_default().dividends += amount
+            }
+            Event::Fee { amount } => self.fees += amount,
+        }
+    }
+
+    /// The open positions, sorted by ticker.
+    pub fn holdings(&self) -> Vec<Holding> {
+        self.books
+            .iter()
+            .filter(|(_, book)| book.quantity() > EPSILON)
+            .map(|(ticker, book)| Holding {
+                ticker: ticker.clone(),
+                quantity: book.quantity(),
+                cost_basis: book.cost_basis(),
+            })
+            .collect()
+    }
+
+    /// Value the ledger with the given unit prices, keyed by ticker.
+    pub fn performance(&self, prices: &HashMap<String, f64>) -> Performance {
+        let securities = self
+            .books
+            .iter()
+            .map(|(ticker, book)| {
+                let quantity = book.quantity();
+                let cost_basis = book.cost_basis();
+                let market_value = if quantity > EPSILON {
+                    prices.get(ticker).map(|price| price * quantity)
+                } else {
+                    Some(0.0)
+                };
+                SecurityPerformance {
+                    ticker: ticker.clone(),
+                    quantity,
+                    cost_basis,
+                    realized: book.realized,
+                    dividends: book.dividends,
+                    market_value,
+                    unrealized: market_value.map(|value| value - cost_basis),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        Performance {
+            method: self.method,
+            realized: securities.iter().map(|security| security.realized).sum(),
+            unrealized: securities
+                .iter()
+                .filter_map(|security| security.unrealized)
+                .sum(),
+            dividends: securities.iter().map(|security| security.dividends).sum(),
+            fees: self.fees,
+            securities,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buy(ticker: &str, quantity: f64, cost: f64) -> Event {
+        Event::Buy {
+            ticker: String::from(ticker),
+            quantity,
+            cost,
+        }
+    }
+
+    fn sell(ticker: &str, quantity: f64, proceeds: f64) -> Event {
+        Event::Sell {
+            ticker: String::from(ticker),
+            quantity,
+            proceeds,
+        }
+    }
+
+    fn ledger() -> Vec<Event> {
+        vec![
+            buy("AAPL", 10.0, 1000.0),
+            buy("AAPL", 10.0, 2000.0),
+            sell("AAPL", 15.0, 2250.0),
+        ]
+    }
+
+    #[test]
+    fn test_fifo() {
+        let ledger = Ledger::replay(Method::Fifo, &ledger());
+        let performance = ledger.performance(&HashMap::new());
+
+        // 10 @ 100 + 5 @ 200
+        assert_eq!(performance.realized, 250.0);
+        assert_eq!(
+            ledger.holdings(),
+            vec![Holding {
+                ticker: String::from("AAPL"),
+                quantity: 5.0,
+                cost_basis: 1000.0,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_lifo() {
+        let ledger = Ledger::replay(Method::Lifo, &ledger());
+        let performance = ledger.performance(&HashMap::new());
+
+        // 10 @ 200 + 5 @ 100
+        assert_eq!(performance.realized, -250.0);
+        assert_eq!(ledger.holdings()[0].cost_basis, 500.0);
+    }
+
+    #[test]
+    fn test_average_cost() {
+        let ledger = Ledger::replay(Method::AverageCost, &ledger());
+        let performance = ledger.performance(&HashMap::new());
+
+        // 15 @ 150
+        assert_eq!(performance.realized, 0.0);
+        assert_eq!(ledger.holdings()[0].cost_basis, 750.0);
+    }
+
+    #[test]
+    fn test_unrealized_and_income() {
+        let mut events = ledger();
+        events.push(buy("MSFT", 2.0, 400.0));
+        events.push(Event::Dividend {
+            ticker: String::from("AAPL"),
+            amount: 12.0,
+        });
+        events.push(Event::Fee { amount: 2.0 });
+
+        let mut prices = HashMap::new();
+        prices.insert(String::from("AAPL"), 210.0);
+
+        let performance = Ledger::replay(Method::Fifo, &events).performance(&prices);
+
+        assert_eq!(performance.unrealized, 50.0);
+        assert_eq!(performance.dividends, 12.0);
+        assert_eq!(performance.total(), 250.0 + 50.0 + 12.0 - 2.0);
+
+        let msft = &performance.securities[1];
+        assert_eq!(msft.ticker, "MSFT");
+        assert_eq!(msft.market_value, None);
+        assert_eq!(msft.unrealized, None);
+    }
+
+    #[test]
+    fn test_closed_position() {
+        let events = vec![buy("AAPL", 10.0, 1000.0), sell("AAPL", 10.0, 900.0)];
+
+        let ledger = Ledger::replay(Method::Fifo, &events);
+        let performance = ledger.performance(&HashMap::new());
+
+        assert!(ledger.holdings().is_empty());
+        assert_eq!(performance.realized, -100.0);
+        assert_eq!(performance.securities[0].unrealized, Some(0.0));
+    }
+}
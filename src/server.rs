@@ -1,22 +1,30 @@
 use async_graphql::extensions::TracingConfig;
 use async_graphql::http::{playground_source, GraphQLPlaygroundConfig};
 use clap::ArgMatches;
-use http::StatusCode;
+use http::{HeaderMap, StatusCode};
+use serde::{Deserialize, Serialize};
 use snafu::{ResultExt, Snafu};
-use sqlx::postgres::PgPool;
+use sqlx::postgres::{PgConnectOptions, PgPool};
 use std::convert::Infallible;
-use std::net::ToSocketAddrs;
-use tracing::{info, instrument, span, Level};
-use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
-use tracing_log::LogTracer;
-use tracing_subscriber::layer::SubscriberExt;
-use tracing_subscriber::{EnvFilter, Registry};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, instrument, Instrument};
 use uuid::Uuid;
 use warp::{http::Response as HttpResponse, Filter, Rejection};
 
-use stocks::api::gql;
+use stocks::api::auth::{self, Authenticator, Permission, Principal};
+use stocks::api::bus::Bus;
+use stocks::api::cache::CachedStockService;
+use stocks::api::model::Service;
+use stocks::api::{error, gql, health, metrics, notify};
+use stocks::db::migrate;
+use stocks::logging::{self, FilterHandle};
 use stocks::settings::Settings;
+use stocks::telemetry;
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Snafu)]
 pub enum Error {
     #[snafu(display("Could not get database pool: {}", source))]
@@ -31,6 +39,21 @@ pub enum Error {
         #[snafu(backtrace)]
         source: stocks::state::Error,
     },
+    #[snafu(display("Could not migrate database: {}", source))]
+    DBMigrationError { source: sqlx::migrate::MigrateError },
+    #[snafu(display("Could not install trace exporter: {}", source))]
+    TraceExporterError {
+        source: opentelemetry::trace::TraceError,
+    },
+    #[snafu(display("Could not setup authentication: {}", source))]
+    AuthError { source: auth::Error },
+    #[snafu(display("Could not setup logging: {}", source))]
+    LoggingError { source: logging::Error },
+    #[snafu(display("Could not serve metrics on {}: {}", addr, source))]
+    MetricsBindError {
+        addr: SocketAddr,
+        source: warp::Error,
+    },
     #[snafu(display("Socket Addr Error {}", source))]
     SockAddrError { source: std::io::Error },
     #[snafu(display("Addr Resolution Error {}", msg))]
@@ -40,54 +63,225 @@ pub enum Error {
 #[allow(clippy::needless_lifetimes)]
 pub async fn run<'a>(matches: &ArgMatches<'a>) -> Result<(), Error> {
     let settings = Settings::new(matches).context(SettingsError)?;
-    LogTracer::init().expect("Unable to setup log tracer!");
 
     // following code mostly from https://betterprogramming.pub/production-grade-logging-in-rust-applications-2c7fffd108a6
     let app_name = concat!(env!("CARGO_PKG_NAME"), "-", env!("CARGO_PKG_VERSION")).to_string();
 
-    let file_appender = tracing_appender::rolling::daily(&settings.logging.path, "stocks.log");
-    let (non_blocking, _guard) = tracing_appender::non_blocking(file_appender);
-    // let (non_blocking, _guard) = tracing_appender::non_blocking(std::io::stdout());
+    let tracer = if settings.tracing.enabled {
+        Some(telemetry::tracer(&settings.tracing).context(TraceExporterError)?)
+    } else {
+        None
+    };
+    let (_guard, filter) =
+        logging::init(&settings.logging, app_name, tracer).context(LoggingError)?;
+
+    let res = run_server(settings, filter).await;
+    // Flush the spans not exported yet.
+    opentelemetry::global::shutdown_tracer_provider();
+    res
+}
+
+/// A request whose credentials were missing or could not be checked.
+#[derive(Debug)]
+struct Unauthenticated(auth::Error);
+
+impl warp::reject::Reject for Unauthenticated {}
 
-    let bunyan_formatting_layer = BunyanFormattingLayer::new(app_name, non_blocking);
-    let subscriber = Registry::default()
-        .with(EnvFilter::new("INFO"))
-        .with(JsonStorageLayer)
-        .with(bunyan_formatting_layer);
-    tracing::subscriber::set_global_default(subscriber).unwrap();
+/// A request from a caller without the permission it needs.
+#[derive(Debug)]
+struct Forbidden;
 
-    run_server(settings).await
+impl warp::reject::Reject for Forbidden {}
+
+/// The directives of the log filter, in the admin endpoint.
+#[derive(Debug, Serialize, Deserialize)]
+struct LogFilter {
+    filter: String,
 }
 
-#[instrument]
-pub async fn run_server(settings: Settings) -> Result<(), Error> {
-    let pool = PgPool::connect(&settings.database.url)
+#[instrument(skip(filter))]
+pub async fn run_server(settings: Settings, filter: FilterHandle) -> Result<(), Error> {
+    // The database messages are only returned to clients when debugging.
+    error::set_debug(settings.debug);
+
+    // Each instance has its own application name, so it can tell its own changes in
+    // the notifications from the database.
+    let origin = format!("{}-{}", env!("CARGO_PKG_NAME"), Uuid::new_v4().to_simple());
+    let options = PgConnectOptions::from_str(&settings.database.url)
+        .context(DBConnectionError)?
+        .application_name(&origin);
+    let pool = PgPool::connect_with(options)
         .await
         .context(DBConnectionError)?;
-    let service = Box::new(stocks::api::imp::StockServiceImpl { pool });
 
-    let schema = gql::schema(service);
+    if settings.database.auto_migrate {
+        let mut conn = pool.acquire().await.context(DBConnectionError)?;
+        for (migration, elapsed) in migrate::up(&mut conn).await.context(DBMigrationError)? {
+            info!(
+                "Applied migration {}/{} ({:?})",
+                migration.version, migration.description, elapsed
+            );
+        }
+    }
 
-    let graphql_post = async_graphql_warp::graphql(schema).and_then(
-        |(schema, request): (gql::StocksSchema, async_graphql::Request)| async move {
-            let request_id = Uuid::new_v4();
-            let root_span = span!(parent: None, Level::INFO, "graphql request", %request_id);
-            let request = request.data(TracingConfig::default().parent_span(root_span));
-            Ok::<_, Infallible>(async_graphql_warp::Response::from(
-                schema.execute(request).await,
-            ))
-        },
-    );
+    if settings.metrics.enabled {
+        let addr = resolve(&settings.metrics.host, settings.metrics.port)?;
+        let pool = pool.clone();
+        let route = warp::path!("metrics")
+            .and(warp::get())
+            .map(move || metrics::render(&pool));
+        // Bound here, so that a port already in use stops the service instead of its metrics.
+        let (addr, server) = warp::serve(route)
+            .try_bind_ephemeral(addr)
+            .context(MetricsBindError { addr })?;
+        info!("Serving metrics on {}", addr);
+        tokio::spawn(server);
+    }
+
+    let bus = Bus::default();
+
+    if let Some(channel) = settings.database.notify_channel.clone() {
+        tokio::spawn(notify::relay(pool.clone(), channel, origin, bus.clone()));
+    }
+    let service = stocks::api::imp::StockServiceImpl {
+        pool: pool.clone(),
+        bus: bus.clone(),
+    };
+    let service: Service = if settings.cache.enabled {
+        info!(
+            "Caching reference data for {}s, up to {} values",
+            settings.cache.ttl_secs, settings.cache.max_entries
+        );
+        Arc::new(CachedStockService::new(service, &settings.cache))
+    } else {
+        Arc::new(service)
+    };
+
+    let schema = gql::schema(service.clone(), bus);
+
+    let authenticator =
+        Arc::new(Authenticator::new(pool.clone(), &settings.auth).context(AuthError)?);
+
+    // Websocket clients send their credentials in the payload of the `connection_init` message.
+    let graphql_subscription = {
+        let authenticator = authenticator.clone();
+        async_graphql_warp::graphql_subscription_with_data(
+            schema.clone(),
+            move |payload: serde_json::Value| async move {
+                let principal = authenticator
+                    .authenticate_payload(&payload)
+                    .await
+                    .map_err(|err| async_graphql::Error::new(auth_failure(&err).1))?;
+                let mut data = async_graphql::Data::default();
+                data.insert(principal);
+                Ok(data)
+            },
+        )
+    };
+
+    let authenticate = warp::header::headers_cloned().and_then(move |headers: HeaderMap| {
+        let authenticator = authenticator.clone();
+        async move {
+            authenticator
+                .authenticate(&headers)
+                .await
+                .map_err(|err| warp::reject::custom(Unauthenticated(err)))
+        }
+    });
+
+    // Lets the request through only for callers with the admin permission.
+    let admin = authenticate
+        .clone()
+        .and_then(|principal: Principal| async move {
+            if principal.permissions.contains(&Permission::Admin) {
+                Ok(())
+            } else {
+                Err(warp::reject::custom(Forbidden))
+            }
+        })
+        .untuple_one();
+
+    // Each request has its own loaders, the values they load are reused until it completes.
+    let graphql_post = async_graphql_warp::graphql(schema)
+        .and(warp::header::headers_cloned())
+        .and(authenticate)
+        .and_then(
+            move |(schema, request): (gql::StocksSchema, async_graphql::Request),
+                  headers: HeaderMap,
+                  principal: Principal| {
+                let loaders = gql::loaders(service.clone());
+                async move {
+                    let root_span = telemetry::request_span(&headers);
+                    let request = request
+                        .data(TracingConfig::default().parent_span(root_span.clone()))
+                        .data(principal)
+                        .data(loaders);
+                    Ok::<_, Infallible>(async_graphql_warp::Response::from(
+                        schema.execute(request).instrument(root_span).await,
+                    ))
+                }
+            },
+        );
 
     let graphql_playground = warp::path("playground").and(warp::get()).map(|| {
         HttpResponse::builder()
             .header("content-type", "text/html")
-            .body(playground_source(GraphQLPlaygroundConfig::new("/")))
+            .body(playground_source(
+                GraphQLPlaygroundConfig::new("/").subscription_endpoint("/"),
+            ))
     });
 
-    let routes = graphql_playground
+    // The health checks come before the GraphQL filters, which accept any path, and are
+    // not traced: the orchestrator polls them.
+    let health_live = warp::path!("health" / "live")
+        .and(warp::get())
+        .map(|| health_reply(health::live()));
+
+    let ready_timeout = Duration::from_millis(settings.service.ready_timeout_ms);
+    let health_ready =
+        warp::path!("health" / "ready")
+            .and(warp::get())
+            .and_then(move || {
+                let pool = pool.clone();
+                async move {
+                    Ok::<_, Infallible>(health_reply(health::ready(&pool, ready_timeout).await))
+                }
+            });
+
+    // Changes the log filter until the next change or restart, eg
+    // `curl -X PUT -H "X-API-Key: $KEY" -H "Content-Type: application/json" \
+    //   -d '{"filter": "info,stocks=debug"}' $HOST:$PORT/admin/logging/filter`
+    // Only callers with the admin permission may read or change it.
+    let log_filter = warp::path!("admin" / "logging" / "filter")
+        .and(admin)
+        .map(move || filter.clone());
+    let get_log_filter = log_filter
+        .clone()
+        .and(warp::get())
+        .map(|filter: FilterHandle| log_filter_reply(filter.current()));
+    let put_log_filter = log_filter.and(warp::put()).and(warp::body::json()).map(
+        |filter: FilterHandle, change: LogFilter| {
+            log_filter_reply(filter.set(&change.filter).and_then(|_| filter.current()))
+        },
+    );
+
+    // The admin endpoints answer their own authentication failures, so that the request does not
+    // fall through to the GraphQL filters, which accept any path.
+    let admin_routes = get_log_filter
+        .or(put_log_filter)
+        .recover(|err: Rejection| async move { auth_reply(&err).ok_or(err) });
+
+    let routes = health_live
+        .or(health_ready)
+        .or(admin_routes)
+        .or(graphql_subscription)
+        .or(graphql_playground)
         .or(graphql_post)
         .recover(|err: Rejection| async move {
+            if let Some(reply) = auth_reply(&err) {
+                return Ok::<_, Infallible>(reply);
+            }
+
             if let Some(async_graphql_warp::BadRequest(err)) = err.find() {
                 return Ok::<_, Infallible>(warp::reply::with_status(
                     err.to_string(),
@@ -101,19 +295,70 @@ pub async fn run_server(settings: Settings) -> Result<(), Error> {
             ))
         });
 
-    let host = settings.service.host;
-    let port = settings.service.port;
-    let addr = (host.as_str(), port);
-    let addr = addr
+    let addr = resolve(&settings.service.host, settings.service.port)?;
+
+    info!("Serving stocks on {}", addr);
+    warp::serve(routes).run(addr).await;
+
+    Ok(())
+}
+
+fn resolve(host: &str, port: u16) -> Result<SocketAddr, Error> {
+    (host, port)
         .to_socket_addrs()
         .context(SockAddrError)?
         .next()
         .ok_or(Error::AddrResolutionError {
-            msg: String::from("Cannot resolve addr"),
-        })?;
+            msg: format!("Cannot resolve addr {}:{}", host, port),
+        })
+}
 
-    info!("Serving stocks on {}", addr);
-    warp::serve(routes).run(addr).await;
+/// The reply to a request whose caller could not be authenticated, or lacks a permission.
+fn auth_reply(err: &Rejection) -> Option<warp::reply::WithStatus<String>> {
+    if let Some(Unauthenticated(err)) = err.find() {
+        let (status, message) = auth_failure(err);
+        return Some(warp::reply::with_status(message, status));
+    }
 
-    Ok(())
+    err.find::<Forbidden>()
+        .map(|_| warp::reply::with_status("FORBIDDEN".to_string(), StatusCode::FORBIDDEN))
+}
+
+/// The status and message of a failed authentication. When the keys could not be looked up,
+/// the clients are only told so, the details are logged.
+fn auth_failure(err: &auth::Error) -> (StatusCode, String) {
+    match err {
+        auth::Error::DBConnectionError { .. } | auth::Error::DBProvideError { .. } => {
+            error!("Could not authenticate: {}", err);
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                String::from("Authentication unavailable"),
+            )
+        }
+        _ => (StatusCode::UNAUTHORIZED, err.to_string()),
+    }
+}
+
+fn log_filter_reply(
+    filter: Result<String, logging::Error>,
+) -> warp::reply::WithStatus<warp::reply::Json> {
+    match filter {
+        Ok(filter) => {
+            warp::reply::with_status(warp::reply::json(&LogFilter { filter }), StatusCode::OK)
+        }
+        Err(err) => warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({ "error": err.to_string() })),
+            StatusCode::BAD_REQUEST,
+        ),
+    }
+}
+
+/// The health as JSON, with a 503 status when it is down.
+fn health_reply(health: health::Health) -> warp::reply::WithStatus<warp::reply::Json> {
+    let status = if health.is_up() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    warp::reply::with_status(warp::reply::json(&health), status)
 }
@@ -16,6 +16,8 @@ pub enum Error {
 pub struct State {
     pub pool: PgPool,
     pub settings: Settings,
+    /// The version reported by the database server.
+    pub db_version: String,
 }
 
 impl State {
@@ -23,7 +25,7 @@ impl State {
         let pool = PgPool::connect(&settings.database.url)
             .await
             .context(DBConnectionError {
-                msg: String::from("foo"),
+                msg: format!("Could not connect to {}", settings.database.redacted_url()),
             })?;
 
         let row: (String,) = sqlx::query_as("SELECT version()")
@@ -32,7 +34,7 @@ impl State {
             .context(DBVersionError {
                 msg: format!(
                     "Could not test database version for {}",
-                    &settings.database.url,
+                    settings.database.redacted_url(),
                 ),
             })?;
 
@@ -41,6 +43,7 @@ impl State {
         Ok(Self {
             pool,
             settings: settings.clone(),
+            db_version: row.0,
         })
     }
 }
@@ -0,0 +1,307 @@
+use clap::ArgMatches;
+use serde::Serialize;
+use snafu::{ResultExt, Snafu};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use stocks::db::migrate;
+use stocks::db::model::ProvideStock;
+use stocks::settings::Settings;
+use stocks::state::State;
+
+/// How long to wait for the running instance to answer.
+const SERVICE_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Could not generate settings: {}", source))]
+    SettingsError {
+        #[snafu(backtrace)]
+        source: stocks::settings::Error,
+    },
+    #[snafu(display("Could not serialize report: {}", source))]
+    SerializationError { source: serde_json::Error },
+}
+
+/// The overall status, from the first failing check.
+///
+/// Each status has its own exit code, so that scripts can branch on it. Errors preventing
+/// the checks altogether, such as invalid settings, exit with 1.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Ok,
+    DatabaseUnreachable,
+    /// Also when the schema could not be read, it is then not known to be up to date.
+    MigrationsPending,
+    ServiceUnreachable,
+}
+
+impl Status {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Status::Ok => 0,
+            Status::DatabaseUnreachable => 2,
+            Status::MigrationsPending => 3,
+            Status::ServiceUnreachable => 4,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+struct DatabaseReport {
+    reachable: bool,
+    version: Option<String>,
+    error: Option<String>,
+    /// Why the migrations could not be listed, once connected.
+    schema_error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct MigrationReport {
+    version: i64,
+    description: String,
+    applied: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct ServiceReport {
+    url: String,
+    answering: bool,
+    latency_ms: Option<u128>,
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Report {
+    status: Status,
+    database: DatabaseReport,
+    /// Empty when the database is unreachable.
+    migrations: Vec<MigrationReport>,
+    /// Rows per entity, absent when the schema is missing or not up to date.
+    entities: Option<BTreeMap<String, i64>>,
+    service: ServiceReport,
+}
+
+impl Report {
+    fn pending(&self) -> impl Iterator<Item = &MigrationReport> {
+        self.migrations
+            .iter()
+            .filter(|migration| !migration.applied)
+    }
+
+    fn status(&self) -> Status {
+        if !self.database.reachable {
+            Status::DatabaseUnreachable
+        } else if self.database.schema_error.is_some() || self.pending().next().is_some() {
+            Status::MigrationsPending
+        } else if !self.service.answering {
+            Status::ServiceUnreachable
+        } else {
+            Status::Ok
+        }
+    }
+
+    fn print(&self) {
+        match (&self.database.version, &self.database.error) {
+            (Some(version), _) => println!("database    reachable ({})", version),
+            (None, Some(error)) => println!("database    unreachable: {}", error),
+            (None, None) => println!("database    unreachable"),
+        }
+        if let Some(error) = &self.database.schema_error {
+            println!("migrations  unknown: {}", error);
+        } else if self.database.reachable {
+            println!(
+                "migrations  {} applied, {} pending",
+                self.migrations.len() - self.pending().count(),
+                self.pending().count()
+            );
+            for migration in self.pending() {
+                println!(
+                    "            pending {}/{}",
+                    migration.version, migration.description
+                );
+            }
+        }
+        if let Some(entities) = &self.entities {
+            println!("entities");
+            for (entity, count) in entities {
+                println!("            {:<16} {}", entity, count);
+            }
+        }
+        match (&self.service.latency_ms, &self.service.error) {
+            (Some(latency), None) => println!(
+                "service     answering at {} ({} ms)",
+                self.service.url, latency
+            ),
+            (_, Some(error)) => println!(
+                "service     not answering at {}: {}",
+                self.service.url, error
+            ),
+            _ => println!("service     not answering at {}", self.service.url),
+        }
+        println!("status      {:?}", self.status);
+    }
+}
+
+/// Check the database, the schema, and the running instance.
+///
+/// Returns the exit code of the status, see `Status`.
+#[allow(clippy::needless_lifetimes)]
+pub async fn status<'a>(matches: &ArgMatches<'a>) -> Result<i32, Error> {
+    let settings = Settings::new(matches).context(SettingsError)?;
+    let json = matches
+        .subcommand_matches("status")
+        .is_some_and(|matches| matches.is_present("json"));
+
+    let mut database = DatabaseReport::default();
+    let mut migrations = Vec::new();
+    let mut entities = None;
+
+    match State::new(&settings).await {
+        Ok(state) => {
+            database.reachable = true;
+            database.version = Some(state.db_version.clone());
+            match check_schema(&state).await {
+                Ok((status, counts)) => {
+                    migrations = status;
+                    entities = counts;
+                }
+                Err(err) => database.schema_error = Some(err.to_string()),
+            }
+        }
+        Err(err) => database.error = Some(err.to_string()),
+    }
+
+    let service = check_service(&settings).await;
+
+    let mut report = Report {
+        status: Status::Ok,
+        database,
+        migrations,
+        entities,
+        service,
+    };
+    report.status = report.status();
+
+    if json {
+        let report = serde_json::to_string_pretty(&report).context(SerializationError)?;
+        println!("{}", report);
+    } else {
+        report.print();
+    }
+
+    Ok(report.status.exit_code())
+}
+
+/// List the migrations and, when the schema is up to date, count the rows per entity.
+async fn check_schema(
+    state: &State,
+) -> Result<(Vec<MigrationReport>, Option<BTreeMap<String, i64>>), Box<dyn std::error::Error>> {
+    let mut conn = state.pool.acquire().await?;
+
+    let migrations = migrate::status(&mut conn)
+        .await?
+        .into_iter()
+        .map(|migration| MigrationReport {
+            version: migration.version,
+            description: migration.description,
+            applied: migration.applied,
+        })
+        .collect::<Vec<_>>();
+
+    let entities = if migrations.iter().all(|migration| migration.applied) {
+        Some(conn.count_entities().await?.into_iter().collect())
+    } else {
+        None
+    };
+
+    Ok((migrations, entities))
+}
+
+/// Ask the instance listening on the configured address whether it is live.
+async fn check_service(settings: &Settings) -> ServiceReport {
+    // A service bound to every interface answers on the loopback.
+    let host = match settings.service.host.as_str() {
+        "0.0.0.0" => "127.0.0.1",
+        "::" | "[::]" => "[::1]",
+        host => host,
+    };
+    let url = format!("http://{}:{}/health/live", host, settings.service.port);
+
+    let start = Instant::now();
+    let res = reqwest::Client::new()
+        .get(&url)
+        .timeout(SERVICE_TIMEOUT)
+        .send()
+        .await
+        .and_then(|res| res.error_for_status());
+    let latency = start.elapsed();
+
+    match res {
+        Ok(_) => ServiceReport {
+            url,
+            answering: true,
+            latency_ms: Some(latency.as_millis()),
+            error: None,
+        },
+        Err(err) => ServiceReport {
+            url,
+            answering: false,
+            latency_ms: None,
+            error: Some(err.to_string()),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(reachable: bool, pending: bool, answering: bool) -> Report {
+        Report {
+            status: Status::Ok,
+            database: DatabaseReport {
+                reachable,
+                version: None,
+                error: None,
+                schema_error: None,
+            },
+            migrations: vec![MigrationReport {
+                version: 1,
+                description: String::from("currencies"),
+                applied: !pending,
+            }],
+            entities: None,
+            service: ServiceReport {
+                url: String::from("http://127.0.0.1:8080/health/live"),
+                answering,
+                latency_ms: None,
+                error: None,
+            },
+        }
+    }
+
+    #[test]
+    fn test_status_is_the_first_failing_check() {
+        assert_eq!(report(true, false, true).status(), Status::Ok);
+        assert_eq!(
+            report(false, true, false).status(),
+            Status::DatabaseUnreachable
+        );
+        assert_eq!(
+            report(true, true, false).status(),
+            Status::MigrationsPending
+        );
+        assert_eq!(
+            report(true, false, false).status(),
+            Status::ServiceUnreachable
+        );
+
+        let mut unreadable = report(true, false, true);
+        unreadable.database.schema_error = Some(String::from("permission denied"));
+        assert_eq!(unreadable.status(), Status::MigrationsPending);
+        assert_eq!(unreadable.status().exit_code(), 3);
+    }
+}
@@ -0,0 +1,196 @@
+//! Export of the spans to an OpenTelemetry collector.
+//!
+//! When enabled, spans are exported over OTLP/gRPC in addition to the bunyan log. Requests
+//! carrying a W3C `traceparent` header continue the trace of the caller.
+
+use http::HeaderMap;
+use opentelemetry::propagation::{Extractor, TextMapPropagator};
+use opentelemetry::sdk::propagation::TraceContextPropagator;
+use opentelemetry::sdk::trace::{self, Sampler, Tracer};
+use opentelemetry::sdk::Resource;
+use opentelemetry::trace::{TraceContextExt, TraceError};
+use opentelemetry::{Context, KeyValue};
+use tracing::{span, Level, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use uuid::Uuid;
+
+use crate::settings;
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl<'a> Extractor for HeaderExtractor<'a> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+/// Install the OTLP exporter, and return the tracer feeding it.
+///
+/// Spans are exported in batches, from a tokio task: this must be called within the runtime.
+pub fn tracer(settings: &settings::Tracing) -> Result<Tracer, TraceError> {
+    // Traces started by a caller follow its sampling decision.
+    let sampler = Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(settings.sample_ratio)));
+    let resource = Resource::new(vec![KeyValue::new(
+        "service.name",
+        settings.service_name.clone(),
+    )]);
+
+    opentelemetry_otlp::new_pipeline()
+        .with_endpoint(&settings.endpoint)
+        .with_trace_config(
+            trace::config()
+                .with_sampler(sampler)
+                .with_resource(resource),
+        )
+        .with_tonic()
+        .install_batch(opentelemetry::runtime::Tokio)
+}
+
+/// The trace context sent by the caller, empty if there is none or it is invalid.
+pub fn remote_context(headers: &HeaderMap) -> Context {
+    TraceContextPropagator::new().extract(&HeaderExtractor(headers))
+}
+
+/// The id of a request: the id of the caller's trace if any, so that both match.
+fn request_id(parent: &Context) -> Uuid {
+    match parent.remote_span_context() {
+        Some(span_context) if span_context.is_valid() => {
+            Uuid::from_u128(span_context.trace_id().to_u128())
+        }
+        _ => Uuid::new_v4(),
+    }
+}
+
+/// The root span of a GraphQL request, continuing the trace of the caller.
+pub fn request_span(headers: &HeaderMap) -> Span {
+    let parent = remote_context(headers);
+    let request_id = request_id(&parent);
+    let span = span!(parent: None, Level::INFO, "graphql request", %request_id);
+    if parent
+        .remote_span_context()
+        .is_some_and(|span_context| span_context.is_valid())
+    {
+        span.set_parent(parent);
+    }
+    span
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry_otlp::proto::collector::trace::v1::trace_service_server::{
+        TraceService, TraceServiceServer,
+    };
+    use opentelemetry_otlp::proto::collector::trace::v1::{
+        ExportTraceServiceRequest, ExportTraceServiceResponse,
+    };
+    use std::net::TcpListener;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::Registry;
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+    fn headers() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "traceparent",
+            format!("00-{}-00f067aa0ba902b7-01", TRACE_ID)
+                .parse()
+                .unwrap(),
+        );
+        headers
+    }
+
+    /// Stands in for a collector, handing over the requests it receives.
+    struct Collector(mpsc::UnboundedSender<ExportTraceServiceRequest>);
+
+    #[tonic::async_trait]
+    impl TraceService for Collector {
+        async fn export(
+            &self,
+            request: tonic::Request<ExportTraceServiceRequest>,
+        ) -> Result<tonic::Response<ExportTraceServiceResponse>, tonic::Status> {
+            let _ = self.0.send(request.into_inner());
+            Ok(tonic::Response::new(ExportTraceServiceResponse {}))
+        }
+    }
+
+    #[test]
+    fn test_request_id_is_the_trace_id_of_the_caller() {
+        let parent = remote_context(&headers());
+
+        assert_eq!(request_id(&parent).to_simple().to_string(), TRACE_ID);
+        assert_ne!(
+            request_id(&remote_context(&HeaderMap::new())),
+            request_id(&remote_context(&HeaderMap::new()))
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_spans_are_exported_in_the_trace_of_the_caller() {
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .expect("free port");
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(TraceServiceServer::new(Collector(sender)))
+                .serve(addr),
+        );
+
+        let tracer = tracer(&settings::Tracing {
+            enabled: true,
+            endpoint: format!("http://{}", addr),
+            service_name: String::from("stocks-test"),
+            sample_ratio: 1.0,
+        })
+        .expect("tracer");
+        let subscriber =
+            Registry::default().with(tracing_opentelemetry::layer().with_tracer(tracer));
+        tracing::subscriber::with_default(subscriber, || {
+            request_span(&headers()).in_scope(|| tracing::info_span!("resolver").in_scope(|| {}));
+        });
+        // Flushes the pending spans.
+        tokio::task::spawn_blocking(opentelemetry::global::shutdown_tracer_provider)
+            .await
+            .expect("shutdown");
+
+        // The spans may come in several requests, the first one is awaited.
+        let mut requests = vec![
+            tokio::time::timeout(Duration::from_secs(10), receiver.recv())
+                .await
+                .expect("export")
+                .expect("request"),
+        ];
+        while let Ok(request) = receiver.try_recv() {
+            requests.push(request);
+        }
+        let spans = requests
+            .iter()
+            .flat_map(|request| request.resource_spans.iter())
+            .flat_map(|spans| spans.instrumentation_library_spans.iter())
+            .flat_map(|spans| spans.spans.iter())
+            .collect::<Vec<_>>();
+        let root = spans
+            .iter()
+            .find(|span| span.name == "graphql request")
+            .expect("root span");
+
+        assert_eq!(
+            Uuid::from_slice(&root.trace_id)
+                .unwrap()
+                .to_simple()
+                .to_string(),
+            TRACE_ID
+        );
+        assert!(spans
+            .iter()
+            .any(|span| span.name == "resolver" && span.trace_id == root.trace_id));
+    }
+}
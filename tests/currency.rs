@@ -1,8 +1,11 @@
 use cucumber::async_trait;
 use sqlx::postgres::PgPoolOptions;
 use std::convert::Infallible;
+use std::sync::Arc;
 use std::time::Duration;
 
+use stocks::api::auth::{AuthMethod, Permission, Principal};
+use stocks::api::bus::Bus;
 use stocks::api::{gql, imp};
 use stocks::utils;
 
@@ -24,14 +27,31 @@ impl cucumber::World for MyWorld {
             .connect(&url)
             .await
             .expect("Database connection");
-        let service = imp::StockServiceImpl { pool };
+        let bus = Bus::default();
+        let service = imp::StockServiceImpl {
+            pool,
+            bus: bus.clone(),
+        };
         Ok(Self {
             response: async_graphql::Response::new(()),
-            schema: gql::schema(Box::new(service)),
+            schema: gql::schema(Arc::new(service), bus),
         })
     }
 }
 
+impl MyWorld {
+    /// Execute the request on behalf of an administrator.
+    async fn execute(&self, request: async_graphql::Request) -> async_graphql::Response {
+        let admin = Principal {
+            subject: String::from("cucumber"),
+            method: AuthMethod::ApiKey,
+            roles: vec![String::from("admin")],
+            permissions: vec![Permission::Read, Permission::Write, Permission::Admin],
+        };
+        self.schema.execute(request.data(admin)).await
+    }
+}
+
 mod example_steps {
     use async_graphql::{value, Variables};
     use cucumber::{t, Steps};
@@ -42,8 +62,36 @@ mod example_steps {
         builder
             .given_async(
                 "there are no currencies in store",
-                t!(|mut world, _step| {
-                    // TODO Call delete_all_currencies.
+                t!(|world, _step| {
+                    let request = async_graphql::Request::new(
+                        r#"
+                        query listCurrencies {
+                            listCurrencies { code }
+                        }
+                      "#,
+                    );
+                    let response = world.execute(request).await;
+                    let data = response.data.into_json().expect("json");
+                    let codes = data["listCurrencies"]
+                        .as_array()
+                        .expect("currencies")
+                        .iter()
+                        .map(|currency| currency["code"].as_str().expect("code").to_owned())
+                        .collect::<Vec<_>>();
+                    for code in codes {
+                        let request = async_graphql::Request::new(
+                            r#"
+                            mutation deleteCurrency($code: String!) {
+                                deleteCurrency(code: $code) { code }
+                            }
+                          "#,
+                        )
+                        .variables(Variables::from_value(value!({
+                            "code": code,
+                        })));
+                        let response = world.execute(request).await;
+                        assert!(response.is_ok());
+                    }
                     world
                 }),
             )
@@ -69,7 +117,7 @@ mod example_steps {
                             "decimals": decimals
                         }
                     })));
-                    world.response = world.schema.execute(request).await;
+                    world.response = world.execute(request).await;
                     world
                 }),
             )
@@ -100,12 +148,12 @@ mod example_steps {
                     .variables(Variables::from_value(value!({
                         "code": code,
                     })));
-                    let response = world.schema.execute(request).await;
+                    let response = world.execute(request).await;
                     if response.is_err() {
                         for err in response.errors {
                             println!("{}", err);
                         }
-                        println!("");
+                        println!();
                     } else {
                         assert_eq!(
                             response.data,
@@ -118,7 +166,7 @@ mod example_steps {
                             })
                         );
                         // println!("data: {}", response.data);
-                        // println!("");
+                        // println!();
                     }
                     world
                 }),
@@ -135,7 +183,7 @@ async fn main() {
 
     cucumber::Cucumber::<MyWorld>::new()
         // Specifies where our feature files exist
-        .features(&["./features"])
+        .features(["./features"])
         // Adds the implementation of our steps to the runner
         .steps(example_steps::steps())
         // Parses the command line arguments if passed